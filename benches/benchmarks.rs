@@ -1,62 +1,72 @@
 use criterion::Criterion;
 use criterion::{black_box, criterion_group, criterion_main};
-use rand::SeedableRng as _;
+use rand::{Rng as _, SeedableRng as _};
 use glam::{Vec3, vec3};
 
 use raytracer::*;
 
 fn sphere_ray(c: &mut Criterion) {
     let sphere = Sphere::new(vec3(0.1, 0.2, 3.), 1.);
-    let origin = Vec3::zero();
-    let dir = Vec3::unit_z();
+    let ray = Ray::new(Vec3::zero(), Vec3::unit_z());
     c.bench_function("sphere ray", |b| {
-        b.iter(|| black_box(&sphere).ray_intersect(black_box(origin), black_box(dir)))
+        b.iter(|| black_box(&sphere).ray_intersect(black_box(&ray)))
     });
 }
 
 fn plane_ray(c: &mut Criterion) {
     let plane = Plane::new(vec3(0., -1., 0.), Vec3::unit_y());
-    let origin = Vec3::new(0., 0., 3.);
-    let dir = Vec3::new(0., -0.1, 1.).normalize();
+    let ray = Ray::new(Vec3::new(0., 0., 3.), Vec3::new(0., -0.1, 1.).normalize());
     c.bench_function("plane ray", |b| {
-        b.iter(|| black_box(&plane).ray_intersect(black_box(origin), black_box(dir)))
+        b.iter(|| black_box(&plane).ray_intersect(black_box(&ray)))
     });
 }
 
 fn create_scene() -> Scene {
-    let mut scene = Scene::new();
-    scene.add_plane(
-        Plane::new(vec3(0., -1., 0.), vec3(0., 1., 0.)),
-        Material::new(0.8, 0.8, 0.8),
-    );
-
-    scene.add_sphere(
-        Sphere::new(vec3(0.0, 0.0, -3.), 1.),
-        Material::new(0.75, 0.25, 0.25),
-    );
-    scene.add_sphere(
-        Sphere::new(vec3(1.0, 3.0, -10.), 2.),
-        Material::new(0.25, 0.65, 0.25),
-    );
-    scene.add_sphere(
-        Sphere::new(vec3(0.65, 0.65, -2.3), 0.1),
-        Material::new(0.6, 0.4, 0.2),
-    );
-
-    scene.add_point_light(vec3(0., 0.1, 3.5), 3.);
-    scene.add_sphere_light(vec3(2., 1., 0.), 0.5, 3.);
-    scene.add_sphere_light(vec3(-1., 1., 0.), 0.5, 2.);
-    scene.add_sphere_light(vec3(0., 10., -5.), 1.0, 30.);
-
-    scene
+    Scene::from_file(concat!(env!("CARGO_MANIFEST_DIR"), "/scenes/default.scene")).unwrap()
 }
 
 fn scene_ray(c: &mut Criterion) {
     let scene = create_scene();
-    let origin = vec3(0., 0., 3.);
-    let dir = vec3(0., 0., 1.);
+    let ray = Ray::new(vec3(0., 0., 3.), vec3(0., 0., 1.));
     c.bench_function("scene ray", |b| {
-        b.iter(|| black_box(&scene).find_intersection(black_box(origin), black_box(dir)))
+        b.iter(|| black_box(&scene).find_intersection(black_box(&ray)))
+    });
+}
+
+fn scene_ray_random_spheres(c: &mut Criterion) {
+    let mut rng = rand::rngs::SmallRng::seed_from_u64(239);
+    let mut scene = Scene::new();
+    for _ in 0..10000 {
+        let center = vec3(
+            rng.gen_range(-50.0..50.0),
+            rng.gen_range(-50.0..50.0),
+            rng.gen_range(-50.0..50.0),
+        );
+        scene.add_sphere(
+            Sphere::new(center, rng.gen_range(0.1..1.0)),
+            Material::new(0.5, 0.5, 0.5),
+        );
+    }
+    let rays: Vec<Ray> = (0..100)
+        .map(|_| {
+            let dir = vec3(
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+            )
+            .normalize();
+            Ray::new(Vec3::zero(), dir)
+        })
+        .collect();
+    // Build the hierarchy before measuring.
+    scene.find_intersection(&rays[0]);
+
+    c.bench_function("scene ray 10000 random spheres", |b| {
+        b.iter(|| {
+            for ray in rays.iter() {
+                black_box(&scene).find_intersection(black_box(ray));
+            }
+        })
     });
 }
 
@@ -64,10 +74,10 @@ fn transform_ray(c: &mut Criterion) {
     let camera = Camera::new()
         .set_eye(Vec3::new(1., 2., 3.))
         .set_target(Vec3::zero());
-    let vector = Vec3::new(0.1, 0.2, -1.0);
+    let ray = Ray::new(Vec3::zero(), Vec3::new(0.1, 0.2, -1.0));
 
     c.bench_function("transform_ray", |b| {
-        b.iter(|| black_box(&camera).transform_ray(black_box(vector)))
+        b.iter(|| black_box(&camera).transform_ray(black_box(&ray)))
     });
 }
 
@@ -155,12 +165,40 @@ fn render16x16_threadrng(c: &mut Criterion) {
     });
 }
 
+fn render16x16_parallel_1thread(c: &mut Criterion) {
+    let scene = create_scene();
+
+    let camera = Camera::new()
+        .set_dimensions(16, 16)
+        .set_fov(std::f32::consts::PI / 5.)
+        .set_tile_size(4)
+        .set_threads(1);
+
+    c.bench_function("render16x16_parallel_1thread", |b| {
+        b.iter(|| black_box(&camera).render_parallel(black_box(&scene), 239))
+    });
+}
+
+fn render16x16_parallel(c: &mut Criterion) {
+    let scene = create_scene();
+
+    let camera = Camera::new()
+        .set_dimensions(16, 16)
+        .set_fov(std::f32::consts::PI / 5.)
+        .set_tile_size(4);
+
+    c.bench_function("render16x16_parallel", |b| {
+        b.iter(|| black_box(&camera).render_parallel(black_box(&scene), 239))
+    });
+}
+
 criterion_group!{
     name = benches;
     config = Criterion::default().significance_level(0.05).sample_size(500);
     targets = sphere_ray,
     plane_ray,
     scene_ray,
+    scene_ray_random_spheres,
     transform_ray,
     pixel_ray,
     sample_pixel_ray_smallrng,
@@ -169,6 +207,8 @@ criterion_group!{
     render256x256_empty_threadrng,
     render16x16_smallrng,
     render16x16_threadrng,
+    render16x16_parallel_1thread,
+    render16x16_parallel,
 }
 
 criterion_main!(benches);
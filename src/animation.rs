@@ -0,0 +1,561 @@
+//! Values that change over time: keyframe tracks, affine transforms and shapes moving with
+//! keyframed transforms.
+
+use glam::{Mat4, Quat, Vec3};
+
+use crate::bvh::Aabb;
+use crate::camera::Camera;
+use crate::material::Color;
+use crate::ray::Ray;
+use crate::shape::{Intersection, Shape};
+
+/// Values that can be blended between two keyframes.
+pub trait Interpolate: Copy {
+    /// The value at the fraction `t` in [0, 1] of the way from `self` to `other`.
+    fn interpolate(self, other: Self, t: f32) -> Self;
+}
+
+impl Interpolate for f32 {
+    fn interpolate(self, other: Self, t: f32) -> Self {
+        self + (other - self) * t
+    }
+}
+
+impl Interpolate for Vec3 {
+    fn interpolate(self, other: Self, t: f32) -> Self {
+        self.lerp(other, t)
+    }
+}
+
+impl Interpolate for Color {
+    fn interpolate(self, other: Self, t: f32) -> Self {
+        self * (1. - t) + other * t
+    }
+}
+
+/// A value given at a sequence of times. In between the keyframes it is interpolated linearly,
+/// before the first and after the last keyframe it stays constant.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Keyframes<T> {
+    keys: Vec<(f32, T)>,
+}
+
+impl<T: Interpolate> Keyframes<T> {
+    /// Keyframes given as (time, value) pairs. There has to be at least one keyframe.
+    pub fn new(mut keys: Vec<(f32, T)>) -> Self {
+        assert!(!keys.is_empty());
+        keys.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Keyframes { keys }
+    }
+
+    /// A value that doesn't change.
+    pub fn constant(value: T) -> Self {
+        Keyframes {
+            keys: vec![(0., value)],
+        }
+    }
+
+    pub fn keys(&self) -> &[(f32, T)] {
+        &self.keys
+    }
+
+    pub fn is_constant(&self) -> bool {
+        self.keys.len() == 1
+    }
+
+    pub fn at(&self, time: f32) -> T {
+        // Index of the first keyframe after `time`.
+        let next = self.keys.partition_point(|&(key_time, _)| key_time <= time);
+        if next == 0 {
+            return self.keys[0].1;
+        }
+        if next == self.keys.len() {
+            return self.keys[next - 1].1;
+        }
+        let (t0, v0) = self.keys[next - 1];
+        let (t1, v1) = self.keys[next];
+        v0.interpolate(v1, (time - t0) / (t1 - t0))
+    }
+}
+
+/// Scaling, followed by a rotation and a translation.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    pub scale: Vec3,
+    pub rotation: Quat,
+    pub translation: Vec3,
+}
+
+impl Transform {
+    pub fn identity() -> Self {
+        Transform {
+            scale: Vec3::one(),
+            rotation: Quat::identity(),
+            translation: Vec3::zero(),
+        }
+    }
+
+    pub fn from_translation(translation: Vec3) -> Self {
+        Transform {
+            translation,
+            ..Self::identity()
+        }
+    }
+
+    pub fn matrix(&self) -> Mat4 {
+        Mat4::from_scale_rotation_translation(self.scale, self.rotation, self.translation)
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl Interpolate for Transform {
+    /// Interpolates the components separately, the rotation along the shortest arc.
+    fn interpolate(self, other: Self, t: f32) -> Self {
+        let end_rotation = if self.rotation.dot(other.rotation) < 0. {
+            -other.rotation
+        } else {
+            other.rotation
+        };
+        Transform {
+            scale: self.scale.lerp(other.scale, t),
+            rotation: self.rotation.slerp(end_rotation, t).normalize(),
+            translation: self.translation.lerp(other.translation, t),
+        }
+    }
+}
+
+/// A transform that changes over time.
+pub trait Motion: Send + Sync {
+    fn transform(&self, time: f32) -> Transform;
+
+    /// Times of the keyframes, in increasing order. In between them the transform changes
+    /// smoothly.
+    fn key_times(&self) -> Vec<f32>;
+}
+
+impl Motion for Keyframes<Transform> {
+    fn transform(&self, time: f32) -> Transform {
+        self.at(time)
+    }
+
+    fn key_times(&self) -> Vec<f32> {
+        self.keys.iter().map(|&(time, _)| time).collect()
+    }
+}
+
+/// Separate keyframes for the translation, rotation and scale of a transform. The rotation is
+/// given by Euler angles in radians, applied about x, then y, then z. Unlike quaternions, two
+/// consecutive rotation keyframes can be more than half a turn apart, e.g. for a turntable.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TransformKeyframes {
+    pub translation: Keyframes<Vec3>,
+    pub rotation: Keyframes<Vec3>,
+    pub scale: Keyframes<Vec3>,
+}
+
+impl Default for TransformKeyframes {
+    fn default() -> Self {
+        TransformKeyframes {
+            translation: Keyframes::constant(Vec3::zero()),
+            rotation: Keyframes::constant(Vec3::zero()),
+            scale: Keyframes::constant(Vec3::one()),
+        }
+    }
+}
+
+impl Motion for TransformKeyframes {
+    fn transform(&self, time: f32) -> Transform {
+        let angles = self.rotation.at(time);
+        Transform {
+            scale: self.scale.at(time),
+            rotation: Quat::from_rotation_z(angles.z)
+                * Quat::from_rotation_y(angles.y)
+                * Quat::from_rotation_x(angles.x),
+            translation: self.translation.at(time),
+        }
+    }
+
+    fn key_times(&self) -> Vec<f32> {
+        let mut times: Vec<f32> = [&self.translation, &self.rotation, &self.scale]
+            .iter()
+            .flat_map(|keyframes| keyframes.keys.iter().map(|&(time, _)| time))
+            .collect();
+        times.sort_by(f32::total_cmp);
+        times.dedup();
+        times
+    }
+}
+
+/// Number of steps per keyframe interval at which the motion is sampled for the bounding box.
+const BOUNDS_STEPS: usize = 16;
+
+/// A shape moving with a keyframed transform. The transform at the time of the ray maps the
+/// shape from its own coordinates into the scene.
+pub struct Animated<S, M = Keyframes<Transform>> {
+    shape: S,
+    motion: M,
+    /// The matrix of the transform and its inverse if the motion has a single keyframe, so that
+    /// they aren't computed for every ray.
+    fixed_matrices: Option<(Mat4, Mat4)>,
+}
+
+impl<S: Shape, M: Motion> Animated<S, M> {
+    pub fn new(shape: S, motion: M) -> Self {
+        let fixed_matrices = match motion.key_times()[..] {
+            [time] => {
+                let matrix = motion.transform(time).matrix();
+                Some((matrix, matrix.inverse()))
+            }
+            _ => None,
+        };
+        Animated {
+            shape,
+            motion,
+            fixed_matrices,
+        }
+    }
+
+    pub fn shape(&self) -> &S {
+        &self.shape
+    }
+
+    pub fn motion(&self) -> &M {
+        &self.motion
+    }
+}
+
+impl<S: Shape, M: Motion> Shape for Animated<S, M> {
+    fn ray_intersect(&self, ray: &Ray) -> Intersection {
+        let (matrix, inverse) = self.fixed_matrices.unwrap_or_else(|| {
+            let matrix = self.motion.transform(ray.time).matrix();
+            (matrix, matrix.inverse())
+        });
+        // The shapes expect a unit direction, so the distances are scaled to the local
+        // coordinates and back.
+        let dir = inverse.transform_vector3(ray.dir);
+        let scale = dir.length();
+        let local_ray = Ray {
+            origin: inverse.transform_point3(ray.origin),
+            dir: dir / scale,
+            time: ray.time,
+            tmin: ray.tmin * scale,
+            tmax: ray.tmax * scale,
+        };
+        let intersection = self.shape.ray_intersect(&local_ray);
+        if !intersection.exists() {
+            return intersection;
+        }
+        let normal_matrix = inverse.transpose();
+        let transform_normal = |normal| normal_matrix.transform_vector3(normal).normalize();
+        let normal = transform_normal(intersection.normal);
+        Intersection::new(intersection.dist / scale, normal)
+            .set_geometric_normal(transform_normal(intersection.geometric_normal))
+            .set_uv(intersection.uv)
+            .set_tangents(
+                matrix.transform_vector3(intersection.tangent),
+                matrix.transform_vector3(intersection.bitangent),
+            )
+    }
+
+    /// Union of the bounds along the motion. Between the samples of the motion the corners of the
+    /// box move along arcs, so the box is expanded by the largest distance of an arc from its
+    /// chord.
+    fn bounds(&self) -> Option<Aabb> {
+        let local = self.shape.bounds()?;
+        let corners: Vec<Vec3> = (0..8)
+            .map(|i| {
+                Vec3::new(
+                    if i & 1 == 0 { local.min.x } else { local.max.x },
+                    if i & 2 == 0 { local.min.y } else { local.max.y },
+                    if i & 4 == 0 { local.min.z } else { local.max.z },
+                )
+            })
+            .collect();
+
+        let key_times = self.motion.key_times();
+        let mut times = vec![key_times[0]];
+        for pair in key_times.windows(2) {
+            let (t0, t1) = (pair[0], pair[1]);
+            times.extend(
+                (1..=BOUNDS_STEPS).map(|step| t0 + (t1 - t0) * step as f32 / BOUNDS_STEPS as f32),
+            );
+        }
+
+        let mut bounds = Aabb::empty();
+        let mut margin = 0f32;
+        let mut previous: Option<Transform> = None;
+        for &time in times.iter() {
+            let transform = self.motion.transform(time);
+            let matrix = transform.matrix();
+            for &corner in corners.iter() {
+                bounds = bounds.grow(matrix.transform_point3(corner));
+            }
+            if let Some(previous) = previous {
+                let angle = previous
+                    .rotation
+                    .dot(transform.rotation)
+                    .abs()
+                    .min(1.)
+                    .acos()
+                    * 2.;
+                let radius = corners
+                    .iter()
+                    .map(|&corner| (corner * previous.scale.max(transform.scale)).length())
+                    .fold(0., f32::max);
+                margin = margin.max(radius * (1. - (angle / 2.).cos()));
+            }
+            previous = Some(transform);
+        }
+        Some(Aabb::new(
+            bounds.min - Vec3::splat(margin),
+            bounds.max + Vec3::splat(margin),
+        ))
+    }
+}
+
+/// Keyframed camera settings and the frames of an animation. Frame `n`, counted from 1, is at
+/// the time `(n - 1) / frame_rate`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Animation {
+    eye: Option<Keyframes<Vec3>>,
+    target: Option<Keyframes<Vec3>>,
+    fov: Option<Keyframes<f32>>,
+    frame_count: u32,
+    frame_rate: f32,
+}
+
+impl Animation {
+    /// A single frame at 24 frames per second, without camera keyframes.
+    pub fn new() -> Self {
+        Animation {
+            eye: None,
+            target: None,
+            fov: None,
+            frame_count: 1,
+            frame_rate: 24.,
+        }
+    }
+
+    pub fn set_frames(mut self, frame_count: u32, frame_rate: f32) -> Self {
+        assert!(frame_count > 0 && frame_rate > 0.);
+        self.frame_count = frame_count;
+        self.frame_rate = frame_rate;
+        self
+    }
+
+    pub fn set_eye(mut self, eye: Keyframes<Vec3>) -> Self {
+        self.eye = Some(eye);
+        self
+    }
+
+    pub fn set_target(mut self, target: Keyframes<Vec3>) -> Self {
+        self.target = Some(target);
+        self
+    }
+
+    /// Keyframes of the horizontal field of view in radians.
+    pub fn set_fov(mut self, fov: Keyframes<f32>) -> Self {
+        self.fov = Some(fov);
+        self
+    }
+
+    pub fn eye(&self) -> Option<&Keyframes<Vec3>> {
+        self.eye.as_ref()
+    }
+
+    pub fn target(&self) -> Option<&Keyframes<Vec3>> {
+        self.target.as_ref()
+    }
+
+    pub fn fov(&self) -> Option<&Keyframes<f32>> {
+        self.fov.as_ref()
+    }
+
+    pub fn frame_count(&self) -> u32 {
+        self.frame_count
+    }
+
+    pub fn frame_rate(&self) -> f32 {
+        self.frame_rate
+    }
+
+    pub fn frame_time(&self, frame: u32) -> f32 {
+        assert!(frame > 0);
+        (frame - 1) as f32 / self.frame_rate
+    }
+
+    /// The camera for a frame. The keyframed settings are taken at the time of the frame, in
+    /// place of those of `camera`, and the shutter interval of `camera` is taken relative to it.
+    pub fn frame_camera(&self, camera: &Camera, frame: u32) -> Camera {
+        let time = self.frame_time(frame);
+        let mut camera = camera.clone();
+        if let Some(ref eye) = self.eye {
+            camera = camera.set_eye(eye.at(time));
+        }
+        if let Some(ref target) = self.target {
+            camera = camera.set_target(target.at(time));
+        }
+        if let Some(ref fov) = self.fov {
+            camera = camera.set_fov(fov.at(time));
+        }
+        let (open, close) = camera.shutter();
+        camera.set_shutter(time + open, time + close)
+    }
+}
+
+impl Default for Animation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::vec3;
+    use std::f32::consts::PI;
+
+    use super::*;
+    use crate::sphere::Sphere;
+
+    #[test]
+    fn keyframe_interpolation() {
+        let keyframes = Keyframes::new(vec![(1., 2.), (0., 1.), (3., 0.)]);
+        assert_eq!(keyframes.at(-1.), 1.);
+        assert_eq!(keyframes.at(0.5), 1.5);
+        assert_eq!(keyframes.at(1.), 2.);
+        assert_eq!(keyframes.at(2.5), 0.5);
+        assert_eq!(keyframes.at(10.), 0.);
+        assert_eq!(
+            Keyframes::constant(vec3(1., 2., 3.)).at(5.),
+            vec3(1., 2., 3.)
+        );
+        // Times that can't be ordered don't stop the others from being sorted.
+        let keyframes = Keyframes::new(vec![(1., 2.), (f32::NAN, 5.), (0., 1.)]);
+        assert_eq!(keyframes.keys()[..2], [(0., 1.), (1., 2.)]);
+
+        let start = Transform::identity();
+        let end = Transform {
+            scale: Vec3::splat(3.),
+            rotation: Quat::from_rotation_y(PI / 2.),
+            translation: vec3(2., 0., 0.),
+        };
+        let middle = start.interpolate(end, 0.5);
+        assert_eq!(middle.scale, Vec3::splat(2.));
+        assert_eq!(middle.translation, vec3(1., 0., 0.));
+        assert!((middle.rotation * Vec3::unit_x() - vec3(1., 0., -1.).normalize()).length() < 1E-6);
+    }
+
+    #[test]
+    fn moving_sphere() {
+        let sphere = Animated::new(
+            Sphere::new(Vec3::zero(), 1.),
+            Keyframes::new(vec![
+                (0., Transform::from_translation(vec3(0., 0., -5.))),
+                (1., Transform::from_translation(vec3(4., 0., -5.))),
+            ]),
+        );
+        let ray = Ray::new(Vec3::zero(), -Vec3::unit_z());
+        let intersection = sphere.ray_intersect(&ray);
+        assert_relative_eq!(intersection.dist, 4.);
+        assert!((intersection.normal - Vec3::unit_z()).length() < 1E-6);
+        assert!(!sphere.ray_intersect(&ray.set_time(0.5)).exists());
+        let ray = Ray::new(vec3(2., 0., 0.), -Vec3::unit_z()).set_time(0.5);
+        assert_relative_eq!(sphere.ray_intersect(&ray).dist, 4.);
+
+        let bounds = sphere.bounds().unwrap();
+        assert!((bounds.min - vec3(-1., -1., -6.)).length() < 1E-6);
+        assert!((bounds.max - vec3(5., 1., -4.)).length() < 1E-6);
+    }
+
+    #[test]
+    fn static_shape() {
+        let sphere = Animated::new(
+            Sphere::new(Vec3::zero(), 1.),
+            Keyframes::constant(Transform::from_translation(vec3(0., 0., -5.))),
+        );
+        assert!(sphere.fixed_matrices.is_some());
+        for &time in [0., 3.].iter() {
+            let ray = Ray::new(Vec3::zero(), -Vec3::unit_z()).set_time(time);
+            assert_relative_eq!(sphere.ray_intersect(&ray).dist, 4., epsilon = 1E-5);
+        }
+    }
+
+    #[test]
+    fn scaled_and_rotated_shape() {
+        let rotation = Quat::from_rotation_z(0.9 * PI);
+        let sphere = Animated::new(
+            Sphere::new(vec3(1., 0., 0.), 1.),
+            Keyframes::new(vec![
+                (0., Transform::identity()),
+                (
+                    1.,
+                    Transform {
+                        scale: Vec3::splat(2.),
+                        rotation,
+                        translation: Vec3::zero(),
+                    },
+                ),
+            ]),
+        );
+        // At the end the sphere has radius 2.
+        let center = rotation * vec3(2., 0., 0.);
+        let ray = Ray::new(center + vec3(0., 0., 5.), -Vec3::unit_z()).set_time(1.);
+        let intersection = sphere.ray_intersect(&ray);
+        assert_relative_eq!(intersection.dist, 3., epsilon = 1E-5);
+        assert!((intersection.normal - Vec3::unit_z()).length() < 1E-5);
+        assert!(!sphere.ray_intersect(&ray.set_tmax(2.9)).exists());
+
+        // Halfway the sphere has radius 1.5 and reaches higher than at the keyframes.
+        let bounds = sphere.bounds().unwrap();
+        assert!(bounds.max.y >= 1.5 + 1.5 * (0.45 * PI).sin());
+        assert!(bounds.min.x <= center.x - 2. && bounds.max.x >= 2.);
+    }
+
+    #[test]
+    fn turntable() {
+        let motion = TransformKeyframes {
+            translation: Keyframes::constant(vec3(0., 0., -5.)),
+            rotation: Keyframes::new(vec![(0., Vec3::zero()), (4., vec3(0., 2. * PI, 0.))]),
+            ..TransformKeyframes::default()
+        };
+        assert_eq!(motion.key_times(), vec![0., 4.]);
+        let point = |time| {
+            motion
+                .transform(time)
+                .matrix()
+                .transform_point3(Vec3::unit_x())
+        };
+        assert!((point(0.) - vec3(1., 0., -5.)).length() < 1E-6);
+        assert!((point(1.) - vec3(0., 0., -6.)).length() < 1E-6);
+        assert!((point(2.) - vec3(-1., 0., -5.)).length() < 1E-6);
+        assert!((point(4.) - vec3(1., 0., -5.)).length() < 1E-5);
+
+        // The bounds cover the whole circle, not just the positions at the keyframes.
+        let sphere = Animated::new(Sphere::new(Vec3::unit_x(), 0.5), motion);
+        let bounds = sphere.bounds().unwrap();
+        assert!(bounds.min.x <= -1.5 && bounds.max.x >= 1.5);
+        assert!(bounds.min.z <= -6.5 && bounds.max.z >= -3.5);
+    }
+
+    #[test]
+    fn frame_camera() {
+        let animation = Animation::new()
+            .set_frames(49, 24.)
+            .set_eye(Keyframes::new(vec![
+                (0., Vec3::zero()),
+                (2., vec3(0., 4., 0.)),
+            ]));
+        assert_eq!(animation.frame_time(1), 0.);
+        assert_eq!(animation.frame_time(49), 2.);
+
+        let camera = Camera::new().set_shutter(0., 0.02);
+        let frame = animation.frame_camera(&camera, 25);
+        assert_eq!(frame.shutter(), (1., 1.02));
+        assert_eq!(frame.pixel_ray(0, 0).unwrap().origin, vec3(0., 2., 0.));
+    }
+}
@@ -0,0 +1,442 @@
+//! Bounding volume hierarchy over axis-aligned bounding boxes, built with the surface area
+//! heuristic.
+
+use glam::Vec3;
+use std::ops::ControlFlow;
+
+use crate::ray::Ray;
+use crate::shape::Intersection;
+
+/// Number of buckets for the binned SAH split search.
+const BINS: usize = 16;
+/// Leaves with at most this many primitives are never split.
+const MAX_LEAF_SIZE: usize = 2;
+/// Cost of traversing a node relative to the cost of intersecting a primitive.
+const TRAVERSAL_COST: f32 = 1.;
+
+/// Axis-aligned bounding box.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Aabb { min, max }
+    }
+
+    /// A box that doesn't contain anything. It is the identity for `union`.
+    pub fn empty() -> Self {
+        Aabb {
+            min: Vec3::splat(f32::INFINITY),
+            max: Vec3::splat(f32::NEG_INFINITY),
+        }
+    }
+
+    /// The smallest box containing all the points.
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Self {
+        points
+            .into_iter()
+            .fold(Aabb::empty(), |aabb, point| aabb.grow(point))
+    }
+
+    pub fn union(self, other: Aabb) -> Self {
+        Aabb {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    pub fn grow(self, point: Vec3) -> Self {
+        Aabb {
+            min: self.min.min(point),
+            max: self.max.max(point),
+        }
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn surface_area(&self) -> f32 {
+        let d = self.max - self.min;
+        if d.x < 0. || d.y < 0. || d.z < 0. {
+            return 0.;
+        }
+        2. * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+
+    /// Slab test. Returns the distance at which the ray enters the box if it does so before
+    /// `max_dist`. `inv_dir` is the component-wise inverse of the ray direction.
+    pub fn ray_entry(&self, origin: Vec3, inv_dir: Vec3, max_dist: f32) -> Option<f32> {
+        let mut t_near = 0f32;
+        let mut t_far = max_dist;
+        for axis in 0..3 {
+            let t1 = (self.min[axis] - origin[axis]) * inv_dir[axis];
+            let t2 = (self.max[axis] - origin[axis]) * inv_dir[axis];
+            if t1.is_nan() || t2.is_nan() {
+                // The ray is parallel to the slab and lies on its boundary.
+                continue;
+            }
+            t_near = t_near.max(t1.min(t2));
+            t_far = t_far.min(t1.max(t2));
+        }
+        if t_near <= t_far {
+            Some(t_near)
+        } else {
+            None
+        }
+    }
+}
+
+struct Node {
+    bounds: Aabb,
+    /// For leaves: the index of the first primitive in `Bvh::indices`. For interior nodes: the
+    /// index of the second child. The first child immediately follows the node.
+    offset: usize,
+    /// Number of primitives in a leaf, 0 for interior nodes.
+    count: usize,
+}
+
+pub struct Bvh {
+    nodes: Vec<Node>,
+    /// Primitive indices, ordered so that each leaf refers to a contiguous range.
+    indices: Vec<usize>,
+}
+
+impl Bvh {
+    /// Builds the hierarchy over primitives with the given bounding boxes.
+    pub fn build(bounds: &[Aabb]) -> Self {
+        let mut bvh = Bvh {
+            nodes: Vec::with_capacity(2 * bounds.len()),
+            indices: (0..bounds.len()).collect(),
+        };
+        if !bounds.is_empty() {
+            let centers: Vec<Vec3> = bounds.iter().map(Aabb::center).collect();
+            bvh.build_node(bounds, &centers, 0, bounds.len());
+        }
+        bvh
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        self.nodes
+            .first()
+            .map_or_else(Aabb::empty, |node| node.bounds)
+    }
+
+    /// Builds the subtree for the primitives in `self.indices[start..end]` and returns the index
+    /// of its root.
+    fn build_node(&mut self, bounds: &[Aabb], centers: &[Vec3], start: usize, end: usize) -> usize {
+        let node_bounds = self.indices[start..end]
+            .iter()
+            .fold(Aabb::empty(), |aabb, &i| aabb.union(bounds[i]));
+        let node_index = self.nodes.len();
+        self.nodes.push(Node {
+            bounds: node_bounds,
+            offset: start,
+            count: end - start,
+        });
+
+        let count = end - start;
+        if count <= MAX_LEAF_SIZE {
+            return node_index;
+        }
+
+        let split = match self.find_split(bounds, centers, start, end, &node_bounds) {
+            Some(split) => split,
+            None => return node_index,
+        };
+
+        self.nodes[node_index].count = 0;
+        self.build_node(bounds, centers, start, split);
+        let second = self.build_node(bounds, centers, split, end);
+        self.nodes[node_index].offset = second;
+        node_index
+    }
+
+    /// Finds the best split of the primitives according to the surface area heuristic, and
+    /// partitions `self.indices[start..end]` accordingly. Returns the index of the first
+    /// primitive of the second half, or `None` if it is cheaper to make a leaf.
+    fn find_split(
+        &mut self,
+        bounds: &[Aabb],
+        centers: &[Vec3],
+        start: usize,
+        end: usize,
+        node_bounds: &Aabb,
+    ) -> Option<usize> {
+        let indices = &mut self.indices[start..end];
+        let center_bounds = Aabb::from_points(indices.iter().map(|&i| centers[i]));
+        let extent = center_bounds.max - center_bounds.min;
+        let axis = if extent.x > extent.y && extent.x > extent.z {
+            0
+        } else if extent.y > extent.z {
+            1
+        } else {
+            2
+        };
+        if extent[axis] <= 0. {
+            // All centers coincide, splitting won't help.
+            return None;
+        }
+
+        let bin_of = |i: usize| {
+            let relative = (centers[i][axis] - center_bounds.min[axis]) / extent[axis];
+            ((relative * BINS as f32) as usize).min(BINS - 1)
+        };
+
+        let mut bin_bounds = [Aabb::empty(); BINS];
+        let mut bin_counts = [0usize; BINS];
+        for &i in indices.iter() {
+            let bin = bin_of(i);
+            bin_bounds[bin] = bin_bounds[bin].union(bounds[i]);
+            bin_counts[bin] += 1;
+        }
+
+        // Costs of splitting after each of the first BINS - 1 bins, sweeping from the right.
+        let mut right_areas = [0.; BINS];
+        let mut right_counts = [0; BINS];
+        let mut accumulated = Aabb::empty();
+        let mut accumulated_count = 0;
+        for bin in (1..BINS).rev() {
+            accumulated = accumulated.union(bin_bounds[bin]);
+            accumulated_count += bin_counts[bin];
+            right_areas[bin] = accumulated.surface_area();
+            right_counts[bin] = accumulated_count;
+        }
+
+        let mut best_cost = f32::INFINITY;
+        let mut best_bin = 0;
+        let mut accumulated = Aabb::empty();
+        let mut accumulated_count = 0;
+        for bin in 0..BINS - 1 {
+            accumulated = accumulated.union(bin_bounds[bin]);
+            accumulated_count += bin_counts[bin];
+            let cost = accumulated.surface_area() * accumulated_count as f32
+                + right_areas[bin + 1] * right_counts[bin + 1] as f32;
+            if cost < best_cost {
+                best_cost = cost;
+                best_bin = bin;
+            }
+        }
+
+        let split_cost =
+            TRAVERSAL_COST + best_cost / node_bounds.surface_area().max(f32::MIN_POSITIVE);
+        if split_cost >= indices.len() as f32 {
+            return None;
+        }
+
+        // Partition the indices in place.
+        let mut mid = 0;
+        for j in 0..indices.len() {
+            if bin_of(indices[j]) <= best_bin {
+                indices.swap(j, mid);
+                mid += 1;
+            }
+        }
+        if mid == 0 || mid == indices.len() {
+            return None;
+        }
+        Some(start + mid)
+    }
+
+    /// Finds the nearest intersection of the ray with the primitives. `intersect_primitive` is
+    /// called with the primitive index for every primitive whose bounding box is hit. Returns the
+    /// nearest intersection and the index of the primitive.
+    pub fn intersect(
+        &self,
+        ray: &Ray,
+        mut intersect_primitive: impl FnMut(usize) -> Intersection,
+    ) -> (Intersection, usize) {
+        let mut nearest = Intersection::new_empty();
+        let mut nearest_index = 0;
+        self.traverse(ray, |index| {
+            let intersection = intersect_primitive(index);
+            if intersection < nearest {
+                nearest = intersection;
+                nearest_index = index;
+            }
+            ControlFlow::Continue(if nearest.exists() {
+                nearest.dist
+            } else {
+                ray.tmax
+            })
+        });
+        (nearest, nearest_index)
+    }
+
+    /// Checks whether the ray hits any of the primitives. The primitives have to respect the
+    /// bounds of the ray.
+    pub fn any_hit(
+        &self,
+        ray: &Ray,
+        mut intersect_primitive: impl FnMut(usize) -> Intersection,
+    ) -> bool {
+        let mut hit = false;
+        self.traverse(ray, |index| {
+            if intersect_primitive(index).exists() {
+                hit = true;
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(ray.tmax)
+            }
+        });
+        hit
+    }
+
+    /// Visits the primitives in the leaves hit by the ray, nearest nodes first. `visit` either
+    /// returns the maximum distance that is still of interest, so that farther nodes are skipped,
+    /// or stops the traversal.
+    fn traverse(&self, ray: &Ray, mut visit: impl FnMut(usize) -> ControlFlow<(), f32>) {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let origin = ray.origin;
+        let inv_dir = Vec3::one() / ray.dir;
+        let mut max_dist = ray.tmax;
+        let mut stack = Vec::with_capacity(64);
+        if self.nodes[0]
+            .bounds
+            .ray_entry(origin, inv_dir, max_dist)
+            .is_none()
+        {
+            return;
+        }
+        stack.push((0, 0.));
+
+        while let Some((node_index, entry)) = stack.pop() {
+            if entry > max_dist {
+                continue;
+            }
+            let node = &self.nodes[node_index];
+            if node.count > 0 {
+                for &index in self.indices[node.offset..node.offset + node.count].iter() {
+                    match visit(index) {
+                        ControlFlow::Continue(dist) => max_dist = max_dist.min(dist),
+                        ControlFlow::Break(()) => return,
+                    }
+                }
+                continue;
+            }
+
+            let first = node_index + 1;
+            let second = node.offset;
+            let first_entry = self.nodes[first]
+                .bounds
+                .ray_entry(origin, inv_dir, max_dist);
+            let second_entry = self.nodes[second]
+                .bounds
+                .ray_entry(origin, inv_dir, max_dist);
+            match (first_entry, second_entry) {
+                (Some(f), Some(s)) => {
+                    // Push the farther child first, so that the nearer one is processed first.
+                    if f <= s {
+                        stack.push((second, s));
+                        stack.push((first, f));
+                    } else {
+                        stack.push((first, f));
+                        stack.push((second, s));
+                    }
+                }
+                (Some(f), None) => stack.push((first, f)),
+                (None, Some(s)) => stack.push((second, s)),
+                (None, None) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::vec3;
+    use rand::{Rng as _, SeedableRng as _};
+
+    use super::*;
+    use crate::shape::Shape;
+    use crate::sphere::Sphere;
+
+    fn random_spheres(n: usize) -> Vec<Sphere> {
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(239);
+        (0..n)
+            .map(|_| {
+                let center = vec3(
+                    rng.gen_range(-10.0..10.0),
+                    rng.gen_range(-10.0..10.0),
+                    rng.gen_range(-10.0..10.0),
+                );
+                Sphere::new(center, rng.gen_range(0.05..0.5))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn aabb_ray_entry() {
+        let aabb = Aabb::new(vec3(-1., -1., -1.), vec3(1., 1., 1.));
+        let inv_dir = |dir: Vec3| Vec3::one() / dir;
+        assert_eq!(
+            aabb.ray_entry(vec3(0., 0., 5.), inv_dir(-Vec3::unit_z()), f32::INFINITY),
+            Some(4.)
+        );
+        assert_eq!(
+            aabb.ray_entry(vec3(0., 0., 5.), inv_dir(-Vec3::unit_z()), 3.),
+            None
+        );
+        assert_eq!(
+            aabb.ray_entry(vec3(0., 0., 5.), inv_dir(Vec3::unit_z()), f32::INFINITY),
+            None
+        );
+        assert_eq!(
+            aabb.ray_entry(vec3(0., 2., 5.), inv_dir(-Vec3::unit_z()), f32::INFINITY),
+            None
+        );
+        assert_eq!(
+            aabb.ray_entry(Vec3::zero(), inv_dir(Vec3::unit_x()), f32::INFINITY),
+            Some(0.)
+        );
+    }
+
+    #[test]
+    fn bvh_matches_brute_force() {
+        let spheres = random_spheres(1000);
+        let bounds: Vec<Aabb> = spheres.iter().map(|s| s.bounds().unwrap()).collect();
+        let bvh = Bvh::build(&bounds);
+
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(1);
+        for _ in 0..1000 {
+            let origin = vec3(
+                rng.gen_range(-12.0..12.0),
+                rng.gen_range(-12.0..12.0),
+                rng.gen_range(-12.0..12.0),
+            );
+            let dir = vec3(
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+            )
+            .normalize();
+            let ray = Ray::new(origin, dir);
+
+            let mut expected = Intersection::new_empty();
+            let mut expected_index = 0;
+            for (i, sphere) in spheres.iter().enumerate() {
+                let intersection = sphere.ray_intersect(&ray);
+                if intersection < expected {
+                    expected = intersection;
+                    expected_index = i;
+                }
+            }
+
+            let (intersection, index) = bvh.intersect(&ray, |i| spheres[i].ray_intersect(&ray));
+            assert_eq!(intersection.exists(), expected.exists());
+            if expected.exists() {
+                assert_eq!(intersection.dist, expected.dist);
+                assert_eq!(index, expected_index);
+            }
+
+            let segment = ray.set_tmax(5.);
+            let any_hit = bvh.any_hit(&segment, |i| spheres[i].ray_intersect(&segment));
+            assert_eq!(any_hit, expected.exists() && expected.dist < 5.);
+        }
+    }
+}
@@ -1,17 +1,59 @@
 use glam::{Mat4, Quat, Vec3};
-use std::f32::consts::FRAC_PI_2;
+use rand::SeedableRng as _;
+use std::f32::consts::{FRAC_PI_2, PI};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
+use crate::film::Film;
+use crate::filter::Filter;
+use crate::framebuffer::Framebuffer;
 use crate::material::Color;
+use crate::ray::Ray;
+use crate::sampler::{
+    BlueNoiseSampler, HaltonSampler, Sampler, SamplerType, SobolSampler, StratifiedSampler,
+};
+use crate::sampling::{concentric_sample_disk, uniform_sample_polygon};
 use crate::scene::Scene;
+use crate::scene_file::{load_scene, LoadError};
 
+/// How the camera maps the image onto the view directions. All projections look from the eye
+/// towards the target, with the up vector pointing to the top of the image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    /// Pinhole or thin lens camera with the horizontal field of view of the camera.
+    Perspective,
+    /// Parallel rays from a rectangle of the given width in scene units, centered at the eye.
+    Orthographic { width: f32 },
+    /// Equidistant fisheye: the angle from the view direction is proportional to the distance
+    /// from the image center, with the horizontal field of view spanning the image width. It can
+    /// go up to 360 degrees, pixels beyond that are black.
+    Fisheye,
+    /// Full 360 x 180 degree panorama in the equirectangular (latitude-longitude) layout, with the
+    /// view direction in the image center.
+    Equirectangular,
+}
+
+#[derive(Clone)]
 pub struct Camera {
     eye: Vec3,
     target: Vec3,
     up: Vec3,
     horizontal_fov: f32,
+    projection: Projection,
+    aperture_radius: f32,
+    aperture_blades: u32,
+    focus_distance: Option<f32>,
+    shutter_open: f32,
+    shutter_close: f32,
     w: u32,
     h: u32,
     samples: u32,
+    min_samples: u32,
+    adaptive_threshold: f32,
+    sampler: SamplerType,
+    filter: Filter,
+    threads: usize,
+    tile_size: u32,
 
     w_half: i32,
     h_half: i32,
@@ -32,9 +74,21 @@ impl Camera {
             target,
             up,
             horizontal_fov,
+            projection: Projection::Perspective,
+            aperture_radius: 0.,
+            aperture_blades: 0,
+            focus_distance: None,
+            shutter_open: 0.,
+            shutter_close: 0.,
             w,
             h,
             samples: 100,
+            min_samples: 16,
+            adaptive_threshold: 0.,
+            sampler: SamplerType::Independent,
+            filter: Filter::default(),
+            threads: 0,
+            tile_size: 16,
             w_half: (w / 2) as i32,
             h_half: (h / 2) as i32,
             scale: scale_from_dims(w, horizontal_fov),
@@ -42,6 +96,11 @@ impl Camera {
         }
     }
 
+    /// Loads the camera settings from a scene file.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, LoadError> {
+        load_scene(path).map(|(_, camera)| camera)
+    }
+
     pub fn set_eye(mut self, eye: Vec3) -> Self {
         self.eye = eye;
         self.view_rotation = create_view_rotation(self.eye, self.target, self.up);
@@ -66,6 +125,61 @@ impl Camera {
         self
     }
 
+    pub fn set_projection(mut self, projection: Projection) -> Self {
+        if let Projection::Orthographic { width } = projection {
+            assert!(width > 0.);
+        }
+        self.projection = projection;
+        self
+    }
+
+    pub fn projection(&self) -> Projection {
+        self.projection
+    }
+
+    /// Radius of the lens. 0 gives a pinhole camera with everything in focus, larger apertures
+    /// give a shallower depth of field. Only the perspective and orthographic projections have
+    /// a lens.
+    pub fn set_aperture_radius(mut self, radius: f32) -> Self {
+        assert!(radius >= 0.);
+        self.aperture_radius = radius;
+        self
+    }
+
+    /// Number of blades of the aperture. The aperture is a regular polygon with that many sides,
+    /// which shows in the shape of out of focus highlights. 0 means a circular aperture.
+    pub fn set_aperture_blades(mut self, blades: u32) -> Self {
+        assert!(blades == 0 || blades >= 3);
+        self.aperture_blades = blades;
+        self
+    }
+
+    /// Distance from the eye to the plane that is in focus. By default it is the distance to the
+    /// target.
+    pub fn set_focus_distance(mut self, distance: f32) -> Self {
+        assert!(distance > 0.);
+        self.focus_distance = Some(distance);
+        self
+    }
+
+    pub fn focus_distance(&self) -> f32 {
+        self.focus_distance
+            .unwrap_or_else(|| (self.target - self.eye).length())
+    }
+
+    /// Interval of time during which the shutter is open. Every camera ray gets a random time in
+    /// it, so objects moving during the interval are blurred.
+    pub fn set_shutter(mut self, open: f32, close: f32) -> Self {
+        assert!(close >= open);
+        self.shutter_open = open;
+        self.shutter_close = close;
+        self
+    }
+
+    pub fn shutter(&self) -> (f32, f32) {
+        (self.shutter_open, self.shutter_close)
+    }
+
     pub fn set_dimensions(mut self, w: u32, h: u32) -> Self {
         self.w = w;
         self.h = h;
@@ -75,55 +189,386 @@ impl Camera {
         self
     }
 
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.w, self.h)
+    }
+
+    /// Number of samples per pixel. With adaptive sampling this is the maximum number.
     pub fn set_samples(mut self, samples: u32) -> Self {
+        assert!(samples > 0);
         self.samples = samples;
         self
     }
 
-    pub fn render(
+    /// Minimum number of samples per pixel with adaptive sampling.
+    pub fn set_min_samples(mut self, min_samples: u32) -> Self {
+        assert!(min_samples > 0);
+        self.min_samples = min_samples;
+        self
+    }
+
+    /// Enables adaptive sampling if `threshold` is positive. A pixel stops being sampled once the
+    /// 95% confidence interval of its mean luminance is within `threshold` times the mean, i.e.
+    /// the threshold is the tolerated relative error. 0 disables adaptive sampling.
+    pub fn set_adaptive_threshold(mut self, threshold: f32) -> Self {
+        assert!(threshold >= 0.);
+        self.adaptive_threshold = threshold;
+        self
+    }
+
+    /// The sampler used by `render_parallel`.
+    pub fn set_sampler(mut self, sampler: SamplerType) -> Self {
+        self.sampler = sampler;
+        self
+    }
+
+    /// Reconstruction filter that weights the samples for the pixels around them.
+    pub fn set_filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn filter(&self) -> Filter {
+        self.filter
+    }
+
+    /// Number of worker threads used by `render_parallel`. 0 means one thread per available core.
+    pub fn set_threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Size of the square tiles that `render_parallel` distributes between the threads.
+    pub fn set_tile_size(mut self, tile_size: u32) -> Self {
+        assert!(tile_size > 0);
+        self.tile_size = tile_size;
+        self
+    }
+
+    /// Renders the image on the current thread, taking all the samples from `sampler`.
+    pub fn render(&self, scene: &Scene, sampler: &mut impl Sampler) -> Framebuffer {
+        let mut film = Film::new(0, 0, self.w, self.h);
+
+        for y in 0..self.h {
+            for x in 0..self.w {
+                self.render_pixel(scene, x, y, sampler, &mut film);
+            }
+        }
+
+        film.to_framebuffer()
+    }
+
+    /// Render the image on multiple threads. The frame is split into tiles, and each tile gets its
+    /// own RNG derived from `seed` and the tile index, so the result only depends on the seed and
+    /// not on the number of threads or the order in which the tiles are rendered. Samples near the
+    /// tile borders are also splatted into the neighbouring tiles' pixels, and the tiles are merged
+    /// in a fixed order.
+    pub fn render_parallel(&self, scene: &Scene, seed: u64) -> Framebuffer {
+        self.render_parallel_with_sample_counts(scene, seed).0
+    }
+
+    /// Same as `render_parallel`, but also returns the number of samples taken for every pixel,
+    /// in row-major order. The counts only vary with adaptive sampling.
+    pub fn render_parallel_with_sample_counts(
+        &self,
+        scene: &Scene,
+        seed: u64,
+    ) -> (Framebuffer, Vec<u32>) {
+        let tiles = self.tiles();
+        let threads = match self.threads {
+            0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n,
+        }
+        .min(tiles.len())
+        .max(1);
+
+        let next_tile = AtomicUsize::new(0);
+        let rendered: Vec<Vec<(usize, RenderedTile)>> = std::thread::scope(|s| {
+            let workers: Vec<_> = (0..threads)
+                .map(|_| {
+                    s.spawn(|| {
+                        let mut done = Vec::new();
+                        loop {
+                            let index = next_tile.fetch_add(1, Ordering::Relaxed);
+                            if index >= tiles.len() {
+                                break;
+                            }
+                            let tile =
+                                self.render_tile_with_seed(scene, &tiles[index], seed, index);
+                            done.push((index, tile));
+                        }
+                        done
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .map(|worker| worker.join().unwrap())
+                .collect()
+        });
+
+        let mut rendered: Vec<_> = rendered.into_iter().flatten().collect();
+        rendered.sort_by_key(|&(index, _)| index);
+
+        let mut film = Film::new(0, 0, self.w, self.h);
+        let mut sample_counts = vec![0; (self.w * self.h) as usize];
+        for (index, (tile_film, counts)) in rendered {
+            film.merge(&tile_film);
+            let tile = &tiles[index];
+            let pixels = (tile.y0..tile.y1).flat_map(|y| (tile.x0..tile.x1).map(move |x| (x, y)));
+            for ((x, y), samples) in pixels.zip(counts) {
+                sample_counts[(y * self.w + x) as usize] = samples;
+            }
+        }
+
+        (film.to_framebuffer(), sample_counts)
+    }
+
+    /// Traces random rays through the pixel, adds them to the film and returns the number of rays.
+    /// Without adaptive sampling `self.samples` rays are traced.
+    fn render_pixel(
         &self,
         scene: &Scene,
-        rng: &mut impl rand::Rng,
-    ) -> image::ImageBuffer<image::Rgb<u8>, Vec<u8>> {
-        let mut image = image::ImageBuffer::new(self.w, self.h);
-
-        for (x, y, pixel) in image.enumerate_pixels_mut() {
-            let mut color_sum = Color::black();
-            for _ in 0..self.samples {
-                let dir = self.sample_pixel_ray(x, y, rng);
-                color_sum += scene.ray_color(self.eye, dir, rng);
+        x: u32,
+        y: u32,
+        sampler: &mut impl Sampler,
+        film: &mut Film,
+    ) -> u32 {
+        let mut luminance = RunningStats::new();
+        let mut samples = 0;
+        while samples < self.samples {
+            sampler.start_pixel_sample(x, y, samples);
+            let [dx, dy] = sampler.next_2d();
+            let (fx, fy) = (x as f32 + dx, y as f32 + dy);
+            let color = match self.film_ray(fx, fy, sampler) {
+                Some(ray) => scene.ray_color(&ray, sampler),
+                None => Color::black(),
+            };
+            film.add_sample(&self.filter, fx, fy, color);
+            samples += 1;
+
+            if self.adaptive_threshold > 0. {
+                luminance.push(color.luminance());
+                if samples >= self.min_samples && luminance.converged(self.adaptive_threshold) {
+                    break;
+                }
             }
+        }
 
-            let color = color_sum * (1. / self.samples as f32);
-            *pixel = color.into();
+        samples
+    }
+
+    /// Renders a tile with the configured sampler. The samplers only depend on the seed, the tile
+    /// index and the pixel.
+    fn render_tile_with_seed(
+        &self,
+        scene: &Scene,
+        tile: &Tile,
+        seed: u64,
+        index: usize,
+    ) -> RenderedTile {
+        match self.sampler {
+            SamplerType::Independent => self.render_tile(scene, tile, &mut tile_rng(seed, index)),
+            SamplerType::Stratified => {
+                // Adaptive sampling can stop after any pass of `min_samples` samples, so each pass
+                // is stratified by itself rather than all the samples together.
+                let strata = if self.adaptive_threshold > 0. {
+                    self.min_samples.min(self.samples)
+                } else {
+                    self.samples
+                };
+                self.render_tile(scene, tile, &mut StratifiedSampler::new(seed, strata))
+            }
+            SamplerType::Halton => self.render_tile(scene, tile, &mut HaltonSampler::new(seed)),
+            SamplerType::Sobol => self.render_tile(scene, tile, &mut SobolSampler::new(seed)),
+            SamplerType::BlueNoise => {
+                self.render_tile(scene, tile, &mut BlueNoiseSampler::new(seed))
+            }
         }
+    }
+
+    fn render_tile(&self, scene: &Scene, tile: &Tile, sampler: &mut impl Sampler) -> RenderedTile {
+        let bounds = (tile.x0, tile.y0, tile.x1, tile.y1);
+        let mut film = Film::for_pixels(bounds, &self.filter, self.w, self.h);
+        let mut counts = Vec::with_capacity(((tile.x1 - tile.x0) * (tile.y1 - tile.y0)) as usize);
+        for y in tile.y0..tile.y1 {
+            for x in tile.x0..tile.x1 {
+                counts.push(self.render_pixel(scene, x, y, sampler, &mut film));
+            }
+        }
+        (film, counts)
+    }
 
-        image
+    fn tiles(&self) -> Vec<Tile> {
+        let mut tiles = Vec::new();
+        for y0 in (0..self.h).step_by(self.tile_size as usize) {
+            for x0 in (0..self.w).step_by(self.tile_size as usize) {
+                tiles.push(Tile {
+                    x0,
+                    y0,
+                    x1: (x0 + self.tile_size).min(self.w),
+                    y1: (y0 + self.tile_size).min(self.h),
+                });
+            }
+        }
+        tiles
     }
 
-    /// Transform a ray from camera coordinates (i.e. a vector from origin to (x, y, -1)) into
-    /// scene coordinates.
-    pub fn transform_ray(&self, camera_ray: Vec3) -> Vec3 {
-        (self.view_rotation * camera_ray).normalize()
+    /// Transform a ray from camera coordinates (i.e. with the eye at the origin looking along -z
+    /// and Y up) into scene coordinates. The direction gets normalized.
+    pub fn transform_ray(&self, camera_ray: &Ray) -> Ray {
+        Ray {
+            origin: self.eye + self.view_rotation * camera_ray.origin,
+            dir: (self.view_rotation * camera_ray.dir).normalize(),
+            ..*camera_ray
+        }
     }
 
-    /// Convert a pixel to a ray in scene coordinates.
-    pub fn pixel_ray(&self, x: u32, y: u32) -> Vec3 {
-        let x = (x as i32 - self.w_half) as f32;
-        let y = (self.h_half - y as i32) as f32;
-        let camera_ray = Vec3::new(x * self.scale, y * self.scale, -1.);
-        self.transform_ray(camera_ray)
+    /// Convert a pixel to a ray in scene coordinates. `None` if the pixel is outside the image of
+    /// a fisheye projection.
+    pub fn pixel_ray(&self, x: u32, y: u32) -> Option<Ray> {
+        let camera_ray = self.camera_ray(x as f32, y as f32)?;
+        Some(self.transform_ray(&camera_ray.set_time(self.shutter_open)))
     }
 
     /// Generate a random ray within a given pixel.
-    pub fn sample_pixel_ray(&self, x: u32, y: u32, rng: &mut impl rand::Rng) -> Vec3 {
-        let x = (x as i32 - self.w_half) as f32 + rng.gen::<f32>();
-        let y = (self.h_half - y as i32) as f32 + rng.gen::<f32>();
-        let camera_ray = Vec3::new(x * self.scale, y * self.scale, -1.);
-        self.transform_ray(camera_ray)
+    pub fn sample_pixel_ray(&self, x: u32, y: u32, sampler: &mut impl Sampler) -> Option<Ray> {
+        let [dx, dy] = sampler.next_2d();
+        self.film_ray(x as f32 + dx, y as f32 + dy, sampler)
+    }
+
+    /// Ray through a point of the image, in pixels from the top left corner, at a random time
+    /// while the shutter is open. With a finite aperture the ray starts at a random point of the
+    /// lens and passes through the point of the focal plane that is seen in the image point.
+    fn film_ray(&self, x: f32, y: f32, sampler: &mut impl Sampler) -> Option<Ray> {
+        let time = if self.shutter_close > self.shutter_open {
+            self.shutter_open + (self.shutter_close - self.shutter_open) * sampler.next_1d()
+        } else {
+            self.shutter_open
+        };
+        let camera_ray = self.camera_ray(x, y)?.set_time(time);
+        let has_lens = match self.projection {
+            Projection::Perspective | Projection::Orthographic { .. } => self.aperture_radius > 0.,
+            Projection::Fisheye | Projection::Equirectangular => false,
+        };
+        if !has_lens {
+            return Some(self.transform_ray(&camera_ray));
+        }
+
+        let lens = match self.aperture_blades {
+            0 => concentric_sample_disk(sampler),
+            blades => uniform_sample_polygon(blades, sampler),
+        } * self.aperture_radius;
+        let lens = camera_ray.origin + Vec3::new(lens.x, lens.y, 0.);
+        // The direction has z = -1 for these projections.
+        let focus = camera_ray.at(self.focus_distance());
+        Some(self.transform_ray(&Ray::new(lens, focus - lens).set_time(time)))
+    }
+
+    /// Ray through a point of the image in camera coordinates, i.e. with the eye at the origin
+    /// looking along -z.
+    fn camera_ray(&self, x: f32, y: f32) -> Option<Ray> {
+        let x = x - self.w_half as f32;
+        let y = self.h_half as f32 - y;
+        match self.projection {
+            Projection::Perspective => Some(Ray::new(
+                Vec3::zero(),
+                Vec3::new(x * self.scale, y * self.scale, -1.),
+            )),
+            Projection::Orthographic { width } => {
+                let scale = width / self.w as f32;
+                Some(Ray::new(
+                    Vec3::new(x * scale, y * scale, 0.),
+                    -Vec3::unit_z(),
+                ))
+            }
+            Projection::Fisheye => {
+                let theta = (x * x + y * y).sqrt() * self.horizontal_fov / self.w as f32;
+                if theta > PI {
+                    return None;
+                }
+                let phi = y.atan2(x);
+                let dir = Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    -theta.cos(),
+                );
+                Some(Ray::new(Vec3::zero(), dir))
+            }
+            Projection::Equirectangular => {
+                let longitude = 2. * PI * x / self.w as f32;
+                let latitude = PI * y / self.h as f32;
+                let dir = Vec3::new(
+                    latitude.cos() * longitude.sin(),
+                    latitude.sin(),
+                    -latitude.cos() * longitude.cos(),
+                );
+                Some(Ray::new(Vec3::zero(), dir))
+            }
+        }
+    }
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Running mean and variance of a sequence of values (Welford's algorithm).
+struct RunningStats {
+    count: u32,
+    mean: f64,
+    m2: f64,
+}
+
+impl RunningStats {
+    fn new() -> Self {
+        RunningStats {
+            count: 0,
+            mean: 0.,
+            m2: 0.,
+        }
+    }
+
+    fn push(&mut self, value: f32) {
+        let value = value as f64;
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    /// Whether the half width of the 95% confidence interval of the mean is within `threshold`
+    /// relative to the mean. Means below `MIN_LUMINANCE` are compared as if they were equal to
+    /// it, so that black pixels converge.
+    fn converged(&self, threshold: f32) -> bool {
+        const MIN_LUMINANCE: f64 = 1E-2;
+        if self.count < 2 {
+            return false;
+        }
+        let n = self.count as f64;
+        let standard_error = (self.m2 / (n - 1.) / n).sqrt();
+        1.96 * standard_error <= threshold as f64 * self.mean.max(MIN_LUMINANCE)
     }
 }
 
+/// Film of a tile and the sample counts of its pixels in row-major order.
+type RenderedTile = (Film, Vec<u32>);
+
+/// A rectangle of pixels [x0, x1) x [y0, y1).
+struct Tile {
+    x0: u32,
+    y0: u32,
+    x1: u32,
+    y1: u32,
+}
+
+/// Independent RNG for the tile with the given index.
+fn tile_rng(seed: u64, index: usize) -> rand::rngs::SmallRng {
+    rand::rngs::SmallRng::seed_from_u64(seed ^ (index as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15))
+}
+
 /// Calculates the scale factor
 fn scale_from_dims(w: u32, horizontal_fov: f32) -> f32 {
     (0.5 * horizontal_fov).tan() / (0.5 * w as f32)
@@ -139,17 +584,24 @@ fn create_view_rotation(eye: Vec3, target: Vec3, up: Vec3) -> Quat {
 
 #[cfg(test)]
 mod tests {
+    use glam::vec3;
+
     use super::*;
+    use crate::animation::{Animated, Keyframes, Transform};
+    use crate::filter::FilterType;
+    use crate::material::Material;
+    use crate::plane::Plane;
+    use crate::sphere::Sphere;
 
     #[test]
     fn transform_ray_look_up() {
         let camera = Camera::new()
             .set_target(Vec3::unit_y())
             .set_up(Vec3::unit_z());
-        assert!((camera.transform_ray(-Vec3::unit_z()) - Vec3::unit_y()).length() < 1E-6);
+        let transform_dir = |dir| camera.transform_ray(&Ray::new(Vec3::zero(), dir)).dir;
+        assert!((transform_dir(-Vec3::unit_z()) - Vec3::unit_y()).length() < 1E-6);
         assert!(
-            (camera.transform_ray(Vec3::new(1., 1., -1.)) - Vec3::new(1., 1., 1.).normalize())
-                .length()
+            (transform_dir(Vec3::new(1., 1., -1.)) - Vec3::new(1., 1., 1.).normalize()).length()
                 < 1E-6
         );
     }
@@ -160,20 +612,21 @@ mod tests {
             .set_eye(Vec3::new(1., 2., -3.))
             .set_target(Vec3::zero())
             .set_up(Vec3::unit_y());
-        assert!(
-            (camera.transform_ray(Vec3::new(0., 0., -1.)) - Vec3::new(-1., -2., 3.).normalize())
-                .length()
-                < 1E-6
-        )
+        let ray = camera.transform_ray(&Ray::new(Vec3::unit_x(), Vec3::new(0., 0., -1.)));
+        assert!((ray.dir - Vec3::new(-1., -2., 3.).normalize()).length() < 1E-6);
+        // The origin is moved to the eye and rotated like the direction.
+        assert_relative_eq!(ray.origin.distance(Vec3::new(1., 2., -3.)), 1.);
+        assert!(ray.dir.dot(ray.origin - Vec3::new(1., 2., -3.)).abs() < 1E-6);
     }
 
     #[test]
     fn pixel_ray() {
         let camera = Camera::new().set_fov(FRAC_PI_2).set_dimensions(400, 200);
-        assert!((camera.pixel_ray(200, 100) - -Vec3::unit_z()).length() < 1E-6);
-        assert!((camera.pixel_ray(0, 100) - Vec3::new(-1., 0., -1.).normalize()).length() < 1E-6);
+        let pixel_ray = |x, y| camera.pixel_ray(x, y).unwrap().dir;
+        assert!((pixel_ray(200, 100) - -Vec3::unit_z()).length() < 1E-6);
+        assert!((pixel_ray(0, 100) - Vec3::new(-1., 0., -1.).normalize()).length() < 1E-6);
 
-        assert!((camera.pixel_ray(400, 0) - Vec3::new(1., 0.5, -1.).normalize()).length() < 1E-6);
+        assert!((pixel_ray(400, 0) - Vec3::new(1., 0.5, -1.).normalize()).length() < 1E-6);
     }
 
     #[test]
@@ -185,6 +638,222 @@ mod tests {
             .set_target(Vec3::zero())
             .set_up(Vec3::unit_y());
 
-        assert!((camera.pixel_ray(200, 100) - Vec3::new(-1., -2., 3.).normalize()).length() < 1E-6);
+        assert!(
+            (camera.pixel_ray(200, 100).unwrap().dir - Vec3::new(-1., -2., 3.).normalize())
+                .length()
+                < 1E-6
+        );
+    }
+
+    #[test]
+    fn projections() {
+        let rng = rand::rngs::SmallRng::seed_from_u64(239);
+        let camera = Camera::new()
+            .set_dimensions(400, 200)
+            .set_eye(vec3(1., 2., 3.))
+            .set_target(vec3(1., 2., 0.));
+        let assert_ray = |camera: &Camera, x, y, origin: Vec3, dir: Vec3| {
+            let ray = camera.film_ray(x, y, &mut rng.clone()).unwrap();
+            assert!((ray.origin - origin).length() < 1E-5, "{:?}", ray);
+            assert!((ray.dir - dir.normalize()).length() < 1E-5, "{:?}", ray);
+        };
+
+        let orthographic = camera
+            .clone()
+            .set_projection(Projection::Orthographic { width: 4. });
+        assert_ray(&orthographic, 200., 100., vec3(1., 2., 3.), -Vec3::unit_z());
+        assert_ray(&orthographic, 0., 0., vec3(-1., 3., 3.), -Vec3::unit_z());
+
+        // 180 degrees across the width: the left and right borders look sideways, and pixels in
+        // the corners are still valid up to 360 degrees.
+        let fisheye = camera
+            .clone()
+            .set_fov(PI)
+            .set_projection(Projection::Fisheye);
+        let eye = vec3(1., 2., 3.);
+        assert_ray(&fisheye, 200., 100., eye, -Vec3::unit_z());
+        assert_ray(&fisheye, 0., 100., eye, -Vec3::unit_x());
+        assert_ray(&fisheye, 200., 0., eye, vec3(0., 1., -1.));
+        assert!(fisheye.set_fov(2. * PI).pixel_ray(0, 0).is_none());
+
+        let panorama = camera.set_projection(Projection::Equirectangular);
+        assert_ray(&panorama, 200., 100., eye, -Vec3::unit_z());
+        assert_ray(&panorama, 100., 100., eye, -Vec3::unit_x());
+        assert_ray(&panorama, 0., 100., eye, Vec3::unit_z());
+        assert_ray(&panorama, 300., 50., eye, vec3(1., 1., 0.));
+        assert_ray(&panorama, 123., 0., eye, Vec3::unit_y());
+    }
+
+    #[test]
+    fn thin_lens_focus() {
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(239);
+        let pinhole = Camera::new()
+            .set_dimensions(400, 200)
+            .set_eye(vec3(1., 2., -3.))
+            .set_target(Vec3::zero());
+        let pinhole_ray = pinhole.film_ray(123.4, 56.7, &mut rng).unwrap();
+        let (eye, pinhole_dir) = (pinhole_ray.origin, pinhole_ray.dir);
+        assert_eq!(eye, pinhole.eye);
+
+        // Rays through the same image point start on the lens and meet at the focal plane through
+        // the target.
+        let forward = pinhole
+            .transform_ray(&Ray::new(Vec3::zero(), -Vec3::unit_z()))
+            .dir;
+        let focus_distance = pinhole.focus_distance();
+        assert_relative_eq!(focus_distance, 14f32.sqrt());
+        let focus = eye + pinhole_dir * (focus_distance / pinhole_dir.dot(forward));
+        for &blades in [0, 5].iter() {
+            let camera = pinhole
+                .clone()
+                .set_aperture_radius(0.5)
+                .set_aperture_blades(blades);
+            for _ in 0..10 {
+                let ray = camera.film_ray(123.4, 56.7, &mut rng).unwrap();
+                assert!((ray.origin - eye).length() <= 0.5 + 1E-6);
+                assert!((ray.origin - eye).dot(forward).abs() < 1E-6);
+                let point = ray.at(focus_distance / ray.dir.dot(forward));
+                assert!((point - focus).length() < 1E-4);
+            }
+        }
+    }
+
+    #[test]
+    fn motion_blur() {
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(239);
+        let camera = Camera::new().set_dimensions(40, 20).set_shutter(0.5, 1.5);
+        assert_eq!(camera.pixel_ray(3, 4).unwrap().time, 0.5);
+        let mut time_sum = 0.;
+        for _ in 0..1000 {
+            let ray = camera.sample_pixel_ray(3, 4, &mut rng).unwrap();
+            assert!(ray.time >= 0.5 && ray.time <= 1.5);
+            time_sum += ray.time;
+        }
+        assert!((time_sum / 1000. - 1.).abs() < 0.05);
+
+        // A sphere crossing the center of the image covers the center pixel during a part of the
+        // shutter interval, so the pixel gets a blend of the sphere and the background.
+        let mut scene = Scene::new();
+        scene.add_object(
+            Animated::new(
+                Sphere::new(Vec3::zero(), 0.5),
+                Keyframes::new(vec![
+                    (0.5, Transform::from_translation(vec3(-2., 0., -3.))),
+                    (1.5, Transform::from_translation(vec3(2., 0., -3.))),
+                ]),
+            ),
+            Material::new(1., 1., 1.),
+        );
+        scene.add_point_light(Vec3::zero(), 10.);
+        let center = |camera: &Camera| camera.render_parallel(&scene, 239).get_pixel(20, 10);
+        let still = center(&camera.clone().set_shutter(1., 1.)).luminance();
+        let blurred = center(&camera.set_samples(400)).luminance();
+        assert!(still > 0.);
+        assert!(blurred > 0.1 * still && blurred < 0.5 * still);
+    }
+
+    #[test]
+    fn render_parallel_independent_of_threads() {
+        let mut scene = Scene::new();
+        scene.add_sphere(
+            Sphere::new(vec3(0., 0., -3.), 1.),
+            Material::new(0.75, 0.25, 0.25),
+        );
+        scene.add_sphere_light(vec3(2., 1., 0.), 0.5, 3.);
+
+        let camera = Camera::new()
+            .set_dimensions(40, 24)
+            .set_samples(4)
+            .set_tile_size(8);
+        let single = camera.clone().set_threads(1).render_parallel(&scene, 239);
+        let multi = camera.clone().set_threads(4).render_parallel(&scene, 239);
+        assert!(single == multi);
+
+        // Wide filters splat across the tile borders.
+        let camera = camera.set_filter(Filter::with_default_radius(FilterType::Mitchell));
+        let single = camera.clone().set_threads(1).render_parallel(&scene, 239);
+        let multi = camera.set_threads(4).render_parallel(&scene, 239);
+        assert!(single == multi);
+    }
+
+    #[test]
+    fn adaptive_sampling() {
+        // A sphere lit by a small sphere light on black background. The background converges
+        // right away, the soft shadow edges on the sphere need more samples.
+        let mut scene = Scene::new();
+        scene.add_sphere(
+            Sphere::new(vec3(0., 0., -3.), 1.),
+            Material::new(0.75, 0.25, 0.25),
+        );
+        scene.add_sphere_light(vec3(0., 3., -3.), 0.5, 5.);
+
+        let camera = Camera::new()
+            .set_dimensions(32, 16)
+            .set_samples(64)
+            .set_min_samples(8)
+            .set_adaptive_threshold(0.05);
+        let (_, counts) = camera.render_parallel_with_sample_counts(&scene, 239);
+        assert_eq!(counts.len(), 32 * 16);
+        assert_eq!(counts[0], 8);
+        assert!(counts.iter().all(|&count| (8..=64).contains(&count)));
+        assert!(counts.iter().any(|&count| count > 8));
+
+        let (_, counts) = camera
+            .set_adaptive_threshold(0.)
+            .render_parallel_with_sample_counts(&scene, 239);
+        assert!(counts.iter().all(|&count| count == 64));
+    }
+
+    #[test]
+    fn samplers_converge_faster() {
+        // Direct lighting of a floor with a soft shadow of a sphere.
+        let mut scene = Scene::new();
+        scene.add_plane(
+            Plane::new(vec3(0., 0., 0.), vec3(0., 1., 0.)),
+            Material::new(0.8, 0.8, 0.8),
+        );
+        scene.add_sphere(
+            Sphere::new(vec3(0., 1., 0.), 0.5),
+            Material::new(0.75, 0.25, 0.25),
+        );
+        scene.add_sphere_light(vec3(0., 3., 0.), 1., 5.);
+        scene.set_max_depth(0);
+
+        let camera = Camera::new()
+            .set_eye(vec3(0., 4., 0.01))
+            .set_target(Vec3::zero())
+            .set_dimensions(16, 12)
+            .set_fov(1.);
+        let reference = camera.clone().set_samples(1024).render_parallel(&scene, 1);
+        let rmse = |sampler| {
+            let camera = camera.clone().set_samples(16).set_sampler(sampler);
+            (0..4)
+                .map(|seed| camera.render_parallel(&scene, seed).rmse(&reference))
+                .sum::<f32>()
+        };
+
+        let independent = rmse(SamplerType::Independent);
+        for &sampler in [
+            SamplerType::Stratified,
+            SamplerType::Halton,
+            SamplerType::Sobol,
+            SamplerType::BlueNoise,
+        ]
+        .iter()
+        {
+            assert!(rmse(sampler) < independent, "{:?}", sampler);
+        }
+    }
+
+    #[test]
+    fn running_stats() {
+        let mut stats = RunningStats::new();
+        for &value in [2., 4., 4., 4., 5., 5., 7., 9.].iter() {
+            stats.push(value);
+        }
+        assert_relative_eq!(stats.mean, 5.);
+        assert_relative_eq!(stats.m2 / 7., 32. / 7.);
+        assert!(!stats.converged(0.1));
+        assert!(stats.converged(0.5));
     }
 }
@@ -0,0 +1,193 @@
+//! Light arriving from infinitely far away, seen by the rays that escape the scene.
+
+use glam::{Vec2, Vec3};
+use std::f32::consts::PI;
+use std::io;
+use std::path::Path;
+
+use crate::framebuffer::Framebuffer;
+use crate::material::Color;
+use crate::sampling::Distribution2D;
+use crate::texture::spherical_uv;
+
+/// Radiance surrounding the scene. Environments are shared between the render threads, hence
+/// `Send + Sync`.
+pub trait Environment: Send + Sync {
+    /// Radiance arriving from the direction `dir`.
+    fn radiance(&self, dir: Vec3) -> Color;
+
+    /// Maps a uniform sample in [0, 1)^2 to a direction, preferring the directions with more
+    /// radiance. Returns the unit direction and its probability density per solid angle.
+    fn sample(&self, u: [f32; 2]) -> (Vec3, f32);
+
+    /// Probability density per solid angle with which `sample` returns the direction `dir`.
+    fn pdf(&self, dir: Vec3) -> f32;
+}
+
+/// An equirectangular image of the surroundings, typically an HDR panorama. The center of the
+/// image is in the direction of -z, the left and right edges meet at +z, and the top row is at
+/// +y.
+pub struct EnvironmentMap {
+    image: Framebuffer,
+    intensity: f32,
+    /// Distribution of the positions in the image, proportional to the radiance times the solid
+    /// angle of each pixel.
+    distribution: Distribution2D,
+}
+
+impl EnvironmentMap {
+    /// Panics if the image is empty.
+    pub fn new(image: Framebuffer) -> Self {
+        let (width, height) = (image.width(), image.height());
+        assert!(width > 0 && height > 0);
+        let mut weights = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            let latitude = PI * (0.5 - (y as f32 + 0.5) / height as f32);
+            for x in 0..width {
+                let luminance = image.get_pixel(x, y).luminance().max(0.);
+                weights.push(luminance * latitude.cos());
+            }
+        }
+        EnvironmentMap {
+            distribution: Distribution2D::new(&weights, width as usize),
+            image,
+            intensity: 1.,
+        }
+    }
+
+    /// Loads the image from a file, see `Framebuffer::load`. Fails if the image is empty.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let image = Framebuffer::load(path)?;
+        if image.width() == 0 || image.height() == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "empty image"));
+        }
+        Ok(Self::new(image))
+    }
+
+    /// Multiplies the radiance of the image.
+    pub fn set_intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    /// Position in the image in [0, 1)^2, from the top left corner, seen in the direction `dir`.
+    fn image_position(dir: Vec3) -> Vec2 {
+        // Seen from the inside, so the image is mirrored compared to a textured sphere.
+        let uv = spherical_uv(Vec3::new(dir.x, dir.y, -dir.z));
+        Vec2::new(uv.x, 1. - uv.y)
+    }
+
+    /// Unit direction in which the position `position` of the image is seen.
+    fn direction(position: Vec2) -> Vec3 {
+        let longitude = 2. * PI * (position.x - 0.5);
+        let latitude = PI * (0.5 - position.y);
+        Vec3::new(
+            longitude.sin() * latitude.cos(),
+            latitude.sin(),
+            -longitude.cos() * latitude.cos(),
+        )
+    }
+
+    /// Converts a density over the image to a density per solid angle in the direction `dir`.
+    fn solid_angle_pdf(image_pdf: f32, dir: Vec3) -> f32 {
+        let cos_latitude = (dir.x * dir.x + dir.z * dir.z).sqrt();
+        if cos_latitude == 0. {
+            return 0.;
+        }
+        image_pdf / (2. * PI * PI * cos_latitude)
+    }
+}
+
+impl Environment for EnvironmentMap {
+    fn radiance(&self, dir: Vec3) -> Color {
+        let position = Self::image_position(dir);
+        let (width, height) = (self.image.width(), self.image.height());
+        let x = ((position.x * width as f32) as u32).min(width - 1);
+        let y = ((position.y * height as f32) as u32).min(height - 1);
+        self.image.get_pixel(x, y) * self.intensity
+    }
+
+    fn sample(&self, u: [f32; 2]) -> (Vec3, f32) {
+        let (position, image_pdf) = self.distribution.sample(u);
+        let dir = Self::direction(position);
+        (dir, Self::solid_angle_pdf(image_pdf, dir))
+    }
+
+    fn pdf(&self, dir: Vec3) -> f32 {
+        let image_pdf = self.distribution.pdf(Self::image_position(dir));
+        Self::solid_angle_pdf(image_pdf, dir)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng as _;
+
+    use super::*;
+    use crate::sampler::Sampler;
+    use crate::tonemap::ToneMapping;
+
+    #[test]
+    fn equirectangular_mapping() {
+        let mut image = Framebuffer::new(4, 2);
+        image.put_pixel(1, 0, Color::new(1., 0., 0.));
+        image.put_pixel(2, 1, Color::new(0., 1., 0.));
+        let environment = EnvironmentMap::new(image).set_intensity(2.);
+        // Up and to the left of -z, and down and to the right.
+        let dir = Vec3::new(-0.3, 0.5, -1.).normalize();
+        assert_eq!(environment.radiance(dir), Color::new(2., 0., 0.));
+        let dir = Vec3::new(0.3, -0.5, -1.).normalize();
+        assert_eq!(environment.radiance(dir), Color::new(0., 2., 0.));
+        assert_eq!(environment.radiance(Vec3::unit_z()), Color::black());
+
+        for &(x, y) in [(0.1, 0.2), (0.5, 0.5), (0.9, 0.7)].iter() {
+            let position = Vec2::new(x, y);
+            let dir = EnvironmentMap::direction(position);
+            assert!((EnvironmentMap::image_position(dir) - position).length() < 1E-5);
+        }
+    }
+
+    #[test]
+    fn importance_sampling() {
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(239);
+        // With a uniform image the estimate of the total radiance is exact up to the variation
+        // of the solid angle within the rows.
+        let mut image = Framebuffer::new(16, 8);
+        for y in 0..8 {
+            for x in 0..16 {
+                image.put_pixel(x, y, Color::from(1.));
+            }
+        }
+        let environment = EnvironmentMap::new(image);
+        let n = 10000;
+        let mut sum = 0.;
+        for _ in 0..n {
+            let (dir, pdf) = environment.sample(rng.next_2d());
+            assert_relative_eq!(dir.length(), 1., epsilon = 1E-5);
+            assert_relative_eq!(environment.pdf(dir), pdf, max_relative = 1E-3);
+            sum += environment.radiance(dir).luminance() / pdf;
+        }
+        assert_relative_eq!(sum / n as f32, 4. * PI, max_relative = 1E-2);
+
+        // A single bright pixel gets all the samples.
+        let mut image = Framebuffer::new(16, 8);
+        image.put_pixel(5, 2, Color::from(100.));
+        let environment = EnvironmentMap::new(image);
+        for _ in 0..100 {
+            let (dir, pdf) = environment.sample(rng.next_2d());
+            assert_eq!(environment.radiance(dir), Color::from(100.));
+            assert!(pdf > 0.);
+        }
+    }
+
+    #[test]
+    fn load_empty_image() {
+        let path = std::env::temp_dir().join(format!("raytracer-empty-{}.pfm", std::process::id()));
+        Framebuffer::new(0, 0)
+            .save(&path, &ToneMapping::new())
+            .unwrap();
+        let result = EnvironmentMap::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(result.err().unwrap().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,163 @@
+use crate::filter::Filter;
+use crate::framebuffer::Framebuffer;
+use crate::material::Color;
+
+/// Accumulates filtered samples for a rectangle of pixels [x0, x0 + width) x [y0, y0 + height) of
+/// the image. For every pixel it keeps the sum of the weighted sample colors and the sum of the
+/// weights. Samples are splatted into all the pixels within the filter radius, so a film for a
+/// tile also covers a margin around the tile, and the films of neighbouring tiles are merged.
+/// Smallest sum of the weights of a pixel that isn't black.
+const MIN_WEIGHT: f32 = 1E-3;
+
+pub(crate) struct Film {
+    x0: u32,
+    y0: u32,
+    width: u32,
+    height: u32,
+    colors: Vec<Color>,
+    weights: Vec<f32>,
+}
+
+impl Film {
+    pub(crate) fn new(x0: u32, y0: u32, width: u32, height: u32) -> Self {
+        let size = (width * height) as usize;
+        Film {
+            x0,
+            y0,
+            width,
+            height,
+            colors: vec![Color::black(); size],
+            weights: vec![0.; size],
+        }
+    }
+
+    /// Film that receives all the samples taken in the pixels [x0, x1) x [y0, y1) of an image of
+    /// size `image_width` x `image_height`.
+    pub(crate) fn for_pixels(
+        (x0, y0, x1, y1): (u32, u32, u32, u32),
+        filter: &Filter,
+        image_width: u32,
+        image_height: u32,
+    ) -> Self {
+        // A sample in pixel x can reach the pixel centers up to x + 0.5 + radius.
+        let margin = (filter.radius() + 0.5).ceil() as u32;
+        let fx0 = x0.saturating_sub(margin);
+        let fy0 = y0.saturating_sub(margin);
+        let fx1 = x1.saturating_add(margin).min(image_width);
+        let fy1 = y1.saturating_add(margin).min(image_height);
+        Film::new(fx0, fy0, fx1 - fx0, fy1 - fy0)
+    }
+
+    /// Adds a sample at the position (x, y) in the image, measured in pixels from the top left
+    /// corner, so that the center of the pixel (i, j) is at (i + 0.5, j + 0.5).
+    pub(crate) fn add_sample(&mut self, filter: &Filter, x: f32, y: f32, color: Color) {
+        let radius = filter.radius();
+        let range = |center: f32, start: u32, size: u32| {
+            let first = (center - 0.5 - radius).ceil().max(start as f32) as u32;
+            let last = ((center - 0.5 + radius).floor() + 1.).min((start + size) as f32) as u32;
+            first..last.max(first)
+        };
+        for py in range(y, self.y0, self.height) {
+            for px in range(x, self.x0, self.width) {
+                let weight = filter.evaluate(px as f32 + 0.5 - x, py as f32 + 0.5 - y);
+                if weight == 0. {
+                    continue;
+                }
+                let index = ((py - self.y0) * self.width + px - self.x0) as usize;
+                self.colors[index] += color * weight;
+                self.weights[index] += weight;
+            }
+        }
+    }
+
+    /// Adds the sums of another film, which has to lie within this one.
+    pub(crate) fn merge(&mut self, other: &Film) {
+        assert!(other.x0 >= self.x0 && other.x0 + other.width <= self.x0 + self.width);
+        assert!(other.y0 >= self.y0 && other.y0 + other.height <= self.y0 + self.height);
+        for y in 0..other.height {
+            for x in 0..other.width {
+                let other_index = (y * other.width + x) as usize;
+                let index =
+                    ((other.y0 + y - self.y0) * self.width + other.x0 + x - self.x0) as usize;
+                self.colors[index] += other.colors[other_index];
+                self.weights[index] += other.weights[other_index];
+            }
+        }
+    }
+
+    /// Weighted averages of the samples. Pixels without any weight are black, as are the pixels
+    /// whose weights add up to almost nothing or less with the negative lobes of a filter.
+    pub(crate) fn to_framebuffer(&self) -> Framebuffer {
+        let mut image = Framebuffer::new(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let index = (y * self.width + x) as usize;
+                let weight = self.weights[index];
+                if weight > MIN_WEIGHT {
+                    image.put_pixel(x, y, self.colors[index] * (1. / weight));
+                }
+            }
+        }
+        image
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::FilterType;
+
+    #[test]
+    fn film_splatting() {
+        let filter = Filter::with_default_radius(FilterType::Gaussian);
+        let mut film = Film::new(0, 0, 8, 8);
+        let mut tile = Film::for_pixels((4, 4, 8, 8), &filter, 8, 8);
+        // Constant color stays constant after filtering, wherever the samples are.
+        for i in 0..64 {
+            let (x, y) = ((i % 8) as f32 + 0.3, (i / 8) as f32 + 0.8);
+            if x >= 4. && y >= 4. {
+                tile.add_sample(&filter, x, y, Color::new(0.5, 1., 2.));
+            } else {
+                film.add_sample(&filter, x, y, Color::new(0.5, 1., 2.));
+            }
+        }
+        film.merge(&tile);
+        let image = film.to_framebuffer();
+        // Margins wider than the image are cut off at its edges.
+        let wide = Film::for_pixels((4, 4, 8, 8), &Filter::new(FilterType::Box, 1E10), 8, 8);
+        assert_eq!((wide.x0, wide.y0, wide.width, wide.height), (0, 0, 8, 8));
+        for &color in image.pixels().iter() {
+            assert!((color.to_array()[0] - 0.5).abs() < 1E-5);
+            assert!((color.to_array()[2] - 2.).abs() < 1E-5);
+        }
+    }
+
+    #[test]
+    fn box_filter_stays_in_pixel() {
+        let filter = Filter::default();
+        let mut film = Film::new(0, 0, 3, 3);
+        film.add_sample(&filter, 1.25, 1.75, Color::new(1., 1., 1.));
+        let image = film.to_framebuffer();
+        assert_eq!(image.get_pixel(1, 1), Color::new(1., 1., 1.));
+        assert_eq!(image.get_pixel(1, 2), Color::black());
+        assert_eq!(image.get_pixel(0, 1), Color::black());
+
+        // A sample on the boundary between two pixels goes to only one of them.
+        let mut film = Film::new(0, 0, 3, 3);
+        film.add_sample(&filter, 2., 1.5, Color::new(1., 1., 1.));
+        let image = film.to_framebuffer();
+        assert_eq!(image.get_pixel(1, 1), Color::new(1., 1., 1.));
+        assert_eq!(image.get_pixel(2, 1), Color::black());
+    }
+
+    #[test]
+    fn negative_weights() {
+        // The pixel at 1.5 from the sample only gets the negative lobe of the filter.
+        let filter = Filter::with_default_radius(FilterType::Mitchell);
+        let mut film = Film::new(0, 0, 4, 1);
+        film.add_sample(&filter, 2., 0.5, Color::new(1., 1., 1.));
+        let image = film.to_framebuffer();
+        assert!(image.get_pixel(1, 0).luminance() > 0.);
+        assert_eq!(image.get_pixel(3, 0), Color::black());
+    }
+}
@@ -0,0 +1,180 @@
+use std::f32::consts::PI;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FilterType {
+    /// Equal weights within the radius. With radius 0.5 every sample only contributes to the
+    /// pixel it was taken in.
+    Box,
+    /// Weights falling off linearly to 0 at the radius.
+    Tent,
+    /// Gaussian with the standard deviation of a third of the radius, shifted to reach 0 at the
+    /// radius.
+    Gaussian,
+    /// Mitchell-Netravali cubic with B = C = 1/3.
+    Mitchell,
+    /// Windowed sinc with as many lobes as the radius in pixels.
+    Lanczos,
+}
+
+impl FilterType {
+    /// The usual radius of the filter in pixels.
+    pub fn default_radius(self) -> f32 {
+        match self {
+            FilterType::Box => 0.5,
+            FilterType::Tent => 1.,
+            FilterType::Gaussian => 1.5,
+            FilterType::Mitchell => 2.,
+            FilterType::Lanczos => 3.,
+        }
+    }
+}
+
+impl FromStr for FilterType {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, String> {
+        match name {
+            "box" => Ok(FilterType::Box),
+            "tent" => Ok(FilterType::Tent),
+            "gaussian" => Ok(FilterType::Gaussian),
+            "mitchell" => Ok(FilterType::Mitchell),
+            "lanczos" => Ok(FilterType::Lanczos),
+            _ => Err(format!("unknown filter '{}'", name)),
+        }
+    }
+}
+
+/// Pixel reconstruction filter. Every sample contributes to all the pixels whose centers are
+/// within the radius from it, with the weight given by the filter, and the pixel color is the
+/// weighted average of the contributing samples.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Filter {
+    filter_type: FilterType,
+    radius: f32,
+}
+
+impl Filter {
+    pub fn new(filter_type: FilterType, radius: f32) -> Self {
+        assert!(radius > 0.);
+        Filter {
+            filter_type,
+            radius,
+        }
+    }
+
+    /// Filter of the given type with its default radius.
+    pub fn with_default_radius(filter_type: FilterType) -> Self {
+        Self::new(filter_type, filter_type.default_radius())
+    }
+
+    pub fn filter_type(&self) -> FilterType {
+        self.filter_type
+    }
+
+    pub fn radius(&self) -> f32 {
+        self.radius
+    }
+
+    /// Weight of a sample at the offset (dx, dy) in pixels from the pixel center. The filters are
+    /// separable.
+    pub fn evaluate(&self, dx: f32, dy: f32) -> f32 {
+        self.evaluate_1d(dx) * self.evaluate_1d(dy)
+    }
+
+    fn evaluate_1d(&self, x: f32) -> f32 {
+        let r = self.radius;
+        // The support is half-open, so that a sample on the boundary between two pixels counts
+        // for only one of them with the box filter.
+        if x < -r || x >= r {
+            return 0.;
+        }
+        let x = x.abs();
+        match self.filter_type {
+            FilterType::Box => 1.,
+            FilterType::Tent => 1. - x / r,
+            FilterType::Gaussian => {
+                let sigma = r / 3.;
+                let gaussian = |x: f32| (-x * x / (2. * sigma * sigma)).exp();
+                gaussian(x) - gaussian(r)
+            }
+            FilterType::Mitchell => mitchell(2. * x / r),
+            FilterType::Lanczos => sinc(x) * sinc(x / r),
+        }
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self::with_default_radius(FilterType::Box)
+    }
+}
+
+/// Mitchell-Netravali cubic with B = C = 1/3 for x in [0, 2].
+fn mitchell(x: f32) -> f32 {
+    const B: f32 = 1. / 3.;
+    const C: f32 = 1. / 3.;
+    let polynomial = if x < 1. {
+        (12. - 9. * B - 6. * C) * x.powi(3) + (-18. + 12. * B + 6. * C) * x.powi(2) + (6. - 2. * B)
+    } else {
+        (-B - 6. * C) * x.powi(3)
+            + (6. * B + 30. * C) * x.powi(2)
+            + (-12. * B - 48. * C) * x
+            + (8. * B + 24. * C)
+    };
+    polynomial / 6.
+}
+
+fn sinc(x: f32) -> f32 {
+    if x < 1E-5 {
+        1.
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter_values() {
+        let box_filter = Filter::default();
+        assert_eq!(box_filter.evaluate(0.3, -0.4), 1.);
+        assert_eq!(box_filter.evaluate(0.6, 0.), 0.);
+        assert_eq!(box_filter.evaluate(-0.5, 0.), 1.);
+        assert_eq!(box_filter.evaluate(0.5, 0.), 0.);
+
+        let tent = Filter::new(FilterType::Tent, 2.);
+        assert_eq!(tent.evaluate(1., 0.), 0.5);
+        assert_eq!(tent.evaluate(1., 1.), 0.25);
+
+        let mitchell = Filter::with_default_radius(FilterType::Mitchell);
+        assert_relative_eq!(mitchell.evaluate(0., 0.), (8. / 9.) * (8. / 9.));
+        assert_relative_eq!(mitchell.evaluate(1., 0.), 1. / 18. * 8. / 9.);
+        // Negative lobe.
+        assert!(mitchell.evaluate(1.5, 0.) < 0.);
+
+        let lanczos = Filter::with_default_radius(FilterType::Lanczos);
+        assert_eq!(lanczos.evaluate(0., 0.), 1.);
+        assert!(lanczos.evaluate(1., 0.).abs() < 1E-6);
+        assert!(lanczos.evaluate(1.5, 0.) < 0.);
+    }
+
+    #[test]
+    fn filters_vanish_at_radius() {
+        for &filter_type in [
+            FilterType::Tent,
+            FilterType::Gaussian,
+            FilterType::Mitchell,
+            FilterType::Lanczos,
+        ]
+        .iter()
+        {
+            let filter = Filter::new(filter_type, 1.7);
+            assert!(filter.evaluate(0., 0.) > 0.);
+            assert!(filter.evaluate(1.7, 0.).abs() < 1E-6, "{:?}", filter_type);
+            assert_eq!(filter.evaluate(0., 1.8), 0.);
+        }
+    }
+}
@@ -0,0 +1,477 @@
+//! Floating-point image that keeps the full dynamic range of the rendered radiance, and writers
+//! for the HDR image formats: OpenEXR, Radiance HDR (RGBE) and PFM.
+
+use image::codecs::hdr::HdrDecoder;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::material::Color;
+use crate::tonemap::{srgb_eotf, ToneMapping};
+
+/// A grid of linear RGB colors stored in row-major order, starting from the top left corner.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Framebuffer {
+    width: u32,
+    height: u32,
+    pixels: Vec<Color>,
+}
+
+impl Framebuffer {
+    /// Creates a black framebuffer.
+    pub fn new(width: u32, height: u32) -> Self {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![Color::black(); (width * height) as usize],
+        }
+    }
+
+    /// Visualizes per-pixel values given in row-major order, e.g. sample counts, with a color
+    /// ramp from blue for the smallest value through green to red for the largest one.
+    pub fn heatmap(width: u32, height: u32, values: &[u32]) -> Self {
+        assert_eq!(values.len(), (width * height) as usize);
+        let min = values.iter().copied().min().unwrap_or(0);
+        let max = values.iter().copied().max().unwrap_or(0);
+        let range = (max - min).max(1) as f32;
+        let pixels = values
+            .iter()
+            .map(|&value| {
+                let t = (value - min) as f32 / range;
+                if t < 0.5 {
+                    Color::new(0., 2. * t, 1. - 2. * t)
+                } else {
+                    Color::new(2. * t - 1., 2. - 2. * t, 0.)
+                }
+            })
+            .collect();
+        Framebuffer {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn get_pixel(&self, x: u32, y: u32) -> Color {
+        self.pixels[self.index(x, y)]
+    }
+
+    pub fn put_pixel(&mut self, x: u32, y: u32, color: Color) {
+        let index = self.index(x, y);
+        self.pixels[index] = color;
+    }
+
+    pub fn pixels(&self) -> &[Color] {
+        &self.pixels
+    }
+
+    /// Root mean square difference from another image of the same size, over all the pixels and
+    /// color channels.
+    pub fn rmse(&self, other: &Framebuffer) -> f32 {
+        assert_eq!((self.width, self.height), (other.width, other.height));
+        let mut sum = 0.;
+        for (a, b) in self.pixels.iter().zip(other.pixels.iter()) {
+            for (a, b) in a.to_array().iter().zip(b.to_array().iter()) {
+                sum += ((a - b) as f64).powi(2);
+            }
+        }
+        (sum / (3 * self.pixels.len()) as f64).sqrt() as f32
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        assert!(x < self.width && y < self.height);
+        (y * self.width + x) as usize
+    }
+
+    /// Converts to an 8-bit sRGB image.
+    pub fn to_rgb8(
+        &self,
+        tone_mapping: &ToneMapping,
+    ) -> image::ImageBuffer<image::Rgb<u8>, Vec<u8>> {
+        image::ImageBuffer::from_fn(self.width, self.height, |x, y| {
+            tone_mapping.encode(self.get_pixel(x, y))
+        })
+    }
+
+    /// Saves the image in the format given by the file extension: `exr`, `hdr` or `pfm` keep the
+    /// full range, anything else is converted to 8 bits with `tone_mapping` and saved by the
+    /// `image` crate.
+    pub fn save(&self, path: impl AsRef<Path>, tone_mapping: &ToneMapping) -> io::Result<()> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase());
+        let write: fn(&Self, &mut BufWriter<File>) -> io::Result<()> = match extension.as_deref() {
+            Some("exr") => |image, writer| image.write_exr(writer),
+            Some("hdr") => |image, writer| image.write_hdr(writer),
+            Some("pfm") => |image, writer| image.write_pfm(writer),
+            _ => {
+                return self
+                    .to_rgb8(tone_mapping)
+                    .save(path)
+                    .map_err(io::Error::other);
+            }
+        };
+        let mut writer = BufWriter::new(File::create(path)?);
+        write(self, &mut writer)?;
+        writer.flush()
+    }
+
+    /// Reads an image, with the format given by the extension. HDR and PFM images keep their
+    /// linear values, other formats are 8-bit sRGB and get decoded to linear values.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::load_with(path.as_ref(), srgb_eotf)
+    }
+
+    /// Reads an image like `load`, but keeps 8-bit values as they are, only scaled to [0, 1].
+    /// This is for images holding data rather than colors, such as normal maps.
+    pub fn load_raw(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::load_with(path.as_ref(), |value| value)
+    }
+
+    fn load_with(path: &Path, decode: fn(f32) -> f32) -> io::Result<Self> {
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("hdr") => return Self::read_hdr(BufReader::new(File::open(path)?)),
+            Some("pfm") => return Self::read_pfm(&mut BufReader::new(File::open(path)?)),
+            _ => (),
+        }
+        let image = image::open(path).map_err(io::Error::other)?.to_rgb8();
+        let pixels = image
+            .pixels()
+            .map(|pixel| {
+                let [r, g, b] = pixel.0;
+                let decode = |c: u8| decode(c as f32 / 255.);
+                Color::new(decode(r), decode(g), decode(b))
+            })
+            .collect();
+        Ok(Framebuffer {
+            width: image.width(),
+            height: image.height(),
+            pixels,
+        })
+    }
+
+    /// Writes an uncompressed scanline OpenEXR image with 32-bit float R, G and B channels.
+    pub fn write_exr(&self, writer: &mut impl Write) -> io::Result<()> {
+        let (width, height) = (self.width as i32, self.height as i32);
+        // Channels have to be listed in alphabetical order.
+        let channels = ["B", "G", "R"];
+
+        let mut header = Vec::new();
+        // Magic number and version 2, single-part scanline image.
+        header.extend_from_slice(&[0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0]);
+
+        let mut channel_list = Vec::new();
+        for name in channels.iter() {
+            channel_list.extend_from_slice(name.as_bytes());
+            channel_list.push(0);
+            // FLOAT pixel type, pLinear and reserved bytes, x and y sampling.
+            channel_list.extend_from_slice(&2i32.to_le_bytes());
+            channel_list.extend_from_slice(&[0; 4]);
+            channel_list.extend_from_slice(&1i32.to_le_bytes());
+            channel_list.extend_from_slice(&1i32.to_le_bytes());
+        }
+        channel_list.push(0);
+        exr_attribute(&mut header, "channels", "chlist", &channel_list);
+        // No compression.
+        exr_attribute(&mut header, "compression", "compression", &[0]);
+        let mut window = Vec::new();
+        for value in [0, 0, width - 1, height - 1].iter() {
+            window.extend_from_slice(&value.to_le_bytes());
+        }
+        exr_attribute(&mut header, "dataWindow", "box2i", &window);
+        exr_attribute(&mut header, "displayWindow", "box2i", &window);
+        // Increasing Y.
+        exr_attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+        exr_attribute(
+            &mut header,
+            "pixelAspectRatio",
+            "float",
+            &1f32.to_le_bytes(),
+        );
+        exr_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+        exr_attribute(
+            &mut header,
+            "screenWindowWidth",
+            "float",
+            &1f32.to_le_bytes(),
+        );
+        header.push(0);
+        writer.write_all(&header)?;
+
+        // Offset table with the position of every scanline in the file. Each scanline starts with
+        // its y coordinate and the size of the pixel data.
+        let line_size = 4 * channels.len() * self.width as usize;
+        for y in 0..self.height as usize {
+            let offset = header.len() + 8 * self.height as usize + y * (8 + line_size);
+            writer.write_all(&(offset as u64).to_le_bytes())?;
+        }
+
+        let mut line = Vec::with_capacity(line_size);
+        for y in 0..self.height {
+            line.clear();
+            let row = &self.pixels[(y * self.width) as usize..((y + 1) * self.width) as usize];
+            for channel in (0..3).rev() {
+                for color in row.iter() {
+                    line.extend_from_slice(&color.to_array()[channel].to_le_bytes());
+                }
+            }
+            writer.write_all(&(y as i32).to_le_bytes())?;
+            writer.write_all(&(line_size as i32).to_le_bytes())?;
+            writer.write_all(&line)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes a Radiance HDR image with uncompressed RGBE pixels.
+    pub fn write_hdr(&self, writer: &mut impl Write) -> io::Result<()> {
+        write!(
+            writer,
+            "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+            self.height, self.width
+        )?;
+        for &color in self.pixels.iter() {
+            writer.write_all(&rgbe(color))?;
+        }
+        Ok(())
+    }
+
+    /// Reads a Radiance HDR image.
+    pub fn read_hdr(reader: impl BufRead) -> io::Result<Self> {
+        let decoder = HdrDecoder::new(reader).map_err(io::Error::other)?;
+        let metadata = decoder.metadata();
+        let pixels = decoder
+            .read_image_hdr()
+            .map_err(io::Error::other)?
+            .into_iter()
+            .map(|pixel| {
+                let [r, g, b] = pixel.0;
+                Color::new(r, g, b)
+            })
+            .collect();
+        Ok(Framebuffer {
+            width: metadata.width,
+            height: metadata.height,
+            pixels,
+        })
+    }
+
+    /// Writes a little-endian color PFM image. PFM stores the rows from bottom to top.
+    pub fn write_pfm(&self, writer: &mut impl Write) -> io::Result<()> {
+        write!(writer, "PF\n{} {}\n-1.0\n", self.width, self.height)?;
+        for y in (0..self.height).rev() {
+            for x in 0..self.width {
+                for component in self.get_pixel(x, y).to_array().iter() {
+                    writer.write_all(&component.to_le_bytes())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a color PFM image, e.g. a reference image written by `write_pfm`.
+    pub fn read_pfm(reader: &mut impl Read) -> io::Result<Self> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+
+        // The header is four whitespace-separated tokens followed by a single whitespace.
+        let mut tokens = Vec::new();
+        let mut pos = 0;
+        while tokens.len() < 4 {
+            while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            let start = pos;
+            while pos < bytes.len() && !bytes[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if start == pos {
+                return Err(invalid("truncated PFM header"));
+            }
+            tokens.push(String::from_utf8_lossy(&bytes[start..pos]).into_owned());
+        }
+        let data = bytes.get(pos + 1..).unwrap_or(&[]);
+
+        if tokens[0] != "PF" {
+            return Err(invalid("not a color PFM image"));
+        }
+        let width: u32 = tokens[1]
+            .parse()
+            .map_err(|_| invalid("invalid PFM width"))?;
+        let height: u32 = tokens[2]
+            .parse()
+            .map_err(|_| invalid("invalid PFM height"))?;
+        let scale: f32 = tokens[3]
+            .parse()
+            .map_err(|_| invalid("invalid PFM scale"))?;
+        if data.len() != 12 * (width * height) as usize {
+            return Err(invalid("wrong size of PFM data"));
+        }
+
+        let mut values = data.chunks(4).map(|chunk| {
+            let bytes = [chunk[0], chunk[1], chunk[2], chunk[3]];
+            if scale < 0. {
+                f32::from_le_bytes(bytes)
+            } else {
+                f32::from_be_bytes(bytes)
+            }
+        });
+        let mut image = Framebuffer::new(width, height);
+        for y in (0..height).rev() {
+            for x in 0..width {
+                let r = values.next().unwrap();
+                let g = values.next().unwrap();
+                let b = values.next().unwrap();
+                image.put_pixel(x, y, Color::new(r, g, b));
+            }
+        }
+        Ok(image)
+    }
+}
+
+fn exr_attribute(header: &mut Vec<u8>, name: &str, attribute_type: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(attribute_type.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+/// Encodes a color as three 8-bit mantissas with a shared exponent. Negative components are
+/// clamped to 0.
+fn rgbe(color: Color) -> [u8; 4] {
+    let [r, g, b] = color.to_array();
+    // NaN is treated as black too, while infinity saturates.
+    let max = color.max_component().min(f32::MAX);
+    if max.is_nan() || max < 1E-32 {
+        return [0; 4];
+    }
+    // max = m * 2^exponent with m in [0.5, 1), limited to what the exponent byte can hold.
+    let exponent = (((max.to_bits() >> 23) & 0xff) as i32 - 126).min(127);
+    let scale = 256. / 2f32.powi(exponent);
+    let mantissa = |c: f32| (c.max(0.) * scale) as u8;
+    [
+        mantissa(r),
+        mantissa(g),
+        mantissa(b),
+        (exponent + 128) as u8,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_framebuffer() -> Framebuffer {
+        let mut framebuffer = Framebuffer::new(3, 2);
+        framebuffer.put_pixel(0, 0, Color::new(1., 0.5, 0.25));
+        framebuffer.put_pixel(2, 1, Color::new(100., 0., 2.));
+        framebuffer
+    }
+
+    #[test]
+    fn heatmap() {
+        let heatmap = Framebuffer::heatmap(3, 1, &[4, 12, 8]);
+        assert_eq!(heatmap.get_pixel(0, 0), Color::new(0., 0., 1.));
+        assert_eq!(heatmap.get_pixel(1, 0), Color::new(1., 0., 0.));
+        assert_eq!(heatmap.get_pixel(2, 0), Color::new(0., 1., 0.));
+    }
+
+    #[test]
+    fn rgbe_encoding() {
+        assert_eq!(rgbe(Color::new(1., 0.5, 0.)), [128, 64, 0, 129]);
+        assert_eq!(rgbe(Color::new(0.75, 0.25, 3.)), [48, 16, 192, 130]);
+        assert_eq!(rgbe(Color::new(0., -1., 0.)), [0; 4]);
+        assert_eq!(rgbe(Color::new(f32::INFINITY, 1., 0.)), [255, 0, 0, 255]);
+        assert_eq!(rgbe(Color::new(f32::MAX, 0., 0.)), [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn write_pfm() {
+        let mut bytes = Vec::new();
+        create_framebuffer().write_pfm(&mut bytes).unwrap();
+        let header = b"PF\n3 2\n-1.0\n";
+        assert_eq!(&bytes[..header.len()], header);
+        let data: Vec<f32> = bytes[header.len()..]
+            .chunks(4)
+            .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+            .collect();
+        assert_eq!(data.len(), 3 * 3 * 2);
+        // The bottom row comes first.
+        assert_eq!(&data[6..9], &[100., 0., 2.]);
+        assert_eq!(&data[9..12], &[1., 0.5, 0.25]);
+
+        assert_eq!(
+            Framebuffer::read_pfm(&mut &bytes[..]).unwrap(),
+            create_framebuffer()
+        );
+    }
+
+    #[test]
+    fn read_hdr() {
+        let mut bytes = Vec::new();
+        create_framebuffer().write_hdr(&mut bytes).unwrap();
+        assert_eq!(
+            Framebuffer::read_hdr(&bytes[..]).unwrap(),
+            create_framebuffer()
+        );
+    }
+
+    #[test]
+    fn rmse() {
+        let image = create_framebuffer();
+        assert_eq!(image.rmse(&image), 0.);
+        let mut other = image.clone();
+        other.put_pixel(1, 1, Color::new(3., 0., 0.));
+        assert_relative_eq!(image.rmse(&other), (9f32 / 18.).sqrt());
+    }
+
+    #[test]
+    fn write_exr() {
+        let mut bytes = Vec::new();
+        create_framebuffer().write_exr(&mut bytes).unwrap();
+        assert_eq!(&bytes[..4], &[0x76, 0x2f, 0x31, 0x01]);
+
+        // The offset table follows the header and points to the scanlines.
+        let line_size = 4 + 4 + 3 * 3 * 4;
+        let table = bytes.len() - 2 * line_size - 2 * 8;
+        let offset = |y: usize| {
+            let mut entry = [0; 8];
+            entry.copy_from_slice(&bytes[table + 8 * y..table + 8 * y + 8]);
+            u64::from_le_bytes(entry) as usize
+        };
+        assert_eq!(offset(0), table + 16);
+        assert_eq!(offset(1), table + 16 + line_size);
+
+        // Second scanline: y, data size, then the B, G and R channels.
+        let word = |i: usize| {
+            let start = offset(1) + 4 * i;
+            [
+                bytes[start],
+                bytes[start + 1],
+                bytes[start + 2],
+                bytes[start + 3],
+            ]
+        };
+        assert_eq!(i32::from_le_bytes(word(0)), 1);
+        assert_eq!(i32::from_le_bytes(word(1)), 3 * 3 * 4);
+        let value = |i: usize| f32::from_le_bytes(word(i));
+        assert_eq!([value(4), value(7), value(10)], [2., 0., 100.]);
+    }
+}
@@ -2,18 +2,59 @@
 #[macro_use]
 extern crate approx;
 
+mod animation;
+mod bvh;
 mod camera;
 mod defines;
+mod environment;
+mod film;
+mod filter;
+mod framebuffer;
 mod light;
 mod material;
+mod mesh;
+mod obj;
 mod plane;
+mod procedural;
+mod ray;
+mod sampler;
+mod sampling;
 mod scene;
+mod scene_file;
 mod shape;
+mod sky;
 mod sphere;
+mod texture;
+mod tonemap;
+mod triangle;
 
-pub use self::camera::Camera;
+pub use self::animation::{
+    Animated, Animation, Interpolate, Keyframes, Motion, Transform, TransformKeyframes,
+};
+pub use self::bvh::{Aabb, Bvh};
+pub use self::camera::{Camera, Projection};
+pub use self::environment::{Environment, EnvironmentMap};
+pub use self::filter::{Filter, FilterType};
+pub use self::framebuffer::Framebuffer;
+pub use self::ray::Ray;
 pub use self::scene::Scene;
+pub use self::scene_file::{
+    check_finite, check_non_negative, check_positive, load_animation, load_scene, parse_animation,
+    parse_scene, LoadError, ParseError,
+};
 pub use self::plane::*;
+pub use self::procedural::{
+    fbm, perlin_noise, turbulence, voronoi, Pattern, ProceduralTexture, TextureSpace,
+};
+pub use self::sky::{Sky, SunLight};
 pub use self::sphere::*;
-pub use self::material::Material;
-pub use self::shape::*;
\ No newline at end of file
+pub use self::triangle::Triangle;
+pub use self::mesh::TriangleMesh;
+pub use self::material::{Color, Material, MaterialTextures, Surface};
+pub use self::obj::{load_obj, parse_mtl, parse_obj};
+pub use self::shape::*;
+pub use self::texture::{spherical_uv, ImageTexture, Texture, WrapMode};
+pub use self::tonemap::{srgb_eotf, srgb_oetf, ToneMapOperator, ToneMapping};
+pub use self::sampler::{
+    BlueNoiseSampler, HaltonSampler, Sampler, SamplerType, SobolSampler, StratifiedSampler,
+};
\ No newline at end of file
@@ -1,29 +1,51 @@
-use glam::{Vec3, vec3};
-use rand_distr::{UnitSphere, Distribution};
+use glam::Vec3;
+
+use crate::animation::Motion;
+use crate::material::Color;
+use crate::sampler::Sampler;
+use crate::sampling::uniform_sample_sphere;
 
 pub trait Light {
-    // Returns a vector from `from` to the point of intersection with the light source.
-    fn sample_ray<R: rand::Rng>(&self, from: Vec3, rng: &mut R) -> Vec3;
-    fn intensity(&self) -> f32;
+    /// Samples a point of the light source at the moment `time`. Returns the unit direction from
+    /// `from` towards it and its distance, which is infinite for lights whose intensity doesn't
+    /// fall off with the distance.
+    fn sample_ray(&self, from: Vec3, time: f32, sampler: &mut impl Sampler) -> (Vec3, f32);
+    /// Radiant intensity of the light per color channel.
+    fn intensity(&self) -> Color;
 }
 
 pub struct PointLight {
     position: Vec3,
-    intensity: f32,
+    intensity: Color,
+    motion: Option<Box<dyn Motion>>,
 }
 
 impl PointLight {
-    pub fn new(position: Vec3, intensity: f32) -> Self {
-        PointLight { position, intensity }
+    pub fn new(position: Vec3, intensity: impl Into<Color>) -> Self {
+        PointLight {
+            position,
+            intensity: intensity.into(),
+            motion: None,
+        }
+    }
+
+    /// Moves the light with the transform of `motion`.
+    pub fn set_motion(mut self, motion: impl Motion + 'static) -> Self {
+        self.motion = Some(Box::new(motion));
+        self
+    }
+
+    fn position(&self, time: f32) -> Vec3 {
+        moved_point(self.position, self.motion.as_deref(), time)
     }
 }
 
 impl Light for PointLight {
-    fn sample_ray<R: rand::Rng>(&self, from: Vec3, _rng: &mut R) -> Vec3 {
-        self.position - from
+    fn sample_ray(&self, from: Vec3, time: f32, _sampler: &mut impl Sampler) -> (Vec3, f32) {
+        direction_to(self.position(time) - from)
     }
 
-    fn intensity(&self) -> f32 {
+    fn intensity(&self) -> Color {
         self.intensity
     }
 }
@@ -31,29 +53,50 @@ impl Light for PointLight {
 pub struct SphereLight {
     center: Vec3,
     radius: f32,
-    intensity: f32,
+    intensity: Color,
+    motion: Option<Box<dyn Motion>>,
 }
 
 impl SphereLight {
-    pub fn new(center: Vec3, radius: f32, intensity: f32) -> Self {
+    pub fn new(center: Vec3, radius: f32, intensity: impl Into<Color>) -> Self {
         SphereLight {
             center,
             radius,
-            intensity,
+            intensity: intensity.into(),
+            motion: None,
         }
     }
+
+    /// Moves the center of the light with the transform of `motion`.
+    pub fn set_motion(mut self, motion: impl Motion + 'static) -> Self {
+        self.motion = Some(Box::new(motion));
+        self
+    }
 }
 
 impl Light for SphereLight {
-    fn sample_ray<R: rand::Rng>(&self, from: Vec3, rng: &mut R) -> Vec3 {
-        let radial: [f32; 3] = UnitSphere.sample(rng);
-        let radial = vec3(radial[0], radial[1], radial[2]);
+    fn sample_ray(&self, from: Vec3, time: f32, sampler: &mut impl Sampler) -> (Vec3, f32) {
+        let radial = uniform_sample_sphere(sampler);
         // assert!((unit.magnitude2() - S::one()).abs() < S::from(0.00001).unwrap());
-        let sphere_point = self.center + radial * self.radius;
-        sphere_point - from
+        let center = moved_point(self.center, self.motion.as_deref(), time);
+        let sphere_point = center + radial * self.radius;
+        direction_to(sphere_point - from)
     }
 
-    fn intensity(&self) -> f32 {
+    fn intensity(&self) -> Color {
         self.intensity
     }
 }
+
+/// The unit direction and the length of `vec`.
+fn direction_to(vec: Vec3) -> (Vec3, f32) {
+    let dist = vec.length();
+    (vec / dist, dist)
+}
+
+fn moved_point(point: Vec3, motion: Option<&dyn Motion>, time: f32) -> Vec3 {
+    match motion {
+        Some(motion) => motion.transform(time).matrix().transform_point3(point),
+        None => point,
+    }
+}
@@ -1,39 +1,687 @@
-use glam::vec3;
-use rand::SeedableRng as _;
+use glam::Vec3;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::process;
 
 use raytracer::*;
 
-fn main() {
-    let mut scene = Scene::new();
-    scene.add_plane(
-        Plane::new(vec3(0., -1., 0.), vec3(0., 1., 0.)),
-        Material::new(0.8, 0.8, 0.8),
-    );
+const USAGE: &str = "\
+Usage: raytracer [OPTIONS] [SCENE]
 
-    scene.add_sphere(
-        Sphere::new(vec3(0.0, 0.0, -3.), 1.),
-        Material::new(0.75, 0.25, 0.25),
-    );
-    scene.add_sphere(
-        Sphere::new(vec3(1.0, 3.0, -10.), 2.),
-        Material::new(0.25, 0.65, 0.25),
-    );
-    scene.add_sphere(
-        Sphere::new(vec3(0.65, 0.65, -2.3), 0.1),
-        Material::new(0.6, 0.4, 0.2),
+Renders SCENE (default: scenes/default.scene) into an image, or into a sequence
+of frames if the scene is animated.
+
+Options:
+  -o, --output FILE     Output image (default: image.png). The format is given by
+                        the extension: .exr, .hdr and .pfm keep the full dynamic
+                        range, other formats are 8-bit. Can be repeated. For frame
+                        sequences the last run of '#' in the file name is replaced
+                        by the frame number (default: frame_####.png).
+      --frames FIRST-LAST
+                        Render only these frames of the animation, e.g. 1-48 or 7.
+                        Frames whose outputs all exist already are skipped.
+      --width N         Image width in pixels
+      --height N        Image height in pixels
+  -s, --samples N       Samples per pixel, the maximum with adaptive sampling
+      --adaptive T      Adaptive sampling: stop sampling a pixel once its relative
+                        error is below T, e.g. 0.05
+      --min-samples N   Minimum samples per pixel with adaptive sampling
+      --heatmap FILE    Write an image of the number of samples per pixel
+      --sampler NAME    Sample generator: independent (default), stratified,
+                        halton, sobol or bluenoise
+      --filter NAME     Pixel filter: box (default), tent, gaussian, mitchell or
+                        lanczos
+      --filter-radius R Filter radius in pixels (default: depends on the filter)
+      --reference FILE  Print the RMSE of the image compared to a PFM reference
+      --seed N          Random seed (default: random, 0 for frame sequences)
+  -j, --threads N       Number of render threads (default: one per core)
+      --eye X,Y,Z       Camera position
+      --target X,Y,Z    Point the camera looks at
+      --up X,Y,Z        Camera up direction
+      --fov DEGREES     Horizontal field of view
+      --projection NAME Camera projection: perspective (default), orthographic,
+                        fisheye or equirectangular
+      --ortho-width W   View width of the orthographic projection in scene units
+      --aperture R      Lens radius for depth of field (default: 0, a pinhole)
+      --focus-distance D
+                        Distance of the plane in focus (default: the target)
+      --shutter OPEN,CLOSE
+                        Shutter interval for motion blur (default: 0,0)
+      --exposure STOPS  Exposure adjustment of 8-bit outputs (default: 0)
+      --tonemap OP      Tone mapping of 8-bit outputs: clamp (default), reinhard,
+                        aces or hable
+      --help            Print this message
+";
+
+/// Options that take a value.
+const OPTIONS: &[&str] = &[
+    "-o",
+    "--output",
+    "--frames",
+    "--width",
+    "--height",
+    "-s",
+    "--samples",
+    "--adaptive",
+    "--min-samples",
+    "--heatmap",
+    "--sampler",
+    "--filter",
+    "--filter-radius",
+    "--reference",
+    "--seed",
+    "-j",
+    "--threads",
+    "--eye",
+    "--target",
+    "--up",
+    "--fov",
+    "--projection",
+    "--ortho-width",
+    "--aperture",
+    "--focus-distance",
+    "--shutter",
+    "--exposure",
+    "--tonemap",
+];
+
+struct Options {
+    scene: PathBuf,
+    /// Empty for the default outputs.
+    outputs: Vec<PathBuf>,
+    frames: Option<(u32, u32)>,
+    width: Option<u32>,
+    height: Option<u32>,
+    samples: Option<u32>,
+    adaptive_threshold: Option<f32>,
+    min_samples: Option<u32>,
+    heatmap: Option<PathBuf>,
+    sampler: SamplerType,
+    filter: Option<FilterType>,
+    filter_radius: Option<f32>,
+    reference: Option<PathBuf>,
+    seed: Option<u64>,
+    threads: usize,
+    eye: Option<Vec3>,
+    target: Option<Vec3>,
+    up: Option<Vec3>,
+    fov: Option<f32>,
+    projection: Option<Projection>,
+    aperture_radius: Option<f32>,
+    focus_distance: Option<f32>,
+    shutter: Option<(f32, f32)>,
+    tone_mapping: ToneMapping,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            scene: PathBuf::from("scenes/default.scene"),
+            outputs: Vec::new(),
+            frames: None,
+            width: None,
+            height: None,
+            samples: None,
+            adaptive_threshold: None,
+            min_samples: None,
+            heatmap: None,
+            sampler: SamplerType::Independent,
+            filter: None,
+            filter_radius: None,
+            reference: None,
+            seed: None,
+            threads: 0,
+            eye: None,
+            target: None,
+            up: None,
+            fov: None,
+            projection: None,
+            aperture_radius: None,
+            focus_distance: None,
+            shutter: None,
+            tone_mapping: ToneMapping::new(),
+        }
+    }
+}
+
+/// Parses the command line arguments (without the program name). Returns `None` if the usage
+/// message was requested.
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Option<Options>, String> {
+    let mut options = Options::default();
+    let mut scene = None;
+    let mut projection = None;
+    let mut ortho_width = None;
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        if !arg.starts_with('-') || arg == "-" {
+            if scene.replace(PathBuf::from(&arg)).is_some() {
+                return Err(format!("unexpected argument '{}'", arg));
+            }
+            continue;
+        }
+
+        // Both "--option value" and "--option=value" are accepted.
+        let (name, inline_value) = match arg.find('=') {
+            Some(pos) => (&arg[..pos], Some(arg[pos + 1..].to_string())),
+            None => (arg.as_str(), None),
+        };
+        if name == "--help" {
+            return Ok(None);
+        }
+        if !OPTIONS.contains(&name) {
+            return Err(format!("unknown option '{}'", name));
+        }
+        let value = match inline_value.or_else(|| args.next()) {
+            Some(value) => value,
+            None => return Err(format!("missing value for '{}'", name)),
+        };
+
+        match name {
+            "-o" | "--output" => options.outputs.push(PathBuf::from(value)),
+            "--frames" => options.frames = Some(parse_frames(name, &value)?),
+            "--width" => options.width = Some(parse_positive(name, &value)?),
+            "--height" => options.height = Some(parse_positive(name, &value)?),
+            "-s" | "--samples" => options.samples = Some(parse_positive(name, &value)?),
+            "--adaptive" => {
+                options.adaptive_threshold =
+                    Some(check_non_negative(name, parse_value(name, &value)?)?)
+            }
+            "--min-samples" => options.min_samples = Some(parse_positive(name, &value)?),
+            "--heatmap" => options.heatmap = Some(PathBuf::from(value)),
+            "--sampler" => options.sampler = parse_value(name, &value)?,
+            "--filter" => options.filter = Some(parse_value(name, &value)?),
+            "--filter-radius" => {
+                options.filter_radius = Some(check_positive(name, parse_value(name, &value)?)?)
+            }
+            "--reference" => options.reference = Some(PathBuf::from(value)),
+            "--seed" => options.seed = Some(parse_value(name, &value)?),
+            "-j" | "--threads" => options.threads = parse_value(name, &value)?,
+            "--eye" => options.eye = Some(parse_vec3(name, &value)?),
+            "--target" => options.target = Some(parse_vec3(name, &value)?),
+            "--up" => options.up = Some(parse_vec3(name, &value)?),
+            "--fov" => options.fov = Some(parse_value(name, &value)?),
+            "--projection" => projection = Some(value),
+            "--ortho-width" => match parse_value(name, &value)? {
+                width if width > 0. => ortho_width = Some(width),
+                _ => return Err(format!("'{}' must be positive", name)),
+            },
+            "--aperture" => {
+                options.aperture_radius =
+                    Some(check_non_negative(name, parse_value(name, &value)?)?)
+            }
+            "--focus-distance" => {
+                options.focus_distance = Some(check_positive(name, parse_value(name, &value)?)?)
+            }
+            "--shutter" => options.shutter = Some(parse_shutter(name, &value)?),
+            "--exposure" => {
+                options.tone_mapping = options
+                    .tone_mapping
+                    .set_exposure(parse_value(name, &value)?)
+            }
+            "--tonemap" => {
+                options.tone_mapping = options
+                    .tone_mapping
+                    .set_operator(parse_value(name, &value)?)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    if let Some(scene) = scene {
+        options.scene = scene;
+    }
+    options.projection = match (projection.as_deref(), ortho_width) {
+        (Some("perspective"), None) => Some(Projection::Perspective),
+        (Some("orthographic"), Some(width)) => Some(Projection::Orthographic { width }),
+        (Some("orthographic"), None) => {
+            return Err("'--projection orthographic' requires '--ortho-width'".to_string())
+        }
+        (Some("fisheye"), None) => Some(Projection::Fisheye),
+        (Some("equirectangular"), None) => Some(Projection::Equirectangular),
+        (None, None) => None,
+        (Some(name), None) => return Err(format!("invalid value '{}' for '--projection'", name)),
+        (_, Some(_)) => {
+            return Err("'--ortho-width' requires '--projection orthographic'".to_string())
+        }
+    };
+    Ok(Some(options))
+}
+
+fn parse_value<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value '{}' for '{}'", value, name))
+}
+
+fn parse_positive(name: &str, value: &str) -> Result<u32, String> {
+    match parse_value(name, value)? {
+        0 => Err(format!("'{}' must be positive", name)),
+        n => Ok(n),
+    }
+}
+
+fn parse_vec3(name: &str, value: &str) -> Result<Vec3, String> {
+    let coords = value
+        .split(',')
+        .map(|coord| parse_value(name, coord.trim()))
+        .collect::<Result<Vec<f32>, _>>()?;
+    match coords[..] {
+        [x, y, z] => Ok(Vec3::new(x, y, z)),
+        _ => Err(format!("'{}' requires three comma-separated numbers", name)),
+    }
+}
+
+fn parse_shutter(name: &str, value: &str) -> Result<(f32, f32), String> {
+    let times = value
+        .split(',')
+        .map(|time| check_finite(name, parse_value(name, time.trim())?))
+        .collect::<Result<Vec<f32>, _>>()?;
+    match times[..] {
+        [open, close] if close >= open => Ok((open, close)),
+        [_, _] => Err(format!("'{}' must not close before it opens", name)),
+        _ => Err(format!("'{}' requires two comma-separated numbers", name)),
+    }
+}
+
+fn parse_frames(name: &str, value: &str) -> Result<(u32, u32), String> {
+    let (first, last) = match value.find('-') {
+        Some(pos) => (&value[..pos], &value[pos + 1..]),
+        None => (value, value),
+    };
+    match (parse_value(name, first)?, parse_value(name, last)?) {
+        (first, last) if first > 0 && last >= first => Ok((first, last)),
+        _ => Err(format!("'{}' requires a range of frames from 1 on", name)),
+    }
+}
+
+impl Options {
+    /// The output files, or the patterns of their names for a frame sequence.
+    fn outputs(&self, sequence: bool) -> Vec<PathBuf> {
+        match (self.outputs.is_empty(), sequence) {
+            (false, _) => self.outputs.clone(),
+            (true, false) => vec![PathBuf::from("image.png")],
+            (true, true) => vec![PathBuf::from("frame_####.png")],
+        }
+    }
+}
+
+/// Replaces the last run of '#' in the file name of `pattern` by the frame number, padded with
+/// zeros to the length of the run. `None` if the file name has no '#'.
+fn frame_path(pattern: &Path, frame: u32) -> Option<PathBuf> {
+    let name = pattern.file_name()?.to_str()?;
+    let end = name.rfind('#')? + 1;
+    let start = name[..end].trim_end_matches('#').len();
+    let name = format!(
+        "{}{:0width$}{}",
+        &name[..start],
+        frame,
+        &name[end..],
+        width = end - start
     );
+    Some(pattern.with_file_name(name))
+}
+
+/// Renders an image and writes it to all the outputs, and the sample counts to the heatmap.
+fn render(
+    scene: &Scene,
+    camera: &Camera,
+    seed: u64,
+    outputs: &[PathBuf],
+    heatmap: Option<&Path>,
+    tone_mapping: &ToneMapping,
+) -> Result<Framebuffer, String> {
+    let (image, sample_counts) = camera.render_parallel_with_sample_counts(scene, seed);
+    for output in outputs.iter() {
+        // Written under a temporary name first, so that an interrupted sequence doesn't leave
+        // partial frames that look complete.
+        let extension = output
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or("");
+        let partial = output.with_extension(format!("partial.{}", extension));
+        image
+            .save(&partial, tone_mapping)
+            .and_then(|()| std::fs::rename(&partial, output))
+            .map_err(|err| format!("{}: {}", output.display(), err))?;
+    }
+    if let Some(heatmap) = heatmap {
+        Framebuffer::heatmap(image.width(), image.height(), &sample_counts)
+            .save(heatmap, &ToneMapping::new())
+            .map_err(|err| format!("{}: {}", heatmap.display(), err))?;
+    }
+    Ok(image)
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let (scene, mut camera, animation) =
+        load_animation(&options.scene).map_err(|err| err.to_string())?;
+
+    if options.width.is_some() || options.height.is_some() {
+        let (w, h) = camera.dimensions();
+        camera = camera.set_dimensions(options.width.unwrap_or(w), options.height.unwrap_or(h));
+    }
+    if let Some(samples) = options.samples {
+        camera = camera.set_samples(samples);
+    }
+    if let Some(threshold) = options.adaptive_threshold {
+        camera = camera.set_adaptive_threshold(threshold);
+    }
+    if let Some(min_samples) = options.min_samples {
+        camera = camera.set_min_samples(min_samples);
+    }
+    let keyframed = [
+        ("--eye", options.eye.is_some() && animation.eye().is_some()),
+        (
+            "--target",
+            options.target.is_some() && animation.target().is_some(),
+        ),
+        ("--fov", options.fov.is_some() && animation.fov().is_some()),
+    ];
+    if let Some((name, _)) = keyframed.iter().find(|(_, keyframed)| *keyframed) {
+        return Err(format!("'{}' can't override the camera keyframes", name));
+    }
+    if let Some(eye) = options.eye {
+        camera = camera.set_eye(eye);
+    }
+    if let Some(target) = options.target {
+        camera = camera.set_target(target);
+    }
+    if let Some(up) = options.up {
+        camera = camera.set_up(up);
+    }
+    if let Some(fov) = options.fov {
+        camera = camera.set_fov(fov.to_radians());
+    }
+    if let Some(projection) = options.projection {
+        camera = camera.set_projection(projection);
+    }
+    if let Some(radius) = options.aperture_radius {
+        camera = camera.set_aperture_radius(radius);
+    }
+    if let Some(distance) = options.focus_distance {
+        camera = camera.set_focus_distance(distance);
+    }
+    if let Some((open, close)) = options.shutter {
+        camera = camera.set_shutter(open, close);
+    }
+    if let Some(filter_type) = options.filter {
+        camera = camera.set_filter(Filter::with_default_radius(filter_type));
+    }
+    if let Some(radius) = options.filter_radius {
+        let filter_type = camera.filter().filter_type();
+        camera = camera.set_filter(Filter::new(filter_type, radius));
+    }
+    camera = camera
+        .set_sampler(options.sampler)
+        .set_threads(options.threads);
+
+    let frames = match options.frames {
+        Some(frames) => Some(frames),
+        None if animation.frame_count() > 1 => Some((1, animation.frame_count())),
+        None => None,
+    };
+    if let Some((first, last)) = frames {
+        if last > animation.frame_count() {
+            return Err(format!(
+                "'--frames' goes past the last frame {} of the scene",
+                animation.frame_count()
+            ));
+        }
+        // The same default in every process, so that the frames don't depend on which process
+        // rendered them.
+        let seed = options.seed.unwrap_or(0);
+        return render_frames(options, &scene, &camera, &animation, seed, first..=last);
+    }
+
+    let image = render(
+        &scene,
+        &animation.frame_camera(&camera, 1),
+        options.seed.unwrap_or_else(rand::random),
+        &options.outputs(false),
+        options.heatmap.as_deref(),
+        &options.tone_mapping,
+    )?;
+    if let Some(ref reference) = options.reference {
+        let reference_image = File::open(reference)
+            .and_then(|file| Framebuffer::read_pfm(&mut BufReader::new(file)))
+            .map_err(|err| format!("{}: {}", reference.display(), err))?;
+        if (reference_image.width(), reference_image.height()) != (image.width(), image.height()) {
+            return Err(format!(
+                "{}: the reference image has a different size",
+                reference.display()
+            ));
+        }
+        println!("RMSE: {}", image.rmse(&reference_image));
+    }
+    Ok(())
+}
+
+/// Renders the frames of an animation, skipping the ones that are on disk already. Every frame
+/// gets its own seed, so rendering a range of frames in several processes gives the same images.
+fn render_frames(
+    options: &Options,
+    scene: &Scene,
+    camera: &Camera,
+    animation: &Animation,
+    seed: u64,
+    frames: std::ops::RangeInclusive<u32>,
+) -> Result<(), String> {
+    if options.reference.is_some() {
+        return Err("'--reference' can't be used for a frame sequence".to_string());
+    }
+    let mut patterns = options.outputs(true);
+    patterns.extend(options.heatmap.iter().cloned());
+    if let Some(pattern) = patterns
+        .iter()
+        .find(|pattern| frame_path(pattern, 1).is_none())
+    {
+        return Err(format!(
+            "{}: frame sequences need '#' in the file name for the frame number",
+            pattern.display()
+        ));
+    }
+
+    for frame in frames {
+        let outputs: Vec<PathBuf> = options
+            .outputs(true)
+            .iter()
+            .filter_map(|pattern| frame_path(pattern, frame))
+            .collect();
+        if outputs.iter().all(|output| output.exists()) {
+            println!("frame {}: already rendered", frame);
+            continue;
+        }
+        let heatmap = options
+            .heatmap
+            .as_ref()
+            .and_then(|pattern| frame_path(pattern, frame));
+        render(
+            scene,
+            &animation.frame_camera(camera, frame),
+            seed.wrapping_add(frame as u64),
+            &outputs,
+            heatmap.as_deref(),
+            &options.tone_mapping,
+        )?;
+        println!("frame {}: {}", frame, outputs[0].display());
+    }
+    Ok(())
+}
+
+fn main() {
+    let options = match parse_args(std::env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            print!("{}", USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(err) = run(&options) {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Option<Options>, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
 
-    scene.add_point_light(vec3(0., 0.1, 3.5), 3.);
-    scene.add_sphere_light(vec3(2., 1., 0.), 0.5, 3.);
-    scene.add_sphere_light(vec3(-1., 1., 0.), 0.5, 2.);
-    scene.add_sphere_light(vec3(0., 10., -5.), 1.0, 30.);
-    // scene.add_sphere_light(vec3(-0.65, 0.65, -2.3), 0.1, 0.1);
+    #[test]
+    fn parse_defaults() {
+        let options = parse(&[]).unwrap().unwrap();
+        assert_eq!(options.scene, PathBuf::from("scenes/default.scene"));
+        assert_eq!(options.outputs(false), vec![PathBuf::from("image.png")]);
+        assert_eq!(options.outputs(true), vec![PathBuf::from("frame_####.png")]);
+        assert_eq!(options.samples, None);
+        assert_eq!(options.threads, 0);
+    }
 
-    let mut rng = rand::rngs::SmallRng::from_entropy();
+    #[test]
+    fn parse_options() {
+        let options = parse(&[
+            "room.scene",
+            "-o",
+            "room.png",
+            "--output=room.exr",
+            "--frames",
+            "10-20",
+            "--width=640",
+            "--height",
+            "480",
+            "-s",
+            "16",
+            "--adaptive",
+            "0.05",
+            "--min-samples=4",
+            "--seed",
+            "239",
+            "--sampler",
+            "sobol",
+            "--filter",
+            "mitchell",
+            "--filter-radius=1.5",
+            "-j",
+            "4",
+            "--eye",
+            "1,2,3",
+            "--fov",
+            "60",
+            "--projection",
+            "orthographic",
+            "--ortho-width=2.5",
+            "--aperture",
+            "0.1",
+            "--focus-distance=3",
+            "--shutter",
+            "0,0.5",
+            "--exposure",
+            "-1.5",
+            "--tonemap",
+            "aces",
+        ])
+        .unwrap()
+        .unwrap();
+        assert_eq!(options.scene, PathBuf::from("room.scene"));
+        assert_eq!(
+            options.outputs(false),
+            vec![PathBuf::from("room.png"), PathBuf::from("room.exr")]
+        );
+        assert_eq!(options.frames, Some((10, 20)));
+        assert_eq!(options.width, Some(640));
+        assert_eq!(options.height, Some(480));
+        assert_eq!(options.samples, Some(16));
+        assert_eq!(options.adaptive_threshold, Some(0.05));
+        assert_eq!(options.min_samples, Some(4));
+        assert_eq!(options.seed, Some(239));
+        assert_eq!(options.sampler, SamplerType::Sobol);
+        assert_eq!(options.filter, Some(FilterType::Mitchell));
+        assert_eq!(options.filter_radius, Some(1.5));
+        assert_eq!(options.threads, 4);
+        assert_eq!(options.eye, Some(Vec3::new(1., 2., 3.)));
+        assert_eq!(options.fov, Some(60.));
+        assert_eq!(
+            options.projection,
+            Some(Projection::Orthographic { width: 2.5 })
+        );
+        assert_eq!(options.aperture_radius, Some(0.1));
+        assert_eq!(options.focus_distance, Some(3.));
+        assert_eq!(options.shutter, Some((0., 0.5)));
+        assert_eq!(
+            options.tone_mapping,
+            ToneMapping::new()
+                .set_exposure(-1.5)
+                .set_operator(ToneMapOperator::AcesFilmic)
+        );
+    }
 
-    let camera = Camera::new().set_dimensions(1280, 720);
-    // .set_dimensions(16, 16);
-    let image = camera.render(&scene, &mut rng);
+    #[test]
+    fn parse_errors() {
+        assert!(parse(&["--help"]).unwrap().is_none());
+        assert_eq!(
+            parse(&["--width", "0"]).err().unwrap(),
+            "'--width' must be positive"
+        );
+        assert_eq!(
+            parse(&["--adaptive", "-0.5"]).err().unwrap(),
+            "'--adaptive' must not be negative"
+        );
+        assert_eq!(
+            parse(&["--adaptive", "NaN"]).err().unwrap(),
+            "'--adaptive' must not be negative"
+        );
+        assert_eq!(
+            parse(&["--filter-radius", "inf"]).err().unwrap(),
+            "'--filter-radius' must be positive"
+        );
+        assert_eq!(
+            parse(&["--shutter", "0,inf"]).err().unwrap(),
+            "'--shutter' must be finite"
+        );
+        assert_eq!(
+            parse(&["--samples"]).err().unwrap(),
+            "missing value for '--samples'"
+        );
+        assert_eq!(
+            parse(&["--eye", "1,2"]).err().unwrap(),
+            "'--eye' requires three comma-separated numbers"
+        );
+        assert_eq!(
+            parse(&["--frames", "3-2"]).err().unwrap(),
+            "'--frames' requires a range of frames from 1 on"
+        );
+        assert_eq!(
+            parse(&["a.scene", "b.scene"]).err().unwrap(),
+            "unexpected argument 'b.scene'"
+        );
+        assert_eq!(
+            parse(&["--zoom"]).err().unwrap(),
+            "unknown option '--zoom'"
+        );
+    }
 
-    image.save("image.png").unwrap();
+    #[test]
+    fn frame_paths() {
+        let path = |pattern: &str, frame| frame_path(Path::new(pattern), frame);
+        assert_eq!(
+            path("out/frame_####.png", 7),
+            Some(PathBuf::from("out/frame_0007.png"))
+        );
+        assert_eq!(path("#_##.exr", 123), Some(PathBuf::from("#_123.exr")));
+        assert_eq!(
+            path("shot#2/#.png", 12),
+            Some(PathBuf::from("shot#2/12.png"))
+        );
+        assert_eq!(path("image.png", 1), None);
+    }
 }
@@ -1,21 +1,93 @@
-#[derive(Clone, Copy, Debug)]
+use glam::{Vec2, Vec3};
+use std::sync::Arc;
+
+use crate::sampling::orthonormal_basis;
+use crate::shape::Intersection;
+use crate::texture::Texture;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Color([f32; 3]);
 
 impl Color {
+    pub fn new(r: f32, g: f32, b: f32) -> Self {
+        Color([r, g, b])
+    }
+
     pub fn black() -> Self {
         Color([0., 0., 0.])
     }
+
+    /// Linear sRGB color of a black body at the given temperature in kelvins, normalized to unit
+    /// luminance. Colors outside of the sRGB gamut are clipped. Black if the temperature is too
+    /// low to emit visible light in floating point.
+    pub fn blackbody(temperature: f32) -> Self {
+        // Second radiation constant hc/k in nm K.
+        const C2: f64 = 1.438_777E7;
+        let temperature = temperature as f64;
+        let (mut x, mut y, mut z) = (0., 0., 0.);
+        for lambda in (380..=780).step_by(5).map(|lambda| lambda as f64) {
+            // Planck's law up to a constant factor.
+            let radiance = 1. / (lambda.powi(5) * ((C2 / (lambda * temperature)).exp() - 1.));
+            let [cie_x, cie_y, cie_z] = cie_color_matching(lambda);
+            x += radiance * cie_x;
+            y += radiance * cie_y;
+            z += radiance * cie_z;
+        }
+        // Below about 26 K the radiance underflows at all visible wavelengths.
+        if y.is_nan() || y <= 0. {
+            return Color::black();
+        }
+        Color::from_xyz((x / y) as f32, 1., (z / y) as f32)
+    }
+
+    /// Linear sRGB color with the CIE XYZ tristimulus values. Colors outside of the sRGB gamut
+    /// are clipped.
+    pub fn from_xyz(x: f32, y: f32, z: f32) -> Self {
+        let to_srgb = |r: f32, g: f32, b: f32| (r * x + g * y + b * z).max(0.);
+        Color([
+            to_srgb(3.2406, -1.5372, -0.4986),
+            to_srgb(-0.9689, 1.8758, 0.0415),
+            to_srgb(0.0557, -0.2040, 1.0570),
+        ])
+    }
+
+    pub fn to_array(self) -> [f32; 3] {
+        self.0
+    }
+
+    /// Relative luminance of a linear sRGB color.
+    pub fn luminance(&self) -> f32 {
+        0.2126 * self.0[0] + 0.7152 * self.0[1] + 0.0722 * self.0[2]
+    }
+
+    pub fn max_component(&self) -> f32 {
+        self.0[0].max(self.0[1]).max(self.0[2])
+    }
+}
+
+/// Analytic approximation of the CIE 1931 color matching functions from Wyman, Sloan and Shirley,
+/// "Simple Analytic Approximations to the CIE XYZ Color Matching Functions", JCGT 2013.
+fn cie_color_matching(lambda: f64) -> [f64; 3] {
+    let lobe = |mean: f64, sigma_below: f64, sigma_above: f64| {
+        let sigma = if lambda < mean {
+            sigma_below
+        } else {
+            sigma_above
+        };
+        (-0.5 * ((lambda - mean) / sigma).powi(2)).exp()
+    };
+    [
+        1.056 * lobe(599.8, 37.9, 31.0) + 0.362 * lobe(442.0, 16.0, 26.7)
+            - 0.065 * lobe(501.1, 20.4, 26.2),
+        0.821 * lobe(568.8, 46.9, 40.5) + 0.286 * lobe(530.9, 16.3, 31.1),
+        1.217 * lobe(437.0, 11.8, 36.0) + 0.681 * lobe(459.0, 26.0, 13.8),
+    ]
 }
 
-impl Into<image::Rgb<u8>> for Color {
-    fn into(self) -> image::Rgb<u8> {
-        let rgb = self.0;
-        let rgb_bytes = [
-            (rgb[0] * 256.).max(0.).min(255.) as u8,
-            (rgb[1] * 256.).max(0.).min(255.) as u8,
-            (rgb[2] * 256.).max(0.).min(255.) as u8,
-        ];
-        rgb_bytes.into()
+/// Grey color with all components equal.
+impl From<f32> for Color {
+    fn from(value: f32) -> Self {
+        Color([value, value, value])
     }
 }
 
@@ -28,6 +100,16 @@ impl std::ops::Mul<f32> for Color {
     }
 }
 
+impl std::ops::Mul for Color {
+    type Output = Color;
+
+    fn mul(self, other: Color) -> Color {
+        let [r, g, b] = self.0;
+        let [or, og, ob] = other.0;
+        Color([r * or, g * og, b * ob])
+    }
+}
+
 impl std::ops::Add for Color {
     type Output = Color;
 
@@ -47,12 +129,46 @@ impl std::ops::AddAssign for Color {
     }
 }
 
-#[derive(Clone, Copy)]
+/// How the light interacts with the surface.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Surface {
+    /// Diffuse surface with a highlight from the lights, controlled by `Material::diffusion`,
+    /// `Material::reflection` and `Material::shininess`.
+    Diffuse,
+    /// Mirror-like reflection tinted by the material color. Roughness 0 gives a perfect mirror,
+    /// larger values blur the reflection.
+    Metal { roughness: f32 },
+    /// Transparent material like glass or water with the given index of refraction. The material
+    /// color tints the light passing through the surface.
+    Dielectric { ior: f32 },
+}
+
+/// Textures that vary the parameters of a material or perturb its normal. The color is replaced
+/// by the texture, scalar parameters are multiplied by the luminance of the texture.
+#[derive(Clone, Default)]
+pub struct MaterialTextures {
+    pub color: Option<Arc<dyn Texture>>,
+    pub diffusion: Option<Arc<dyn Texture>>,
+    pub reflection: Option<Arc<dyn Texture>>,
+    pub shininess: Option<Arc<dyn Texture>>,
+    /// Roughness of a metal surface.
+    pub roughness: Option<Arc<dyn Texture>>,
+    /// Normals in tangent space, with the red, green and blue channels mapped from [0, 1] to
+    /// [-1, 1] along the tangent, the bitangent and the normal.
+    pub normal_map: Option<Arc<dyn Texture>>,
+    /// Heights above the surface, multiplied by `Material::bump_scale`.
+    pub bump_map: Option<Arc<dyn Texture>>,
+}
+
+#[derive(Clone)]
 pub struct Material {
     pub color: Color,
     pub diffusion: f32,
     pub reflection: f32,
     pub shininess: f32,
+    pub surface: Surface,
+    pub textures: MaterialTextures,
+    pub bump_scale: f32,
 }
 
 impl Material {
@@ -62,6 +178,300 @@ impl Material {
             diffusion: 1.0,
             reflection: 3.0,
             shininess: 10.0,
+            surface: Surface::Diffuse,
+            textures: MaterialTextures::default(),
+            bump_scale: 1.,
+        }
+    }
+
+    pub fn metal(r: f32, g: f32, b: f32, roughness: f32) -> Self {
+        Material {
+            surface: Surface::Metal { roughness },
+            ..Material::new(r, g, b)
+        }
+    }
+
+    pub fn dielectric(ior: f32) -> Self {
+        Material {
+            surface: Surface::Dielectric { ior },
+            ..Material::new(1., 1., 1.)
+        }
+    }
+
+    /// The material at a surface point with the texture coordinates `uv`, the position `point`
+    /// and the geometric normal `normal`, with the parameters varied by the textures.
+    pub fn at(&self, uv: Vec2, point: Vec3, normal: Vec3) -> Material {
+        let textures = &self.textures;
+        let scalar = |texture: &Option<Arc<dyn Texture>>, value: f32| match texture {
+            Some(texture) => texture.evaluate_scalar(uv, point, normal) * value,
+            None => value,
+        };
+        let surface = match self.surface {
+            Surface::Metal { roughness } => Surface::Metal {
+                roughness: scalar(&textures.roughness, roughness),
+            },
+            surface => surface,
+        };
+        Material {
+            color: match textures.color {
+                Some(ref texture) => texture.evaluate(uv, point, normal),
+                None => self.color,
+            },
+            diffusion: scalar(&textures.diffusion, self.diffusion),
+            reflection: scalar(&textures.reflection, self.reflection),
+            shininess: scalar(&textures.shininess, self.shininess),
+            surface,
+            textures: MaterialTextures::default(),
+            bump_scale: self.bump_scale,
+        }
+    }
+
+    /// The normal used for shading at an intersection at `point`, perturbed by the normal and
+    /// bump maps.
+    pub fn shading_normal(&self, intersection: &Intersection, point: Vec3) -> Vec3 {
+        let textures = &self.textures;
+        if textures.normal_map.is_none() && textures.bump_map.is_none() {
+            return intersection.normal;
+        }
+        let (uv, geometric_normal) = (intersection.uv, intersection.geometric_normal);
+        let mut normal = intersection.normal;
+        let (mut tangent, mut bitangent) = tangent_frame(intersection, normal);
+        if let Some(ref normal_map) = textures.normal_map {
+            let [x, y, z] = normal_map.evaluate(uv, point, geometric_normal).to_array();
+            let local = Vec3::new(x, y, z) * 2. - Vec3::one();
+            normal = (tangent * local.x + bitangent * local.y + normal * local.z).normalize();
+            let frame = tangent_frame(intersection, normal);
+            tangent = frame.0;
+            bitangent = frame.1;
         }
+        if let Some(ref bump_map) = textures.bump_map {
+            // Forward differences of the height over a small step in the texture coordinates,
+            // moving the point along the surface to match. The slopes are per unit length, so the
+            // differences are divided by the distance that the point moves.
+            let (dpdu, dpdv) =
+                if intersection.tangent == Vec3::zero() || intersection.bitangent == Vec3::zero() {
+                    (tangent, bitangent)
+                } else {
+                    (intersection.tangent, intersection.bitangent)
+                };
+            let height = |du: f32, dv: f32| {
+                let point = point + dpdu * du + dpdv * dv;
+                let uv = uv + Vec2::new(du, dv);
+                bump_map.evaluate_scalar(uv, point, geometric_normal) * self.bump_scale
+            };
+            let height0 = height(0., 0.);
+            let slope_u = (height(BUMP_STEP, 0.) - height0) / (BUMP_STEP * dpdu.length());
+            let slope_v = (height(0., BUMP_STEP) - height0) / (BUMP_STEP * dpdv.length());
+            normal = (normal - tangent * slope_u - bitangent * slope_v).normalize();
+        }
+        normal
+    }
+}
+
+const BUMP_STEP: f32 = 1E-3;
+
+/// Unit tangent and bitangent of the intersection, made perpendicular to `normal`.
+fn tangent_frame(intersection: &Intersection, normal: Vec3) -> (Vec3, Vec3) {
+    let tangent = intersection.tangent - normal * normal.dot(intersection.tangent);
+    if tangent.length_squared() < 1E-12 {
+        return orthonormal_basis(normal);
+    }
+    let tangent = tangent.normalize();
+    let bitangent = normal.cross(tangent);
+    // Keep the handedness of the texture coordinates.
+    if bitangent.dot(intersection.bitangent) < 0. {
+        (tangent, -bitangent)
+    } else {
+        (tangent, bitangent)
+    }
+}
+
+/// Mirror reflection of the direction `dir` from a surface with the given normal.
+pub fn reflect(dir: Vec3, normal: Vec3) -> Vec3 {
+    dir - normal * (2. * dir.dot(normal))
+}
+
+/// Direction of the ray refracted according to Snell's law. `normal` faces against `dir`, `eta`
+/// is the ratio of the index of refraction on the side of the incoming ray to the index on the
+/// other side. Returns `None` in case of total internal reflection.
+pub fn refract(dir: Vec3, normal: Vec3, eta: f32) -> Option<Vec3> {
+    let cos_i = -dir.dot(normal);
+    let sin2_t = eta * eta * (1. - cos_i * cos_i);
+    if sin2_t >= 1. {
+        return None;
+    }
+    let cos_t = (1. - sin2_t).sqrt();
+    Some((dir * eta + normal * (eta * cos_i - cos_t)).normalize())
+}
+
+/// Schlick's approximation of the Fresnel reflectance for a ray hitting the surface at the
+/// angle with the given cosine. `eta` is the same as for `refract`.
+pub fn schlick_reflectance(cos_i: f32, eta: f32) -> f32 {
+    let r0 = ((1. - eta) / (1. + eta)).powi(2);
+    // When going into a less dense medium, the approximation uses the angle of the refracted ray.
+    let cos = if eta > 1. {
+        let sin2_t = eta * eta * (1. - cos_i * cos_i);
+        if sin2_t >= 1. {
+            return 1.;
+        }
+        (1. - sin2_t).sqrt()
+    } else {
+        cos_i
+    };
+    r0 + (1. - r0) * (1. - cos).powi(5)
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::vec3;
+    use std::f32::consts::PI;
+
+    use super::*;
+    use crate::framebuffer::Framebuffer;
+    use crate::ray::Ray;
+    use crate::shape::Shape;
+    use crate::sphere::Sphere;
+    use crate::texture::ImageTexture;
+
+    #[test]
+    fn blackbody_colors() {
+        // D65, the sRGB white point, is close to a black body at 6504 K.
+        let [r, g, b] = Color::blackbody(6504.).to_array();
+        for component in [r, g, b].iter() {
+            assert!((component - 1.).abs() < 0.05);
+        }
+
+        let [r, g, b] = Color::blackbody(3000.).to_array();
+        assert!(r > g && g > b);
+        let [r, g, b] = Color::blackbody(10000.).to_array();
+        assert!(b > g && g > r);
+
+        assert_eq!(Color::blackbody(10.), Color::black());
+        assert_eq!(Color::blackbody(0.), Color::black());
+        assert_eq!(Color::blackbody(f32::NAN), Color::black());
+    }
+
+    #[test]
+    fn textured_material() {
+        let mut image = Framebuffer::new(1, 1);
+        image.put_pixel(0, 0, Color::from(0.25));
+        let texture: Arc<dyn Texture> = Arc::new(ImageTexture::new(image));
+        let mut material = Material {
+            surface: Surface::Metal { roughness: 0.5 },
+            ..Material::new(1., 0., 0.)
+        };
+        material.textures.roughness = Some(texture.clone());
+        material.textures.shininess = Some(texture);
+
+        let textured = material.at(Vec2::zero(), Vec3::zero(), Vec3::unit_y());
+        assert_eq!(textured.surface, Surface::Metal { roughness: 0.125 });
+        assert_eq!(textured.shininess, 2.5);
+        assert_eq!(textured.color, Color::new(1., 0., 0.));
+        assert!(textured.textures.roughness.is_none());
+    }
+
+    /// Texture with the x coordinate of the point as its value.
+    struct Ramp;
+
+    impl Texture for Ramp {
+        fn evaluate(&self, _uv: Vec2, point: Vec3, _normal: Vec3) -> Color {
+            Color::from(point.x)
+        }
+    }
+
+    fn floor_intersection() -> Intersection {
+        Intersection::new(1., Vec3::unit_y()).set_tangents(Vec3::unit_x(), -Vec3::unit_z())
+    }
+
+    #[test]
+    fn normal_mapping() {
+        let mut image = Framebuffer::new(1, 1);
+        image.put_pixel(0, 0, Color::new(1., 0.5, 1.));
+        let mut material = Material::new(1., 1., 1.);
+        material.textures.normal_map = Some(Arc::new(ImageTexture::new(image)));
+        let normal = material.shading_normal(&floor_intersection(), Vec3::zero());
+        assert!((normal - vec3(1., 1., 0.).normalize()).length() < 1E-6);
+    }
+
+    #[test]
+    fn bump_mapping() {
+        let mut material = Material::new(1., 1., 1.);
+        assert_eq!(
+            material.shading_normal(&floor_intersection(), Vec3::zero()),
+            Vec3::unit_y()
+        );
+        // The height rises along x with the slope 0.5, so the normal tilts towards -x.
+        material.textures.bump_map = Some(Arc::new(Ramp));
+        material.bump_scale = 0.5;
+        let normal = material.shading_normal(&floor_intersection(), vec3(2., 0., 3.));
+        assert!((normal - vec3(-0.5, 1., 0.).normalize()).length() < 1E-3);
+    }
+
+    /// Texture with the u coordinate as its value.
+    struct UvRamp;
+
+    impl Texture for UvRamp {
+        fn evaluate(&self, uv: Vec2, _point: Vec3, _normal: Vec3) -> Color {
+            Color::from(uv.x)
+        }
+    }
+
+    #[test]
+    fn sphere_bump_mapping() {
+        // The height rises by pi once around the unit sphere, i.e. with the slope 0.5 at the
+        // equator and twice as steep at 60° latitude, where the circle around the y axis is half
+        // as long.
+        let mut material = Material::new(1., 1., 1.);
+        material.textures.bump_map = Some(Arc::new(UvRamp));
+        material.bump_scale = PI;
+        let sphere = Sphere::new(Vec3::zero(), 1.);
+        for &(latitude, slope) in [(0f32, 0.5), (60f32.to_radians(), 1.)].iter() {
+            let normal = vec3(0., latitude.sin(), latitude.cos());
+            let intersection = sphere.ray_intersect(&Ray::new(normal * 2., -normal));
+            let tangent = Vec3::unit_x();
+            let shading_normal = material.shading_normal(&intersection, normal);
+            let expected = (normal - tangent * slope).normalize();
+            assert!((shading_normal - expected).length() < 1E-3);
+        }
+    }
+
+    #[test]
+    fn reflect_dir() {
+        let dir = vec3(1., -1., 0.).normalize();
+        let reflected = reflect(dir, Vec3::unit_y());
+        assert!((reflected - vec3(1., 1., 0.).normalize()).length() < 1E-6);
+    }
+
+    #[test]
+    fn refract_snell() {
+        let normal = Vec3::unit_y();
+        let dir = vec3(1., -1., 0.).normalize();
+        let refracted = refract(dir, normal, 1. / 1.5).unwrap();
+        let sin_i = dir.cross(-normal).length();
+        let sin_t = refracted.cross(-normal).length();
+        assert_relative_eq!(sin_i, 1.5 * sin_t, epsilon = 1E-6);
+        assert!(refracted.y < 0.);
+
+        // Straight through at normal incidence.
+        let refracted = refract(-normal, normal, 1.5).unwrap();
+        assert!((refracted + normal).length() < 1E-6);
+    }
+
+    #[test]
+    fn total_internal_reflection() {
+        let normal = Vec3::unit_y();
+        // The critical angle for glass to air is about 41.8 degrees.
+        let steep = vec3(0.6, -1., 0.).normalize();
+        let shallow = vec3(1., -1., 0.).normalize();
+        assert!(refract(steep, normal, 1.5).is_some());
+        assert!(refract(shallow, normal, 1.5).is_none());
+        assert_eq!(schlick_reflectance(-shallow.dot(normal), 1.5), 1.);
+    }
+
+    #[test]
+    fn schlick() {
+        assert_relative_eq!(schlick_reflectance(1., 1. / 1.5), 0.04, epsilon = 1E-6);
+        assert_relative_eq!(schlick_reflectance(1., 1.5), 0.04, epsilon = 1E-6);
+        assert_relative_eq!(schlick_reflectance(0., 1. / 1.5), 1.);
     }
 }
@@ -0,0 +1,294 @@
+use glam::{Mat4, Vec2, Vec3};
+
+use crate::bvh::{Aabb, Bvh};
+use crate::ray::Ray;
+use crate::shape::*;
+use crate::triangle::intersect_triangle;
+
+/// A mesh of triangles sharing a vertex buffer. Each triangle is a triple of indices into the
+/// vertex buffer. With per-vertex normals the mesh is smooth-shaded by interpolating the normals
+/// across the triangles.
+pub struct TriangleMesh {
+    vertices: Vec<Vec3>,
+    indices: Vec<[u32; 3]>,
+    normals: Option<Vec<Vec3>>,
+    uvs: Option<Vec<Vec2>>,
+    bvh: Bvh,
+}
+
+impl TriangleMesh {
+    pub fn new(vertices: Vec<Vec3>, indices: Vec<[u32; 3]>) -> Self {
+        assert!(indices
+            .iter()
+            .flatten()
+            .all(|&index| (index as usize) < vertices.len()));
+        let bvh = build_bvh(&vertices, &indices);
+        TriangleMesh {
+            vertices,
+            indices,
+            normals: None,
+            uvs: None,
+            bvh,
+        }
+    }
+
+    /// Sets the per-vertex normals. There should be exactly one normal per vertex.
+    pub fn set_normals(mut self, normals: Vec<Vec3>) -> Self {
+        assert_eq!(normals.len(), self.vertices.len());
+        self.normals = Some(normals.into_iter().map(|n| n.normalize()).collect());
+        self
+    }
+
+    /// Sets the per-vertex texture coordinates. There should be exactly one pair per vertex.
+    pub fn set_uvs(mut self, uvs: Vec<Vec2>) -> Self {
+        assert_eq!(uvs.len(), self.vertices.len());
+        self.uvs = Some(uvs);
+        self
+    }
+
+    /// Applies an affine transformation to the vertices and the normals.
+    pub fn transform(mut self, matrix: Mat4) -> Self {
+        for vertex in self.vertices.iter_mut() {
+            *vertex = matrix.transform_point3(*vertex);
+        }
+        if let Some(ref mut normals) = self.normals {
+            let normal_matrix = matrix.inverse().transpose();
+            for normal in normals.iter_mut() {
+                *normal = normal_matrix.transform_vector3(*normal).normalize();
+            }
+        }
+        self.bvh = build_bvh(&self.vertices, &self.indices);
+        self
+    }
+
+    /// Calculates per-vertex normals as the averages of the normals of the adjacent triangles,
+    /// weighted by the angles of the triangles at the vertex.
+    pub fn compute_normals(self) -> Self {
+        let mut normals = vec![Vec3::zero(); self.vertices.len()];
+        for triangle in self.indices.iter() {
+            let vertices = self.triangle_vertices(triangle);
+            let normal = (vertices[1] - vertices[0])
+                .cross(vertices[2] - vertices[0])
+                .normalize();
+            if normal.is_nan().any() {
+                // Degenerate triangle.
+                continue;
+            }
+            for corner in 0..3 {
+                let vertex = vertices[corner];
+                let angle = (vertices[(corner + 1) % 3] - vertex)
+                    .angle_between(vertices[(corner + 2) % 3] - vertex);
+                normals[triangle[corner] as usize] += normal * angle;
+            }
+        }
+        self.set_normals(normals)
+    }
+
+    pub fn vertices(&self) -> &[Vec3] {
+        &self.vertices
+    }
+
+    pub fn indices(&self) -> &[[u32; 3]] {
+        &self.indices
+    }
+
+    pub fn normals(&self) -> Option<&[Vec3]> {
+        self.normals.as_deref()
+    }
+
+    pub fn uvs(&self) -> Option<&[Vec2]> {
+        self.uvs.as_deref()
+    }
+
+    fn triangle_vertices(&self, triangle: &[u32; 3]) -> [Vec3; 3] {
+        [
+            self.vertices[triangle[0] as usize],
+            self.vertices[triangle[1] as usize],
+            self.vertices[triangle[2] as usize],
+        ]
+    }
+
+    /// Texture coordinates at the point with the given barycentric coordinates. Without
+    /// per-vertex texture coordinates every triangle is mapped like a single `Triangle`.
+    fn uv_at(&self, triangle: &[u32; 3], barycentric: Vec3) -> Vec2 {
+        match self.uvs {
+            Some(ref uvs) => {
+                uvs[triangle[0] as usize] * barycentric.x
+                    + uvs[triangle[1] as usize] * barycentric.y
+                    + uvs[triangle[2] as usize] * barycentric.z
+            }
+            None => Vec2::new(barycentric.y, barycentric.z),
+        }
+    }
+
+    /// Directions in which the texture coordinates grow along a triangle.
+    fn tangents(&self, triangle: &[u32; 3]) -> (Vec3, Vec3) {
+        let [v0, v1, v2] = self.triangle_vertices(triangle);
+        let (edge1, edge2) = (v1 - v0, v2 - v0);
+        let uvs = match self.uvs {
+            Some(ref uvs) => uvs,
+            None => return (edge1, edge2),
+        };
+        let uv0 = uvs[triangle[0] as usize];
+        let (duv1, duv2) = (
+            uvs[triangle[1] as usize] - uv0,
+            uvs[triangle[2] as usize] - uv0,
+        );
+        let det = duv1.x * duv2.y - duv2.x * duv1.y;
+        if det.abs() < 1E-12 {
+            // Degenerate texture coordinates.
+            return (Vec3::zero(), Vec3::zero());
+        }
+        (
+            (edge1 * duv2.y - edge2 * duv1.y) / det,
+            (edge2 * duv1.x - edge1 * duv2.x) / det,
+        )
+    }
+
+    fn face_normal(&self, triangle: &[u32; 3]) -> Vec3 {
+        let [v0, v1, v2] = self.triangle_vertices(triangle);
+        (v1 - v0).cross(v2 - v0).normalize()
+    }
+
+    /// Normal at the point with the given barycentric coordinates.
+    fn normal_at(&self, triangle: &[u32; 3], barycentric: Vec3) -> Vec3 {
+        let face_normal = self.face_normal(triangle);
+        let normals = match self.normals {
+            Some(ref normals) => normals,
+            None => return face_normal,
+        };
+
+        let normal = normals[triangle[0] as usize] * barycentric.x
+            + normals[triangle[1] as usize] * barycentric.y
+            + normals[triangle[2] as usize] * barycentric.z;
+        // Keep the interpolated normal on the same side as the geometry.
+        let normal = normal.normalize();
+        if normal.is_nan().any() {
+            face_normal
+        } else if normal.dot(face_normal) < 0. {
+            -normal
+        } else {
+            normal
+        }
+    }
+}
+
+impl Shape for TriangleMesh {
+    fn ray_intersect(&self, ray: &Ray) -> Intersection {
+        let intersect =
+            |triangle: &[u32; 3]| intersect_triangle(ray, &self.triangle_vertices(triangle));
+        let (intersection, index) = self.bvh.intersect(ray, |index| {
+            match intersect(&self.indices[index]) {
+                // The normal is only calculated for the nearest triangle.
+                Some((dist, _)) => Intersection::new(dist, Vec3::unit_z()),
+                None => Intersection::new_empty(),
+            }
+        });
+        if !intersection.exists() {
+            return intersection;
+        }
+
+        let triangle = &self.indices[index];
+        let (_, barycentric) = intersect(triangle).unwrap();
+        let (tangent, bitangent) = self.tangents(triangle);
+        Intersection::new(intersection.dist, self.normal_at(triangle, barycentric))
+            .set_geometric_normal(self.face_normal(triangle))
+            .set_uv(self.uv_at(triangle, barycentric))
+            .set_tangents(tangent, bitangent)
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(self.bvh.bounds())
+    }
+}
+
+fn build_bvh(vertices: &[Vec3], indices: &[[u32; 3]]) -> Bvh {
+    let bounds: Vec<Aabb> = indices
+        .iter()
+        .map(|triangle| Aabb::from_points(triangle.iter().map(|&i| vertices[i as usize])))
+        .collect();
+    Bvh::build(&bounds)
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{vec2, vec3};
+
+    use super::*;
+
+    /// Two triangles forming a unit square at z = -1.
+    fn create_square() -> TriangleMesh {
+        TriangleMesh::new(
+            vec![
+                vec3(0., 0., -1.),
+                vec3(1., 0., -1.),
+                vec3(1., 1., -1.),
+                vec3(0., 1., -1.),
+            ],
+            vec![[0, 1, 2], [0, 2, 3]],
+        )
+    }
+
+    #[test]
+    fn mesh_ray_intersect() {
+        let mesh = create_square();
+        let intersection = mesh.ray_intersect(&Ray::new(vec3(0.25, 0.75, 0.), -Vec3::unit_z()));
+        assert_relative_eq!(intersection.dist, 1.);
+        assert!((intersection.normal - Vec3::unit_z()).length() < 1E-6);
+
+        let intersection = mesh.ray_intersect(&Ray::new(vec3(0.75, 0.25, 0.), -Vec3::unit_z()));
+        assert_relative_eq!(intersection.dist, 1.);
+
+        assert!(!mesh
+            .ray_intersect(&Ray::new(vec3(1.5, 0.5, 0.), -Vec3::unit_z()))
+            .exists());
+    }
+
+    #[test]
+    fn mesh_smooth_normals() {
+        let mesh = create_square().set_normals(vec![
+            vec3(-1., 0., 1.),
+            vec3(1., 0., 1.),
+            vec3(1., 0., 1.),
+            vec3(-1., 0., 1.),
+        ]);
+        let intersection = mesh.ray_intersect(&Ray::new(vec3(0.5, 0.5, 0.), -Vec3::unit_z()));
+        assert!((intersection.normal - Vec3::unit_z()).length() < 1E-6);
+
+        let intersection = mesh.ray_intersect(&Ray::new(vec3(1., 0.5, 0.), -Vec3::unit_z()));
+        assert!((intersection.normal - vec3(1., 0., 1.).normalize()).length() < 1E-6);
+        assert!((intersection.geometric_normal - Vec3::unit_z()).length() < 1E-6);
+    }
+
+    #[test]
+    fn mesh_uvs() {
+        let mesh =
+            create_square().set_uvs(vec![vec2(0., 0.), vec2(2., 0.), vec2(2., 2.), vec2(0., 2.)]);
+        let intersection = mesh.ray_intersect(&Ray::new(vec3(0.25, 0.75, 0.), -Vec3::unit_z()));
+        assert!((intersection.uv - vec2(0.5, 1.5)).length() < 1E-6);
+        let intersection = mesh.ray_intersect(&Ray::new(vec3(0.75, 0.25, 0.), -Vec3::unit_z()));
+        assert!((intersection.uv - vec2(1.5, 0.5)).length() < 1E-6);
+        assert!((intersection.tangent - vec3(0.5, 0., 0.)).length() < 1E-6);
+        assert!((intersection.bitangent - vec3(0., 0.5, 0.)).length() < 1E-6);
+    }
+
+    #[test]
+    fn mesh_compute_normals() {
+        // A roof made of two slopes meeting at x = 0.
+        let mesh = TriangleMesh::new(
+            vec![
+                vec3(-1., 0., 0.),
+                vec3(0., 1., 0.),
+                vec3(0., 1., -1.),
+                vec3(-1., 0., -1.),
+                vec3(1., 0., 0.),
+                vec3(1., 0., -1.),
+            ],
+            vec![[0, 1, 2], [0, 2, 3], [1, 4, 5], [1, 5, 2]],
+        )
+        .compute_normals();
+        let intersection = mesh.ray_intersect(&Ray::new(vec3(0., 2., -0.5), -Vec3::unit_y()));
+        assert_relative_eq!(intersection.dist, 1.);
+        assert!((intersection.normal - Vec3::unit_y()).length() < 1E-6);
+    }
+}
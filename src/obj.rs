@@ -0,0 +1,491 @@
+//! Loader for Wavefront OBJ meshes with MTL material libraries.
+//!
+//! Faces are split into one mesh per `usemtl` group. Polygons are triangulated as fans, so they
+//! should be convex. The MTL properties are mapped onto `Material`: `Kd` to `color`, `Ns` to
+//! `shininess` and the average of `Ks` to `reflection`. Other statements are ignored.
+
+use glam::{Vec2, Vec3};
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::material::{Color, Material};
+use crate::mesh::TriangleMesh;
+use crate::scene_file::{LoadError, ParseError};
+
+/// Loads the meshes from an OBJ file together with their materials. The material libraries are
+/// looked up relative to the OBJ file. Faces that don't belong to any `usemtl` group get
+/// `default_material`.
+pub fn load_obj(
+    path: impl AsRef<Path>,
+    default_material: Material,
+) -> Result<Vec<(TriangleMesh, Material)>, LoadError> {
+    let path = path.as_ref();
+    let text = read_file(path)?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut load_mtl = |name: &str| {
+        let mtl_path = dir.join(name);
+        let text = read_file(&mtl_path)?;
+        parse_mtl(&text).map_err(|err| LoadError::Parse(mtl_path, err))
+    };
+    parse_obj_with(&text, default_material, HashMap::new(), &mut load_mtl).map_err(
+        |err| match err {
+            ObjError::Parse(err) => LoadError::Parse(path.to_path_buf(), err),
+            ObjError::Mtl(err) => err,
+        },
+    )
+}
+
+/// Parses an OBJ file, taking the materials from `materials` instead of the libraries referenced
+/// by `mtllib`.
+pub fn parse_obj(
+    text: &str,
+    default_material: Material,
+    materials: &HashMap<String, Material>,
+) -> Result<Vec<(TriangleMesh, Material)>, ParseError> {
+    let mut load_mtl = |_: &str| Ok(HashMap::new());
+    parse_obj_with(text, default_material, materials.clone(), &mut load_mtl).map_err(
+        |err| match err {
+            ObjError::Parse(err) => err,
+            ObjError::Mtl(_) => unreachable!(),
+        },
+    )
+}
+
+/// Parses an MTL material library.
+pub fn parse_mtl(text: &str) -> Result<HashMap<String, Material>, ParseError> {
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, Material)> = None;
+
+    for (line_number, keyword, args) in statements(text) {
+        let args = &args[..];
+        match keyword {
+            "newmtl" => {
+                let name = match args {
+                    [name] => name.to_string(),
+                    _ => return Err(ParseError::new(line_number, "'newmtl' requires a name")),
+                };
+                if let Some((name, material)) = current.take() {
+                    materials.insert(name, material);
+                }
+                current = Some((name, Material::new(1., 1., 1.)));
+            }
+            "Kd" | "Ks" | "Ns" => {
+                let material = match current {
+                    Some((_, ref mut material)) => material,
+                    None => {
+                        return Err(ParseError::new(
+                            line_number,
+                            format!("'{}' before 'newmtl'", keyword),
+                        ))
+                    }
+                };
+                match keyword {
+                    "Kd" => {
+                        let [r, g, b] = parse_rgb(line_number, args)?;
+                        material.color = Color::new(r, g, b);
+                    }
+                    "Ks" => {
+                        let [r, g, b] = parse_rgb(line_number, args)?;
+                        material.reflection = (r + g + b) / 3.;
+                    }
+                    _ => material.shininess = parse_floats::<1>(line_number, args)?[0],
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if let Some((name, material)) = current {
+        materials.insert(name, material);
+    }
+    Ok(materials)
+}
+
+enum ObjError {
+    Parse(ParseError),
+    Mtl(LoadError),
+}
+
+impl From<ParseError> for ObjError {
+    fn from(err: ParseError) -> Self {
+        ObjError::Parse(err)
+    }
+}
+
+/// Reads the material library with the given name.
+type MtlLoader<'a> = dyn FnMut(&str) -> Result<HashMap<String, Material>, LoadError> + 'a;
+
+/// Indices of the position, texture coordinates and normal of a face vertex.
+type VertexKey = (usize, Option<usize>, Option<usize>);
+
+/// Faces sharing a material.
+struct Group {
+    material: Material,
+    vertex_ids: HashMap<VertexKey, u32>,
+    keys: Vec<VertexKey>,
+    triangles: Vec<[u32; 3]>,
+}
+
+impl Group {
+    fn new(material: Material) -> Self {
+        Group {
+            material,
+            vertex_ids: HashMap::new(),
+            keys: Vec::new(),
+            triangles: Vec::new(),
+        }
+    }
+
+    fn vertex_id(&mut self, key: VertexKey) -> u32 {
+        let keys = &mut self.keys;
+        *self.vertex_ids.entry(key).or_insert_with(|| {
+            keys.push(key);
+            (keys.len() - 1) as u32
+        })
+    }
+
+    fn into_mesh(
+        self,
+        positions: &[Vec3],
+        uvs: &[Vec2],
+        normals: &[Vec3],
+    ) -> (TriangleMesh, Material) {
+        let vertices = self.keys.iter().map(|key| positions[key.0]).collect();
+        let mut mesh = TriangleMesh::new(vertices, self.triangles);
+        // Normals and texture coordinates are only used if all the vertices have them.
+        if let Some(uv_ids) = self
+            .keys
+            .iter()
+            .map(|key| key.1)
+            .collect::<Option<Vec<_>>>()
+        {
+            mesh = mesh.set_uvs(uv_ids.iter().map(|&id| uvs[id]).collect());
+        }
+        if let Some(normal_ids) = self
+            .keys
+            .iter()
+            .map(|key| key.2)
+            .collect::<Option<Vec<_>>>()
+        {
+            mesh = mesh.set_normals(normal_ids.iter().map(|&id| normals[id]).collect());
+        }
+        (mesh, self.material)
+    }
+}
+
+fn parse_obj_with(
+    text: &str,
+    default_material: Material,
+    mut materials: HashMap<String, Material>,
+    load_mtl: &mut MtlLoader,
+) -> Result<Vec<(TriangleMesh, Material)>, ObjError> {
+    let mut positions = Vec::new();
+    let mut uvs = Vec::new();
+    let mut normals = Vec::new();
+    let mut groups = vec![Group::new(default_material)];
+
+    for (line_number, keyword, args) in statements(text) {
+        let args = &args[..];
+        match keyword {
+            "v" => {
+                if args.len() != 3 && args.len() != 4 {
+                    return Err(ParseError::new(line_number, "'v' requires 3 coordinates").into());
+                }
+                let [x, y, z] = parse_floats(line_number, &args[..3])?;
+                positions.push(Vec3::new(x, y, z));
+            }
+            "vt" => {
+                if args.is_empty() || args.len() > 3 {
+                    return Err(
+                        ParseError::new(line_number, "'vt' requires 1 to 3 coordinates").into(),
+                    );
+                }
+                let u = parse_floats::<1>(line_number, &args[..1])?[0];
+                let v = match args.get(1) {
+                    Some(v) => parse_floats::<1>(line_number, &[*v])?[0],
+                    None => 0.,
+                };
+                uvs.push(Vec2::new(u, v));
+            }
+            "vn" => {
+                let [x, y, z] = parse_floats(line_number, args)?;
+                normals.push(Vec3::new(x, y, z));
+            }
+            "f" => {
+                if args.len() < 3 {
+                    return Err(
+                        ParseError::new(line_number, "'f' requires at least 3 vertices").into(),
+                    );
+                }
+                let keys = args
+                    .iter()
+                    .map(|arg| {
+                        parse_face_vertex(
+                            line_number,
+                            arg,
+                            positions.len(),
+                            uvs.len(),
+                            normals.len(),
+                        )
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                let group = groups.last_mut().unwrap();
+                let ids: Vec<u32> = keys.into_iter().map(|key| group.vertex_id(key)).collect();
+                for i in 1..ids.len() - 1 {
+                    group.triangles.push([ids[0], ids[i], ids[i + 1]]);
+                }
+            }
+            "mtllib" => {
+                if args.is_empty() {
+                    return Err(
+                        ParseError::new(line_number, "'mtllib' requires a file name").into(),
+                    );
+                }
+                for name in args.iter() {
+                    materials.extend(load_mtl(name).map_err(ObjError::Mtl)?);
+                }
+            }
+            "usemtl" => {
+                let name = match args {
+                    [name] => name,
+                    _ => {
+                        return Err(ParseError::new(line_number, "'usemtl' requires a name").into())
+                    }
+                };
+                let material = match materials.get(*name) {
+                    Some(material) => material.clone(),
+                    None => {
+                        return Err(ParseError::new(
+                            line_number,
+                            format!("unknown material '{}'", name),
+                        )
+                        .into())
+                    }
+                };
+                groups.push(Group::new(material));
+            }
+            _ => {}
+        }
+    }
+
+    Ok(groups
+        .into_iter()
+        .filter(|group| !group.triangles.is_empty())
+        .map(|group| group.into_mesh(&positions, &uvs, &normals))
+        .collect())
+}
+
+/// Parses a face vertex of the form `v`, `v/vt`, `v//vn` or `v/vt/vn`. The indices are 1-based,
+/// negative indices count from the end of the list read so far.
+fn parse_face_vertex(
+    line: usize,
+    token: &str,
+    positions: usize,
+    uvs: usize,
+    normals: usize,
+) -> Result<VertexKey, ParseError> {
+    let parts: Vec<&str> = token.split('/').collect();
+    if parts.len() > 3 {
+        return Err(ParseError::new(
+            line,
+            format!("invalid face vertex '{}'", token),
+        ));
+    }
+    let position = parse_index(line, parts[0], positions)?;
+    let uv = match parts.get(1) {
+        None | Some(&"") => None,
+        Some(index) => Some(parse_index(line, index, uvs)?),
+    };
+    let normal = match parts.get(2) {
+        None => None,
+        Some(index) => Some(parse_index(line, index, normals)?),
+    };
+    Ok((position, uv, normal))
+}
+
+fn parse_index(line: usize, token: &str, count: usize) -> Result<usize, ParseError> {
+    let index: i64 = token
+        .parse()
+        .map_err(|_| ParseError::new(line, format!("invalid index '{}'", token)))?;
+    let resolved = if index < 0 {
+        count as i64 + index
+    } else {
+        index - 1
+    };
+    if resolved < 0 || resolved >= count as i64 {
+        return Err(ParseError::new(
+            line,
+            format!("index {} is out of range", index),
+        ));
+    }
+    Ok(resolved as usize)
+}
+
+fn parse_floats<const N: usize>(line: usize, args: &[&str]) -> Result<[f32; N], ParseError> {
+    if args.len() != N {
+        return Err(ParseError::new(line, format!("expected {} numbers", N)));
+    }
+    let mut values = [0.; N];
+    for (value, token) in values.iter_mut().zip(args) {
+        *value = token
+            .parse()
+            .map_err(|_| ParseError::new(line, format!("invalid number '{}'", token)))?;
+    }
+    Ok(values)
+}
+
+fn parse_rgb(line: usize, args: &[&str]) -> Result<[f32; 3], ParseError> {
+    // A single value is a shorthand for gray.
+    if args.len() == 1 {
+        let [v] = parse_floats(line, args)?;
+        return Ok([v, v, v]);
+    }
+    parse_floats(line, args)
+}
+
+/// Splits the text into statements, skipping comments and empty lines. Returns line numbers,
+/// keywords and arguments.
+fn statements(text: &str) -> impl Iterator<Item = (usize, &str, Vec<&str>)> {
+    text.lines().enumerate().filter_map(|(index, line)| {
+        let line = match line.find('#') {
+            Some(pos) => &line[..pos],
+            None => line,
+        };
+        let mut tokens = line.split_whitespace();
+        let keyword = tokens.next()?;
+        Some((index + 1, keyword, tokens.collect()))
+    })
+}
+
+fn read_file(path: &Path) -> Result<String, LoadError> {
+    std::fs::read_to_string(path).map_err(|err| LoadError::Io(path.to_path_buf(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::vec3;
+
+    use super::*;
+    use crate::ray::Ray;
+    use crate::shape::Shape;
+
+    fn parse(text: &str) -> Result<Vec<(TriangleMesh, Material)>, ParseError> {
+        let materials = parse_mtl(
+            "
+            newmtl red
+            Kd 0.8 0.1 0.1
+            Ks 0.5 0.5 0.2
+            Ns 20
+            newmtl white
+            Kd 1 1 1
+            ",
+        )
+        .unwrap();
+        parse_obj(text, Material::new(0.5, 0.5, 0.5), &materials)
+    }
+
+    #[test]
+    fn parse_mtl_properties() {
+        let materials = parse_mtl("newmtl red\nKd 0.8 0.1 0.1\nKs 0.5 0.5 0.2\nNs 20\n").unwrap();
+        let red = &materials["red"];
+        assert_eq!(red.color, Color::new(0.8, 0.1, 0.1));
+        assert_relative_eq!(red.reflection, 0.4);
+        assert_eq!(red.shininess, 20.);
+    }
+
+    #[test]
+    fn parse_quad() {
+        let meshes = parse(
+            "
+            # A unit square at z = -1.
+            v 0 0 -1
+            v 1 0 -1
+            v 1 1 -1
+            v 0 1 -1
+            f 1 2 3 4
+            ",
+        )
+        .unwrap();
+        assert_eq!(meshes.len(), 1);
+        let (mesh, material) = &meshes[0];
+        assert_eq!(mesh.indices(), &[[0, 1, 2], [0, 2, 3]]);
+        assert_eq!(material.color, Color::new(0.5, 0.5, 0.5));
+        assert!(mesh.normals().is_none());
+        assert!(mesh.uvs().is_none());
+
+        let intersection = mesh.ray_intersect(&Ray::new(vec3(0.25, 0.75, 0.), -Vec3::unit_z()));
+        assert_relative_eq!(intersection.dist, 1.);
+    }
+
+    #[test]
+    fn parse_groups() {
+        let meshes = parse(
+            "
+            v 0 0 0
+            v 1 0 0
+            v 0 1 0
+            v 1 1 0
+            vt 0 0
+            vt 1 0
+            vt 0 1
+            vn 0 0 1
+            usemtl red
+            f 1/1/1 2/2/1 3/3/1
+            usemtl white
+            f -3//-1 -1//-1 -2//-1
+            ",
+        )
+        .unwrap();
+        assert_eq!(meshes.len(), 2);
+
+        let (red_mesh, red) = &meshes[0];
+        assert_eq!(red.color, Color::new(0.8, 0.1, 0.1));
+        assert_eq!(red_mesh.vertices().len(), 3);
+        assert_eq!(red_mesh.uvs().unwrap()[1], Vec2::new(1., 0.));
+        assert_eq!(red_mesh.normals().unwrap()[0], Vec3::unit_z());
+
+        let (white_mesh, white) = &meshes[1];
+        assert_eq!(white.color, Color::new(1., 1., 1.));
+        assert_eq!(
+            white_mesh.vertices(),
+            &[vec3(1., 0., 0.), vec3(1., 1., 0.), vec3(0., 1., 0.)]
+        );
+        assert!(white_mesh.uvs().is_none());
+        assert!(white_mesh.normals().is_some());
+    }
+
+    fn parse_error(text: &str) -> ParseError {
+        match parse(text) {
+            Ok(_) => panic!("expected an error"),
+            Err(err) => err,
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(
+            parse_error("v 0 0 0\nv 1 0 x"),
+            ParseError::new(2, "invalid number 'x'")
+        );
+        assert_eq!(
+            parse_error("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 4"),
+            ParseError::new(4, "index 4 is out of range")
+        );
+        assert_eq!(
+            parse_error("v 0 0 0\nv 1 0 0\nf 1 2"),
+            ParseError::new(3, "'f' requires at least 3 vertices")
+        );
+        assert_eq!(
+            parse_error("\nusemtl blue"),
+            ParseError::new(2, "unknown material 'blue'")
+        );
+        assert_eq!(
+            parse_error("v 0 0 0\nf 1/1/1/1 1 1"),
+            ParseError::new(2, "invalid face vertex '1/1/1/1'")
+        );
+        match parse_mtl("Kd 1 1 1") {
+            Ok(_) => panic!("expected an error"),
+            Err(err) => assert_eq!(err, ParseError::new(1, "'Kd' before 'newmtl'")),
+        }
+    }
+}
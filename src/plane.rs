@@ -1,31 +1,42 @@
-use glam::Vec3;
+use glam::{Vec2, Vec3};
 
-use crate::defines::*;
+use crate::ray::Ray;
+use crate::sampling::orthonormal_basis;
 use crate::shape::*;
 
+/// An infinite plane. The texture coordinates are the coordinates of the point in the plane,
+/// measured from `point` along two perpendicular directions, so textures tile the plane.
 pub struct Plane {
     point: Vec3,
     normal: Vec3,
+    tangent: Vec3,
+    bitangent: Vec3,
 }
 
 impl Plane {
     pub fn new(point: Vec3, normal: Vec3) -> Self {
-        Plane { point, normal: normal.normalize() }
+        let normal = normal.normalize();
+        let (tangent, bitangent) = orthonormal_basis(normal);
+        Plane { point, normal, tangent, bitangent }
     }
 }
 
 impl Shape for Plane {
-    fn ray_intersect(&self, origin: Vec3, dir: Vec3) -> Intersection {
-        let dir_proj = dir.dot(self.normal);
-        if dir_proj > -EPSILON {
+    fn ray_intersect(&self, ray: &Ray) -> Intersection {
+        let dir_proj = ray.dir.dot(self.normal);
+        if dir_proj >= 0. {
             return Intersection::new_empty();
         }
-        let point_proj = self.normal.dot(self.point - origin);
+        let point_proj = self.normal.dot(self.point - ray.origin);
         let ratio = point_proj / dir_proj;
-        if ratio < EPSILON {
+        if !ray.contains(ratio) {
             return Intersection::new_empty();
         }
+        let offset = ray.at(ratio) - self.point;
+        let uv = Vec2::new(offset.dot(self.tangent), offset.dot(self.bitangent));
         Intersection::new(ratio, self.normal)
+            .set_uv(uv)
+            .set_tangents(self.tangent, self.bitangent)
     }
 }
 
@@ -38,29 +49,48 @@ mod tests {
     #[test]
 fn plane_ray_intersect1() {
     let plane = Plane::new(vec3(0., 0., 1.), vec3(0., 0., -1.));
-    let intersection = plane.ray_intersect(vec3(0., 0., 0.), vec3(0., 0., 1.));
+    let intersection = plane.ray_intersect(&Ray::new(vec3(0., 0., 0.), vec3(0., 0., 1.)));
     assert_eq!(intersection.dist, 1.)
 }
 
 #[test]
 fn plane_ray_intersect2() {
     let plane = Plane::new(vec3(0., 0., 1.), vec3(0., 0., -2.));
-    let intersection = plane.ray_intersect(vec3(0., 0., 0.), vec3(0., 0., 1.));
+    let intersection = plane.ray_intersect(&Ray::new(vec3(0., 0., 0.), vec3(0., 0., 1.)));
     assert_eq!(intersection.dist, 1.)
 }
 
 #[test]
 fn plane_ray_intersect3() {
     let plane = Plane::new(vec3(0., 0., 2.), vec3(0., 0., -1.));
-    let intersection = plane.ray_intersect(vec3(0., 0., 0.), vec3(0., 0., 1.));
+    let intersection = plane.ray_intersect(&Ray::new(vec3(0., 0., 0.), vec3(0., 0., 1.)));
     assert_eq!(intersection.dist, 2.)
 }
 
 #[test]
 fn plane_ray_intersect4() {
     let plane = Plane::new(vec3(0., -1., 0.), vec3(0., 1., 0.));
-    let intersection = plane.ray_intersect(vec3(0., 0., -1.), vec3(0., -0.5, 2.).normalize());
+    let ray = Ray::new(vec3(0., 0., -1.), vec3(0., -0.5, 2.).normalize());
+    let intersection = plane.ray_intersect(&ray);
     assert_relative_eq!(intersection.dist, 17f32.sqrt());
 }
 
+#[test]
+fn plane_grazing_ray() {
+    // Nearly parallel rays hit the plane far away, within the bounds of the ray.
+    let plane = Plane::new(vec3(0., -1., 0.), vec3(0., 1., 0.));
+    let ray = Ray::new(vec3(0., 0., 0.), vec3(0., -1E-7, -1.));
+    let intersection = plane.ray_intersect(&ray);
+    assert_relative_eq!(intersection.dist, 1E7, max_relative = 1E-3);
+    assert!(!plane.ray_intersect(&ray.set_tmax(1E6)).exists());
+}
+
+#[test]
+fn plane_uv() {
+    // A floor has u along +x and v along -z.
+    let plane = Plane::new(vec3(0., -1., 0.), vec3(0., 1., 0.));
+    let intersection = plane.ray_intersect(&Ray::new(vec3(2., 0., -3.), vec3(0., -1., 0.)));
+    assert_eq!(intersection.uv, Vec2::new(2., 3.));
+}
+
 }
\ No newline at end of file
@@ -0,0 +1,373 @@
+//! Procedural textures computed from the surface point instead of an image: checkerboards,
+//! gradient noise and the patterns built on it, and Voronoi cells.
+
+use glam::{Vec2, Vec3};
+use std::f32::consts::PI;
+use std::str::FromStr;
+
+use crate::animation::Interpolate;
+use crate::material::Color;
+use crate::texture::Texture;
+
+/// Hash of an integer lattice point, uniformly distributed over all 32-bit values.
+fn hash(x: i32, y: i32, z: i32) -> u32 {
+    let h = (x as u32).wrapping_mul(0x8da6_b343)
+        ^ (y as u32).wrapping_mul(0xd816_3841)
+        ^ (z as u32).wrapping_mul(0xcb1a_b31f);
+    mix(h)
+}
+
+/// The "lowbias32" integer finalizer by Chris Wellons.
+fn mix(mut h: u32) -> u32 {
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7feb_352d);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846c_a68b);
+    h ^ (h >> 16)
+}
+
+fn unit_float(h: u32) -> f32 {
+    (h >> 8) as f32 / (1 << 24) as f32
+}
+
+/// Dot product of `offset` with one of the 12 gradients pointing to the edges of a cube,
+/// chosen by `hash`, as in Perlin's improved noise.
+fn gradient(hash: u32, offset: Vec3) -> f32 {
+    let h = hash & 15;
+    let u = if h < 8 { offset.x } else { offset.y };
+    let v = match h {
+        0..=3 => offset.y,
+        12 | 14 => offset.x,
+        _ => offset.z,
+    };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+fn lerp(t: f32, a: f32, b: f32) -> f32 {
+    a + t * (b - a)
+}
+
+/// Perlin gradient noise, zero at the integer lattice points and varying smoothly in between,
+/// roughly in [-1, 1].
+pub fn perlin_noise(point: Vec3) -> f32 {
+    let cell = point.floor();
+    let (x, y, z) = (cell.x as i32, cell.y as i32, cell.z as i32);
+    let offset = point - cell;
+    let fade = |t: f32| t * t * t * (t * (t * 6. - 15.) + 10.);
+    let (u, v, w) = (fade(offset.x), fade(offset.y), fade(offset.z));
+    let corner = |dx: i32, dy: i32, dz: i32| {
+        let corner_offset = offset - Vec3::new(dx as f32, dy as f32, dz as f32);
+        gradient(hash(x + dx, y + dy, z + dz), corner_offset)
+    };
+    lerp(
+        w,
+        lerp(
+            v,
+            lerp(u, corner(0, 0, 0), corner(1, 0, 0)),
+            lerp(u, corner(0, 1, 0), corner(1, 1, 0)),
+        ),
+        lerp(
+            v,
+            lerp(u, corner(0, 0, 1), corner(1, 0, 1)),
+            lerp(u, corner(0, 1, 1), corner(1, 1, 1)),
+        ),
+    )
+}
+
+/// Fractional Brownian motion: `octaves` layers of noise, each with twice the frequency and half
+/// the amplitude of the previous one, normalized to the range of a single layer.
+pub fn fbm(point: Vec3, octaves: u32) -> f32 {
+    octave_sum(point, octaves, perlin_noise)
+}
+
+/// Like `fbm`, but summing the absolute values of the noise, in [0, 1].
+pub fn turbulence(point: Vec3, octaves: u32) -> f32 {
+    octave_sum(point, octaves, |point| perlin_noise(point).abs())
+}
+
+fn octave_sum(point: Vec3, octaves: u32, noise: impl Fn(Vec3) -> f32) -> f32 {
+    let mut sum = 0.;
+    let mut total_amplitude = 0.;
+    let (mut frequency, mut amplitude) = (1., 1.);
+    for _ in 0..octaves.max(1) {
+        sum += amplitude * noise(point * frequency);
+        total_amplitude += amplitude;
+        frequency *= 2.;
+        amplitude *= 0.5;
+    }
+    sum / total_amplitude
+}
+
+/// Worley's cellular noise: every unit cell holds a random feature point, and space is divided
+/// into the regions closest to each of them. Gives the distance to the closest feature point and
+/// a random value in [0, 1) that is constant over its region.
+pub fn voronoi(point: Vec3) -> (f32, f32) {
+    let cell = point.floor();
+    let (x, y, z) = (cell.x as i32, cell.y as i32, cell.z as i32);
+    let mut closest = (f32::INFINITY, 0);
+    for dz in -1..=1 {
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let h = hash(x + dx, y + dy, z + dz);
+                let feature = Vec3::new(
+                    (x + dx) as f32 + unit_float(h),
+                    (y + dy) as f32 + unit_float(mix(h ^ 1)),
+                    (z + dz) as f32 + unit_float(mix(h ^ 2)),
+                );
+                let dist = (feature - point).length();
+                if dist < closest.0 {
+                    closest = (dist, h);
+                }
+            }
+        }
+    }
+    (closest.0, unit_float(mix(closest.1 ^ 3)))
+}
+
+/// The pattern of a procedural texture, which blends between its two colors.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Pattern {
+    /// Alternating unit cubes of the two colors, or squares on surfaces that face an axis.
+    Checker,
+    Noise,
+    Fbm,
+    Turbulence,
+    /// Veins along the x axis, distorted by turbulence.
+    Marble,
+    /// Rings around the y axis, distorted by noise.
+    Wood,
+    /// Cells of random blends between the colors.
+    Voronoi,
+}
+
+impl FromStr for Pattern {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, String> {
+        match name {
+            "checker" => Ok(Pattern::Checker),
+            "noise" => Ok(Pattern::Noise),
+            "fbm" => Ok(Pattern::Fbm),
+            "turbulence" => Ok(Pattern::Turbulence),
+            "marble" => Ok(Pattern::Marble),
+            "wood" => Ok(Pattern::Wood),
+            "voronoi" => Ok(Pattern::Voronoi),
+            _ => Err(format!("unknown pattern '{}'", name)),
+        }
+    }
+}
+
+/// Where a procedural texture is evaluated.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextureSpace {
+    /// At the position of the surface point in the scene, carving the pattern out of a solid.
+    Point,
+    /// At the texture coordinates (u, v, 0).
+    Uv,
+}
+
+impl FromStr for TextureSpace {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, String> {
+        match name {
+            "point" => Ok(TextureSpace::Point),
+            "uv" => Ok(TextureSpace::Uv),
+            _ => Err(format!("unknown texture space '{}'", name)),
+        }
+    }
+}
+
+/// Cosine of the largest angle between the normal and an axis for which the checker pattern
+/// treats a surface as facing the axis.
+const ALIGNED_COS: f32 = 0.9999;
+
+pub struct ProceduralTexture {
+    pattern: Pattern,
+    colors: (Color, Color),
+    frequency: f32,
+    space: TextureSpace,
+    octaves: u32,
+}
+
+impl ProceduralTexture {
+    /// A pattern blending from `color0` to `color1`, evaluated at the surface point with unit
+    /// frequency and 4 octaves of noise.
+    pub fn new(pattern: Pattern, color0: Color, color1: Color) -> Self {
+        ProceduralTexture {
+            pattern,
+            colors: (color0, color1),
+            frequency: 1.,
+            space: TextureSpace::Point,
+            octaves: 4,
+        }
+    }
+
+    /// Frequency of the pattern, e.g. the number of checkerboard squares per unit.
+    pub fn set_frequency(mut self, frequency: f32) -> Self {
+        self.frequency = frequency;
+        self
+    }
+
+    pub fn set_space(mut self, space: TextureSpace) -> Self {
+        self.space = space;
+        self
+    }
+
+    /// Number of noise layers of the `fbm`, `turbulence` and `marble` patterns.
+    pub fn set_octaves(mut self, octaves: u32) -> Self {
+        assert!(octaves > 0);
+        self.octaves = octaves;
+        self
+    }
+
+    /// Blend factor between the two colors at a point of the pattern on a surface with the given
+    /// normal, in [0, 1].
+    fn blend(&self, p: Vec3, normal: Vec3) -> f32 {
+        let t = match self.pattern {
+            Pattern::Checker => {
+                // The coordinate along the normal of a surface facing an axis is constant, e.g.
+                // y on a floor. Rounding errors of the hit points would pick either cell if it
+                // fell on a cell boundary, so the axis is left out.
+                let aligned = normal.abs().cmpge(Vec3::splat(ALIGNED_COS));
+                let cell = aligned.select(Vec3::zero(), p.floor());
+                ((cell.x + cell.y + cell.z) as i64).rem_euclid(2) as f32
+            }
+            Pattern::Noise => 0.5 + 0.5 * perlin_noise(p),
+            Pattern::Fbm => 0.5 + 0.5 * fbm(p, self.octaves),
+            Pattern::Turbulence => turbulence(p, self.octaves),
+            Pattern::Marble => 0.5 + 0.5 * (PI * (p.x + 4. * turbulence(p, self.octaves))).sin(),
+            Pattern::Wood => {
+                let rings = (p.x * p.x + p.z * p.z).sqrt() + 0.5 * perlin_noise(p);
+                rings - rings.floor()
+            }
+            Pattern::Voronoi => voronoi(p).1,
+        };
+        t.clamp(0., 1.)
+    }
+}
+
+impl Texture for ProceduralTexture {
+    fn evaluate(&self, uv: Vec2, point: Vec3, normal: Vec3) -> Color {
+        let (p, normal) = match self.space {
+            TextureSpace::Point => (point, normal),
+            TextureSpace::Uv => (uv.extend(0.), Vec3::unit_z()),
+        };
+        let t = self.blend(p * self.frequency, normal);
+        self.colors.0.interpolate(self.colors.1, t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{vec2, vec3};
+
+    use super::*;
+    use crate::plane::Plane;
+    use crate::ray::Ray;
+    use crate::shape::Shape;
+
+    const POINT: Vec3 = Vec3 {
+        x: 0.3,
+        y: 1.7,
+        z: -2.2,
+    };
+
+    fn blend(pattern: Pattern, uv: Vec2, point: Vec3) -> f32 {
+        blend_on_surface(pattern, uv, point, Vec3::one().normalize())
+    }
+
+    fn blend_on_surface(pattern: Pattern, uv: Vec2, point: Vec3, normal: Vec3) -> f32 {
+        ProceduralTexture::new(pattern, Color::black(), Color::from(1.))
+            .evaluate(uv, point, normal)
+            .to_array()[0]
+    }
+
+    #[test]
+    fn perlin_noise_values() {
+        assert_eq!(perlin_noise(vec3(3., -2., 7.)), 0.);
+        assert_relative_eq!(perlin_noise(POINT), 0.103_543_76);
+        assert_relative_eq!(fbm(POINT, 4), 0.152_364_02);
+        assert_relative_eq!(turbulence(POINT, 4), 0.170_248_8);
+        // Smooth and bounded.
+        let step = vec3(1E-3, 0., 0.);
+        assert!((perlin_noise(POINT + step) - perlin_noise(POINT)).abs() < 1E-2);
+        for i in 0..1000 {
+            let point = vec3(i as f32 * 0.137, i as f32 * 0.731, i as f32 * -0.0523);
+            assert!(perlin_noise(point).abs() <= 1.);
+            assert!((0. ..=1.).contains(&turbulence(point, 3)));
+        }
+    }
+
+    #[test]
+    fn voronoi_cells() {
+        let (dist, value) = voronoi(POINT);
+        assert_relative_eq!(dist, 0.525_445_3);
+        assert_relative_eq!(value, 0.820_561_77);
+        // Points nearby lie in the same cell.
+        assert_eq!(voronoi(POINT + vec3(0.01, 0., 0.)).1, value);
+    }
+
+    #[test]
+    fn checker_pattern() {
+        let uv = Vec2::zero();
+        assert_eq!(blend(Pattern::Checker, uv, vec3(0.5, 0.5, 0.5)), 0.);
+        assert_eq!(blend(Pattern::Checker, uv, vec3(1.5, 0.5, 0.5)), 1.);
+        assert_eq!(blend(Pattern::Checker, uv, vec3(-0.5, 0.5, 0.5)), 1.);
+        assert_eq!(blend(Pattern::Checker, uv, vec3(-0.5, -0.5, 0.5)), 0.);
+
+        let texture = ProceduralTexture::new(
+            Pattern::Checker,
+            Color::new(1., 0., 0.),
+            Color::new(0., 0., 1.),
+        )
+        .set_space(TextureSpace::Uv)
+        .set_frequency(4.);
+        let color = |u, v| texture.evaluate(vec2(u, v), Vec3::zero(), Vec3::unit_y());
+        assert_eq!(color(0.1, 0.1), Color::new(1., 0., 0.));
+        assert_eq!(color(0.3, 0.1), Color::new(0., 0., 1.));
+        assert_eq!(color(0.3, 0.3), Color::new(1., 0., 0.));
+    }
+
+    #[test]
+    fn checker_on_axis_aligned_planes() {
+        // Hit points on a floor at y = -1 or close to y = 0 are rounded to either side of the cell
+        // boundary, which must not flip the squares.
+        let origin = vec3(0.13, 0.71, 3.3);
+        for &height in [-1., -0.001].iter() {
+            let plane = Plane::new(vec3(0., height, 0.), Vec3::unit_y());
+            for i in 0..1000 {
+                let dir = vec3(
+                    (i % 40) as f32 * 0.037 - 0.7,
+                    -0.3 - (i % 7) as f32 * 0.05,
+                    (i / 40) as f32 * 0.041 - 1.5,
+                );
+                let ray = Ray::new(origin, dir.normalize());
+                let intersection = plane.ray_intersect(&ray);
+                let point = ray.at(intersection.dist);
+                let (x, z) = (point.x.floor(), point.z.floor());
+                if point.x - x < 1E-2 || point.z - z < 1E-2 {
+                    continue;
+                }
+                let blend = blend_on_surface(
+                    Pattern::Checker,
+                    Vec2::zero(),
+                    point,
+                    intersection.geometric_normal,
+                );
+                assert_eq!(blend, (x + z).rem_euclid(2.));
+            }
+        }
+    }
+
+    #[test]
+    fn noise_patterns() {
+        let uv = Vec2::zero();
+        assert_relative_eq!(blend(Pattern::Noise, uv, POINT), 0.551_771_9);
+        assert_relative_eq!(blend(Pattern::Marble, uv, POINT), 0.529_834_87);
+        assert_relative_eq!(blend(Pattern::Wood, uv, POINT), 0.272_132_4);
+        assert_relative_eq!(blend(Pattern::Voronoi, uv, POINT), 0.820_561_77);
+        // The rings of the wood start on the y axis, where the noise vanishes at lattice points.
+        assert_eq!(blend(Pattern::Wood, uv, vec3(0., 5., 0.)), 0.);
+    }
+}
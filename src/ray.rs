@@ -0,0 +1,102 @@
+use glam::Vec3;
+
+/// Relative distance that a ray leaving a surface has to travel before it can hit anything, so
+/// that it doesn't hit the surface it starts on due to rounding errors.
+const SURFACE_EPSILON: f32 = 1E-4;
+
+/// The part of the half-line `origin + t * dir` with `t` in [`tmin`, `tmax`], at the moment `time`
+/// within the camera shutter interval. Shapes only report intersections within the bounds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ray {
+    pub origin: Vec3,
+    pub dir: Vec3,
+    pub time: f32,
+    pub tmin: f32,
+    pub tmax: f32,
+}
+
+impl Ray {
+    /// Unbounded ray at time 0.
+    pub fn new(origin: Vec3, dir: Vec3) -> Self {
+        Ray {
+            origin,
+            dir,
+            time: 0.,
+            tmin: 0.,
+            tmax: f32::INFINITY,
+        }
+    }
+
+    pub fn set_time(mut self, time: f32) -> Self {
+        self.time = time;
+        self
+    }
+
+    pub fn set_tmax(mut self, tmax: f32) -> Self {
+        self.tmax = tmax;
+        self
+    }
+
+    /// Ray starting at a point on a surface, typically where this ray hit it, at the same time.
+    /// Intersections closer than a small distance that grows with the magnitude of the point are
+    /// ignored, which skips the surface itself.
+    pub fn spawn(&self, origin: Vec3, dir: Vec3) -> Self {
+        Ray {
+            origin,
+            dir,
+            time: self.time,
+            tmin: SURFACE_EPSILON * (1. + origin.abs().max_element()),
+            tmax: f32::INFINITY,
+        }
+    }
+
+    /// Like `spawn`, but also moves the origin off the surface along its geometric `normal`, to
+    /// the side through which `dir` leaves. Shading normals from normal and bump maps can send
+    /// rays off at grazing angles to the actual surface, which the distance bound alone doesn't
+    /// reliably skip.
+    pub fn spawn_off_surface(&self, origin: Vec3, normal: Vec3, dir: Vec3) -> Self {
+        let offset = SURFACE_EPSILON * (1. + origin.abs().max_element());
+        let origin = if dir.dot(normal) < 0. {
+            origin - normal * offset
+        } else {
+            origin + normal * offset
+        };
+        self.spawn(origin, dir)
+    }
+
+    /// The point at the distance `t` along the ray.
+    pub fn at(&self, t: f32) -> Vec3 {
+        self.origin + self.dir * t
+    }
+
+    /// Whether `t` is within the bounds of the ray.
+    pub fn contains(&self, t: f32) -> bool {
+        t >= self.tmin && t <= self.tmax
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::vec3;
+
+    use super::*;
+    use crate::shape::Shape;
+    use crate::sphere::Sphere;
+
+    #[test]
+    fn ray_bounds() {
+        let sphere = Sphere::new(vec3(0., 0., -3.), 1.);
+        let ray = Ray::new(Vec3::zero(), -Vec3::unit_z()).set_time(0.5);
+        let intersection = sphere.ray_intersect(&ray);
+        assert_eq!(intersection.dist, 2.);
+        assert!(!sphere.ray_intersect(&ray.set_tmax(1.5)).exists());
+
+        // Rays leaving the surface don't hit it again at their origin.
+        let point = ray.at(intersection.dist);
+        let reflected = ray.spawn(point, Vec3::unit_z());
+        assert_eq!(reflected.time, 0.5);
+        assert!(!sphere.ray_intersect(&reflected).exists());
+        let refracted = ray.spawn(point, -Vec3::unit_z());
+        assert_relative_eq!(sphere.ray_intersect(&refracted).dist, 2.);
+    }
+}
@@ -0,0 +1,644 @@
+//! Sources of the sample values used for rendering: the positions within the pixels, the points
+//! on the lights, the bounce directions etc.
+//!
+//! Every camera ray starts a new sample of a pixel, and the values taken along its path are the
+//! dimensions of that sample. Besides plain random numbers, the samplers distribute the values of
+//! each dimension (or pair of dimensions) more evenly between the samples of a pixel, which makes
+//! the pixel estimates converge faster.
+
+use rand::{Rng as _, SeedableRng as _};
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+pub trait Sampler {
+    /// Starts the sample with the given index of the pixel (x, y). The next values are the
+    /// dimensions of this sample, starting from the first one.
+    fn start_pixel_sample(&mut self, _x: u32, _y: u32, _index: u32) {}
+
+    /// Value of the next dimension of the current sample, in [0, 1).
+    fn next_1d(&mut self) -> f32;
+
+    /// Values of the next two dimensions, distributed evenly in the unit square.
+    fn next_2d(&mut self) -> [f32; 2] {
+        [self.next_1d(), self.next_1d()]
+    }
+}
+
+/// A random number generator is a sampler with independent uniform values.
+impl<R: rand::RngCore> Sampler for R {
+    fn next_1d(&mut self) -> f32 {
+        self.gen()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SamplerType {
+    /// Independent random values.
+    Independent,
+    /// Random values jittered within strata, see `StratifiedSampler`.
+    Stratified,
+    /// Randomly shifted Halton sequence, see `HaltonSampler`.
+    Halton,
+    /// Owen-scrambled Sobol sequence, see `SobolSampler`.
+    Sobol,
+    /// Low-discrepancy sequence shifted by a blue noise mask, see `BlueNoiseSampler`.
+    BlueNoise,
+}
+
+impl FromStr for SamplerType {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, String> {
+        match name {
+            "independent" => Ok(SamplerType::Independent),
+            "stratified" => Ok(SamplerType::Stratified),
+            "halton" => Ok(SamplerType::Halton),
+            "sobol" => Ok(SamplerType::Sobol),
+            "bluenoise" => Ok(SamplerType::BlueNoise),
+            _ => Err(format!("unknown sampler '{}'", name)),
+        }
+    }
+}
+
+/// Current pixel, sample and dimension, shared by the samplers below. The randomization of the
+/// samplers is derived by hashing these with the seed, so that the values only depend on the
+/// seed, the pixel and the sample index.
+#[derive(Clone, Debug)]
+struct SampleState {
+    seed: u64,
+    pixel_seed: u64,
+    index: u32,
+    dimension: u32,
+}
+
+impl SampleState {
+    fn new(seed: u64) -> Self {
+        SampleState {
+            seed,
+            pixel_seed: seed,
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn start(&mut self, x: u32, y: u32, index: u32) {
+        self.pixel_seed = hash(self.seed, (x as u64) << 32 | y as u64);
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    /// Returns the current dimension and moves on by `count` dimensions.
+    fn advance(&mut self, count: u32) -> u32 {
+        let dimension = self.dimension;
+        self.dimension += count;
+        dimension
+    }
+
+    /// Random bits that are the same for all the samples of the pixel.
+    fn pixel_hash(&self, dimension: u32, salt: u32) -> u32 {
+        hash(self.pixel_seed, (dimension as u64) << 32 | salt as u64) as u32
+    }
+
+    /// Random bits that are different for every sample.
+    fn sample_hash(&self, dimension: u32) -> u32 {
+        hash(
+            self.pixel_seed,
+            (dimension as u64) << 32 | self.index as u64 | 1 << 63,
+        ) as u32
+    }
+}
+
+/// Splits the samples of a pixel between equal strata of every dimension and jitters them within
+/// the strata. Pairs of dimensions are stratified on a square grid. The strata are assigned to the
+/// samples in a random order for every dimension. After `samples_per_pixel` samples the strata
+/// repeat, so that every following pass of as many samples is stratified as well.
+#[derive(Clone, Debug)]
+pub struct StratifiedSampler {
+    state: SampleState,
+    samples_per_pixel: u32,
+}
+
+impl StratifiedSampler {
+    pub fn new(seed: u64, samples_per_pixel: u32) -> Self {
+        assert!(samples_per_pixel > 0);
+        StratifiedSampler {
+            state: SampleState::new(seed),
+            samples_per_pixel,
+        }
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state.start(x, y, index);
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let dimension = self.state.advance(1);
+        let strata = self.samples_per_pixel;
+        let stratum = permute(
+            self.state.index % strata,
+            strata,
+            self.state.pixel_hash(dimension, 0),
+        );
+        let jitter = to_unit(self.state.sample_hash(dimension));
+        ((stratum as f32 + jitter) / strata as f32).min(ONE_MINUS_EPSILON)
+    }
+
+    fn next_2d(&mut self) -> [f32; 2] {
+        let dimension = self.state.advance(2);
+        let side = (self.samples_per_pixel as f32).sqrt().ceil() as u32;
+        let strata = side * side;
+        let stratum = permute(
+            self.state.index % strata,
+            strata,
+            self.state.pixel_hash(dimension, 0),
+        );
+        let x = (stratum % side) as f32 + to_unit(self.state.sample_hash(dimension));
+        let y = (stratum / side) as f32 + to_unit(self.state.sample_hash(dimension + 1));
+        [
+            (x / side as f32).min(ONE_MINUS_EPSILON),
+            (y / side as f32).min(ONE_MINUS_EPSILON),
+        ]
+    }
+}
+
+/// The Halton sequence with a different prime base for every dimension. The digits are randomly
+/// permuted (Owen scrambling), with different permutations for every pixel and dimension.
+/// Dimensions beyond the supported number of bases get independent random values.
+#[derive(Clone, Debug)]
+pub struct HaltonSampler {
+    state: SampleState,
+}
+
+const PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> Self {
+        HaltonSampler {
+            state: SampleState::new(seed),
+        }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state.start(x, y, index);
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let dimension = self.state.advance(1);
+        let value = match PRIMES.get(dimension as usize) {
+            Some(&base) => scrambled_radical_inverse(
+                base,
+                self.state.index,
+                self.state.pixel_hash(dimension, 0),
+            ),
+            None => to_unit(self.state.sample_hash(dimension)),
+        };
+        value.min(ONE_MINUS_EPSILON)
+    }
+}
+
+/// Radical inverse with every digit permuted depending on the digits before it. Unlike a plain
+/// radical inverse, it also scrambles the trailing zero digits, so that the first samples in high
+/// bases are not clustered near 0.
+fn scrambled_radical_inverse(base: u32, mut index: u32, seed: u32) -> f32 {
+    // Enough digits for the f32 precision.
+    let digits = (24. / (base as f32).log2()).ceil() as u32;
+    let inv_base = 1. / base as f64;
+    let mut inv_base_power = 1.;
+    let mut permuted = 0u64;
+    let mut result = 0.;
+    for _ in 0..digits {
+        let digit_seed = hash(seed as u64, permuted) as u32;
+        let digit = permute(index % base, base, digit_seed);
+        permuted = permuted * base as u64 + digit as u64;
+        inv_base_power *= inv_base;
+        result += digit as f64 * inv_base_power;
+        index /= base;
+    }
+    result as f32
+}
+
+/// The first two dimensions of the Sobol sequence with Owen scrambling, implemented by hashing
+/// (Burley, "Practical Hash-based Owen Scrambling", JCGT 2020). Every pair of dimensions uses
+/// the same two Sobol dimensions with independent scrambling and a randomly shuffled order of
+/// the samples, so that the number of dimensions is unlimited.
+#[derive(Clone, Debug)]
+pub struct SobolSampler {
+    state: SampleState,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> Self {
+        SobolSampler {
+            state: SampleState::new(seed),
+        }
+    }
+
+    fn shuffled_index(&self, dimension: u32) -> u32 {
+        nested_uniform_scramble(self.state.index, self.state.pixel_hash(dimension, 0))
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state.start(x, y, index);
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        let dimension = self.state.advance(1);
+        let index = self.shuffled_index(dimension);
+        let seed = self.state.pixel_hash(dimension, 1);
+        to_unit(nested_uniform_scramble(index.reverse_bits(), seed))
+    }
+
+    fn next_2d(&mut self) -> [f32; 2] {
+        let dimension = self.state.advance(2);
+        let index = self.shuffled_index(dimension);
+        [
+            to_unit(nested_uniform_scramble(
+                index.reverse_bits(),
+                self.state.pixel_hash(dimension, 1),
+            )),
+            to_unit(nested_uniform_scramble(
+                sobol_second_dimension(index),
+                self.state.pixel_hash(dimension, 2),
+            )),
+        ]
+    }
+}
+
+/// Second dimension of the Sobol sequence as 32-bit fixed point.
+fn sobol_second_dimension(mut index: u32) -> u32 {
+    let mut result = 0;
+    let mut direction = 1 << 31;
+    while index != 0 {
+        if index & 1 != 0 {
+            result ^= direction;
+        }
+        index >>= 1;
+        direction ^= direction >> 1;
+    }
+    result
+}
+
+/// Owen scrambling of a 32-bit fixed point number in [0, 1).
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x
+}
+
+/// A low-discrepancy sequence (the golden ratio sequence for single dimensions and the R2
+/// sequence for pairs) shifted by the values of a blue noise mask tiled over the image. Each
+/// pixel gets well distributed samples, while the errors of neighbouring pixels are
+/// decorrelated, so that the remaining noise at low sample counts has no low frequencies.
+#[derive(Clone, Debug)]
+pub struct BlueNoiseSampler {
+    state: SampleState,
+    x: u32,
+    y: u32,
+}
+
+impl BlueNoiseSampler {
+    pub fn new(seed: u64) -> Self {
+        BlueNoiseSampler {
+            state: SampleState::new(seed),
+            x: 0,
+            y: 0,
+        }
+    }
+
+    /// The mask value for the pixel. The mask is shifted by a random offset for every dimension.
+    fn mask_value(&self, dimension: u32) -> f32 {
+        let offset = hash(self.state.seed, dimension as u64);
+        let x = (self.x as u64 + (offset & 0xffff)) as usize % BLUE_NOISE_SIZE;
+        let y = (self.y as u64 + (offset >> 16 & 0xffff)) as usize % BLUE_NOISE_SIZE;
+        blue_noise_mask()[y * BLUE_NOISE_SIZE + x]
+    }
+}
+
+impl Sampler for BlueNoiseSampler {
+    fn start_pixel_sample(&mut self, x: u32, y: u32, index: u32) {
+        self.state.start(x, y, index);
+        self.x = x;
+        self.y = y;
+    }
+
+    fn next_1d(&mut self) -> f32 {
+        // Fractional part of the golden ratio.
+        const ALPHA: f64 = 0.618_033_988_749_894_9;
+        let dimension = self.state.advance(1);
+        let value = self.mask_value(dimension) as f64 + self.state.index as f64 * ALPHA;
+        (value.fract() as f32).min(ONE_MINUS_EPSILON)
+    }
+
+    fn next_2d(&mut self) -> [f32; 2] {
+        // Inverse powers of the plastic number.
+        const ALPHA: [f64; 2] = [0.754_877_666_246_692_7, 0.569_840_290_998_053_3];
+        let dimension = self.state.advance(2);
+        let index = self.state.index as f64;
+        let x = self.mask_value(dimension) as f64 + index * ALPHA[0];
+        let y = self.mask_value(dimension + 1) as f64 + index * ALPHA[1];
+        [
+            (x.fract() as f32).min(ONE_MINUS_EPSILON),
+            (y.fract() as f32).min(ONE_MINUS_EPSILON),
+        ]
+    }
+}
+
+const BLUE_NOISE_SIZE: usize = 64;
+
+/// A tileable blue noise mask of BLUE_NOISE_SIZE x BLUE_NOISE_SIZE values in (0, 1), generated on
+/// first use.
+fn blue_noise_mask() -> &'static [f32] {
+    static MASK: OnceLock<Vec<f32>> = OnceLock::new();
+    MASK.get_or_init(|| {
+        let ranks = void_and_cluster(BLUE_NOISE_SIZE, 239);
+        let count = ranks.len() as f32;
+        ranks
+            .into_iter()
+            .map(|rank| (rank as f32 + 0.5) / count)
+            .collect()
+    })
+}
+
+/// Orders the cells of a toroidal `size` x `size` grid so that every prefix of the order is
+/// evenly spread (Ulichney, "The void-and-cluster method for dither array generation", 1993).
+/// Returns the rank of every cell.
+fn void_and_cluster(size: usize, seed: u64) -> Vec<u32> {
+    const SIGMA: f32 = 1.5;
+    let count = size * size;
+    // Gaussian filter weights by the toroidal offset between two cells.
+    let mut kernel = vec![0.; count];
+    for dy in 0..size {
+        for dx in 0..size {
+            let wrap = |d: usize| d.min(size - d) as f32;
+            let dist2 = wrap(dx).powi(2) + wrap(dy).powi(2);
+            kernel[dy * size + dx] = (-dist2 / (2. * SIGMA * SIGMA)).exp();
+        }
+    }
+
+    let mut pattern = Pattern {
+        size,
+        kernel: &kernel,
+        set: vec![false; count],
+        energy: vec![0.; count],
+    };
+
+    // Initial pattern: random points, evened out by moving the point from the tightest cluster
+    // to the largest void until that point is the one that would be moved back.
+    let mut rng = rand::rngs::SmallRng::seed_from_u64(seed);
+    let initial = count / 10;
+    let mut added = 0;
+    while added < initial {
+        let cell = rng.gen_range(0..count);
+        if !pattern.set[cell] {
+            pattern.toggle(cell);
+            added += 1;
+        }
+    }
+    loop {
+        let cluster = pattern.tightest_cluster();
+        pattern.toggle(cluster);
+        let void = pattern.largest_void();
+        if void == cluster {
+            pattern.toggle(cluster);
+            break;
+        }
+        pattern.toggle(void);
+    }
+    let initial_set = pattern.set.clone();
+    let initial_energy = pattern.energy.clone();
+
+    // The points of the initial pattern get the lowest ranks, with the points in the tightest
+    // clusters ranked last.
+    let mut ranks = vec![0; count];
+    for rank in (0..initial).rev() {
+        let cluster = pattern.tightest_cluster();
+        pattern.toggle(cluster);
+        ranks[cluster] = rank as u32;
+    }
+
+    // The rest of the cells are ranked by filling the largest voids.
+    pattern.set = initial_set;
+    pattern.energy = initial_energy;
+    for rank in initial..count {
+        let void = pattern.largest_void();
+        pattern.toggle(void);
+        ranks[void] = rank as u32;
+    }
+    ranks
+}
+
+/// A binary pattern on a toroidal grid, used by `void_and_cluster`. The energy of every cell is
+/// the sum of the filter weights of all the set cells.
+struct Pattern<'a> {
+    size: usize,
+    kernel: &'a [f32],
+    set: Vec<bool>,
+    energy: Vec<f32>,
+}
+
+impl Pattern<'_> {
+    fn toggle(&mut self, cell: usize) {
+        self.set[cell] = !self.set[cell];
+        let sign = if self.set[cell] { 1. } else { -1. };
+        let (cx, cy) = (cell % self.size, cell / self.size);
+        for y in 0..self.size {
+            let dy = (y + self.size - cy) % self.size;
+            for x in 0..self.size {
+                let dx = (x + self.size - cx) % self.size;
+                self.energy[y * self.size + x] += sign * self.kernel[dy * self.size + dx];
+            }
+        }
+    }
+
+    /// The set cell with the most set neighbours.
+    fn tightest_cluster(&self) -> usize {
+        self.extreme(true, |a, b| a > b)
+    }
+
+    /// The unset cell with the least set neighbours.
+    fn largest_void(&self) -> usize {
+        self.extreme(false, |a, b| a < b)
+    }
+
+    fn extreme(&self, set: bool, better: impl Fn(f32, f32) -> bool) -> usize {
+        let mut best = None;
+        for (cell, &energy) in self.energy.iter().enumerate() {
+            if self.set[cell] != set {
+                continue;
+            }
+            match best {
+                Some((_, best_energy)) if !better(energy, best_energy) => {}
+                _ => best = Some((cell, energy)),
+            }
+        }
+        best.unwrap().0
+    }
+}
+
+/// The largest f32 below 1.
+const ONE_MINUS_EPSILON: f32 = 1. - f32::EPSILON / 2.;
+
+/// Converts random bits to a float in [0, 1).
+fn to_unit(bits: u32) -> f32 {
+    (bits >> 8) as f32 / (1 << 24) as f32
+}
+
+/// Mixes two values into well distributed random bits (SplitMix64 finalizer).
+fn hash(a: u64, b: u64) -> u64 {
+    let mut z = a ^ b.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Random permutation of the integers in [0, len), selected by `seed` (Kensler, "Correlated
+/// Multi-Jittered Sampling", 2013).
+fn permute(mut i: u32, len: u32, seed: u32) -> u32 {
+    let mut w = len - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < len {
+            break;
+        }
+    }
+    (i.wrapping_add(seed)) % len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The first `n` 2D samples of a pixel.
+    fn samples_2d(sampler: &mut impl Sampler, n: u32) -> Vec<[f32; 2]> {
+        (0..n)
+            .map(|index| {
+                sampler.start_pixel_sample(3, 5, index);
+                sampler.next_1d();
+                sampler.next_2d()
+            })
+            .collect()
+    }
+
+    /// Checks that every cell of the `side` x `side` grid contains exactly one sample.
+    fn assert_stratified(samples: &[[f32; 2]], side: usize) {
+        let mut cells = vec![0; side * side];
+        for &[x, y] in samples.iter() {
+            assert!((0. ..1.).contains(&x) && (0. ..1.).contains(&y));
+            cells[(y * side as f32) as usize * side + (x * side as f32) as usize] += 1;
+        }
+        assert!(cells.iter().all(|&count| count == 1));
+    }
+
+    #[test]
+    fn stratified_2d() {
+        let samples = samples_2d(&mut StratifiedSampler::new(239, 16), 32);
+        assert_stratified(&samples[..16], 4);
+        // The next pass is stratified again, with different jitter.
+        assert_stratified(&samples[16..], 4);
+        assert_ne!(samples[0], samples[16]);
+    }
+
+    #[test]
+    fn sobol_2d_is_stratified() {
+        // The Owen-scrambled Sobol (0, 2)-sequence is stratified on elementary intervals.
+        let samples = samples_2d(&mut SobolSampler::new(239), 16);
+        assert_stratified(&samples, 4);
+    }
+
+    #[test]
+    fn halton_stratification() {
+        let mut sampler = HaltonSampler::new(239);
+        // Scrambling keeps the stratification of the sequence: the first base^2 values fall into
+        // different intervals of length 1/base^2, including for the larger bases.
+        for dimension in [0, 10].iter() {
+            let base = PRIMES[*dimension];
+            let mut cells = vec![0; (base * base) as usize];
+            for index in 0..base * base {
+                sampler.start_pixel_sample(0, 0, index);
+                for _ in 0..*dimension {
+                    sampler.next_1d();
+                }
+                cells[(sampler.next_1d() * (base * base) as f32) as usize] += 1;
+            }
+            assert!(cells.iter().all(|&count| count == 1));
+        }
+    }
+
+    #[test]
+    fn samplers_are_deterministic() {
+        let mut a = BlueNoiseSampler::new(239);
+        let mut b = BlueNoiseSampler::new(239);
+        a.start_pixel_sample(10, 20, 7);
+        b.start_pixel_sample(10, 20, 7);
+        assert_eq!(a.next_2d(), b.next_2d());
+        assert_eq!(a.next_1d(), b.next_1d());
+    }
+
+    #[test]
+    fn permutation() {
+        for &len in [1, 5, 16, 100].iter() {
+            let mut values: Vec<u32> = (0..len).map(|i| permute(i, len, 239)).collect();
+            values.sort_unstable();
+            assert!(values.iter().copied().eq(0..len));
+        }
+    }
+
+    #[test]
+    fn blue_noise_mask_ranks() {
+        let mask = blue_noise_mask();
+        let mut ranks: Vec<u32> = mask
+            .iter()
+            .map(|&value| (value * mask.len() as f32) as u32)
+            .collect();
+        ranks.sort_unstable();
+        assert!(ranks.iter().copied().eq(0..mask.len() as u32));
+
+        // Blue noise has little low-frequency energy: the averages of 8x8 blocks stay close to
+        // the global average of 0.5.
+        for block in 0..BLUE_NOISE_SIZE * BLUE_NOISE_SIZE / 64 {
+            let (bx, by) = (block % 8 * 8, block / 8 * 8);
+            let sum: f32 = (0..64)
+                .map(|i| mask[(by + i / 8) * BLUE_NOISE_SIZE + bx + i % 8])
+                .sum();
+            assert!((sum / 64. - 0.5).abs() < 0.05);
+        }
+    }
+}
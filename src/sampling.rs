@@ -0,0 +1,317 @@
+use glam::{Vec2, Vec3};
+use std::f32::consts::{FRAC_PI_2, FRAC_PI_4, PI};
+
+use crate::sampler::Sampler;
+
+/// Builds two unit vectors that together with the unit vector `normal` form an orthonormal basis.
+pub fn orthonormal_basis(normal: Vec3) -> (Vec3, Vec3) {
+    // Duff et al., "Building an Orthonormal Basis, Revisited", JCGT 2017.
+    let sign = 1f32.copysign(normal.z);
+    let a = -1. / (sign + normal.z);
+    let b = normal.x * normal.y * a;
+    let tangent = Vec3::new(
+        1. + sign * normal.x * normal.x * a,
+        sign * b,
+        -sign * normal.x,
+    );
+    let bitangent = Vec3::new(b, sign + normal.y * normal.y * a, -normal.y);
+    (tangent, bitangent)
+}
+
+/// Random direction in the hemisphere around `normal` with the probability density proportional
+/// to the cosine of the angle between the direction and the normal.
+pub fn cosine_sample_hemisphere(normal: Vec3, sampler: &mut impl Sampler) -> Vec3 {
+    let [u, v] = sampler.next_2d();
+    let r = u.sqrt();
+    let phi = 2. * PI * v;
+    let x = r * phi.cos();
+    let y = r * phi.sin();
+    let z = (1. - r * r).max(0.).sqrt();
+    let (tangent, bitangent) = orthonormal_basis(normal);
+    (tangent * x + bitangent * y + normal * z).normalize()
+}
+
+/// Uniformly distributed random point in the unit disk, using Shirley and Chiu's concentric
+/// mapping of the square onto the disk, which keeps the stratification of the samples.
+pub fn concentric_sample_disk(sampler: &mut impl Sampler) -> Vec2 {
+    let [u, v] = sampler.next_2d();
+    let (x, y) = (2. * u - 1., 2. * v - 1.);
+    if x == 0. && y == 0. {
+        return Vec2::zero();
+    }
+    let (r, phi) = if x.abs() > y.abs() {
+        (x, FRAC_PI_4 * (y / x))
+    } else {
+        (y, FRAC_PI_2 - FRAC_PI_4 * (x / y))
+    };
+    Vec2::new(r * phi.cos(), r * phi.sin())
+}
+
+/// Uniformly distributed random point in the regular polygon with the given number of sides,
+/// inscribed in the unit circle with a vertex on the positive y axis.
+pub fn uniform_sample_polygon(sides: u32, sampler: &mut impl Sampler) -> Vec2 {
+    let [u, v] = sampler.next_2d();
+    // Pick one of the triangles between the center and two neighbouring vertices, and reuse the
+    // rest of u for the point in the triangle.
+    let u = u * sides as f32;
+    let triangle = (u as u32).min(sides - 1);
+    let u = u - triangle as f32;
+    let vertex = |i: u32| {
+        let phi = FRAC_PI_2 + 2. * PI * i as f32 / sides as f32;
+        Vec2::new(phi.cos(), phi.sin())
+    };
+    let s = u.sqrt();
+    (vertex(triangle) * (1. - v) + vertex(triangle + 1) * v) * s
+}
+
+/// Uniformly distributed random unit vector.
+pub fn uniform_sample_sphere(sampler: &mut impl Sampler) -> Vec3 {
+    let [u, v] = sampler.next_2d();
+    let z = 1. - 2. * u;
+    let r = (1. - z * z).max(0.).sqrt();
+    let phi = 2. * PI * v;
+    Vec3::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+/// Uniformly distributed random unit vector within the angle `acos(cos_max)` around the unit
+/// vector `axis`.
+pub fn uniform_sample_cone(axis: Vec3, cos_max: f32, sampler: &mut impl Sampler) -> Vec3 {
+    let [u, v] = sampler.next_2d();
+    let cos = 1. - u * (1. - cos_max);
+    let sin = (1. - cos * cos).max(0.).sqrt();
+    let phi = 2. * PI * v;
+    let (tangent, bitangent) = orthonormal_basis(axis);
+    (tangent * (sin * phi.cos()) + bitangent * (sin * phi.sin()) + axis * cos).normalize()
+}
+
+/// Uniformly distributed random point in the unit ball.
+pub fn uniform_sample_ball(sampler: &mut impl Sampler) -> Vec3 {
+    let dir = uniform_sample_sphere(sampler);
+    dir * sampler.next_1d().cbrt()
+}
+
+/// Weight of a sample taken with the probability density `pdf` when the same value can also be
+/// sampled by another strategy with the density `other_pdf`, for multiple importance sampling.
+pub fn power_heuristic(pdf: f32, other_pdf: f32) -> f32 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b == 0. {
+        0.
+    } else {
+        a / (a + b)
+    }
+}
+
+/// Piecewise-constant probability distribution on [0, 1), proportional to a function given by
+/// its values on equal-width intervals.
+pub struct Distribution1D {
+    func: Vec<f32>,
+    /// Cumulative distribution at the start of every interval and at the end of the last one.
+    cdf: Vec<f32>,
+    integral: f32,
+}
+
+impl Distribution1D {
+    /// The values must not be negative. If they are all zero the distribution is uniform.
+    pub fn new(func: Vec<f32>) -> Self {
+        assert!(!func.is_empty());
+        let n = func.len() as f32;
+        let mut cdf = Vec::with_capacity(func.len() + 1);
+        cdf.push(0.);
+        for value in func.iter() {
+            debug_assert!(*value >= 0.);
+            cdf.push(cdf.last().unwrap() + value / n);
+        }
+        let integral = *cdf.last().unwrap();
+        for (i, c) in cdf.iter_mut().enumerate() {
+            *c = if integral > 0. {
+                *c / integral
+            } else {
+                i as f32 / n
+            };
+        }
+        Distribution1D {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    /// Integral of the function over [0, 1).
+    pub fn integral(&self) -> f32 {
+        self.integral
+    }
+
+    /// Maps a uniform sample `u` in [0, 1) to a sample of the distribution. Returns the sample,
+    /// its probability density and the index of its interval.
+    pub fn sample(&self, u: f32) -> (f32, f32, usize) {
+        // The last interval whose start is at most u, skipping intervals with zero probability.
+        let index = (self.cdf.partition_point(|&c| c <= u) - 1).min(self.func.len() - 1);
+        let width = self.cdf[index + 1] - self.cdf[index];
+        let offset = if width > 0. {
+            (u - self.cdf[index]) / width
+        } else {
+            0.
+        };
+        let x = ((index as f32 + offset) / self.func.len() as f32).min(1. - f32::EPSILON);
+        (x, self.pdf(index), index)
+    }
+
+    /// Probability density in the interval with the given index.
+    pub fn pdf(&self, index: usize) -> f32 {
+        if self.integral > 0. {
+            self.func[index] / self.integral
+        } else {
+            1.
+        }
+    }
+}
+
+/// Piecewise-constant probability distribution on [0, 1) x [0, 1), proportional to a function
+/// given by its values on a grid of equal cells, `width` cells per row.
+pub struct Distribution2D {
+    /// Distribution of x within each row.
+    rows: Vec<Distribution1D>,
+    /// Distribution of the rows.
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub fn new(func: &[f32], width: usize) -> Self {
+        assert!(width > 0 && func.len().is_multiple_of(width));
+        let rows: Vec<Distribution1D> = func
+            .chunks(width)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(rows.iter().map(|row| row.integral()).collect());
+        Distribution2D { rows, marginal }
+    }
+
+    /// Maps a uniform sample in [0, 1)^2 to a sample of the distribution and its probability
+    /// density.
+    pub fn sample(&self, u: [f32; 2]) -> (Vec2, f32) {
+        let (y, pdf_y, row) = self.marginal.sample(u[1]);
+        let (x, pdf_x, _) = self.rows[row].sample(u[0]);
+        (Vec2::new(x, y), pdf_x * pdf_y)
+    }
+
+    /// Probability density at a point of [0, 1)^2.
+    pub fn pdf(&self, point: Vec2) -> f32 {
+        let cell = |x: f32, n: usize| ((x * n as f32) as usize).min(n - 1);
+        let row = cell(point.y, self.rows.len());
+        let x = cell(point.x, self.rows[row].func.len());
+        self.marginal.pdf(row) * self.rows[row].pdf(x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::vec3;
+    use rand::SeedableRng as _;
+
+    use super::*;
+
+    #[test]
+    fn orthonormal_basis_is_orthonormal() {
+        for normal in [
+            Vec3::unit_x(),
+            -Vec3::unit_z(),
+            vec3(1., 2., -3.).normalize(),
+        ]
+        .iter()
+        {
+            let (t, b) = orthonormal_basis(*normal);
+            assert_relative_eq!(t.length(), 1., epsilon = 1E-6);
+            assert_relative_eq!(b.length(), 1., epsilon = 1E-6);
+            assert!(t.dot(b).abs() < 1E-6);
+            assert!(t.dot(*normal).abs() < 1E-6);
+            assert!(b.dot(*normal).abs() < 1E-6);
+        }
+    }
+
+    #[test]
+    fn cosine_sample_hemisphere_distribution() {
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(239);
+        let normal = vec3(0., 1., 1.).normalize();
+        let n = 100000;
+        let mut cos_sum = 0.;
+        for _ in 0..n {
+            let dir = cosine_sample_hemisphere(normal, &mut rng);
+            assert_relative_eq!(dir.length(), 1., epsilon = 1E-5);
+            let cos = dir.dot(normal);
+            assert!(cos >= 0.);
+            cos_sum += cos;
+        }
+        // The mean of cos(theta) for the density cos(theta) / pi is 2/3.
+        assert_relative_eq!(cos_sum / n as f32, 2. / 3., epsilon = 1E-2);
+    }
+
+    #[test]
+    fn uniform_sample_cone_distribution() {
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(239);
+        let axis = vec3(1., -1., 2.).normalize();
+        let cos_max = 0.9;
+        let n = 10000;
+        let mut cos_sum = 0.;
+        for _ in 0..n {
+            let dir = uniform_sample_cone(axis, cos_max, &mut rng);
+            assert_relative_eq!(dir.length(), 1., epsilon = 1E-5);
+            let cos = dir.dot(axis);
+            assert!(cos >= cos_max - 1E-6);
+            cos_sum += cos;
+        }
+        // Uniform on the spherical cap, so cos(theta) is uniform in [cos_max, 1].
+        assert_relative_eq!(cos_sum / n as f32, (1. + cos_max) / 2., epsilon = 1E-3);
+        assert!((uniform_sample_cone(axis, 1., &mut rng) - axis).length() < 1E-6);
+    }
+
+    #[test]
+    fn piecewise_constant_distributions() {
+        let distribution = Distribution1D::new(vec![1., 3.]);
+        assert_eq!(distribution.integral(), 2.);
+        let (x, pdf, index) = distribution.sample(0.1);
+        assert_relative_eq!(x, 0.2);
+        assert_eq!((pdf, index), (0.5, 0));
+        let (x, pdf, index) = distribution.sample(0.5);
+        assert_relative_eq!(x, 2. / 3.);
+        assert_eq!((pdf, index), (1.5, 1));
+
+        // All the probability is in the top right cell.
+        let distribution = Distribution2D::new(&[0., 0., 0., 4.], 2);
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(239);
+        for _ in 0..100 {
+            let (point, pdf) = distribution.sample(rng.next_2d());
+            assert!(point.x >= 0.5 && point.x < 1. && point.y >= 0.5 && point.y < 1.);
+            assert_eq!(pdf, 4.);
+            assert_eq!(distribution.pdf(point), 4.);
+        }
+        assert_eq!(distribution.pdf(Vec2::new(0.25, 0.75)), 0.);
+
+        // Without any weight the distribution is uniform.
+        let (x, pdf, _) = Distribution1D::new(vec![0., 0.]).sample(0.75);
+        assert_eq!((x, pdf), (0.75, 1.));
+    }
+
+    #[test]
+    fn disk_and_polygon_samples() {
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(239);
+        let n = 10000;
+        let mut sum = Vec2::zero();
+        for _ in 0..n {
+            let p = concentric_sample_disk(&mut rng);
+            assert!(p.length() <= 1. + 1E-6);
+            sum += p;
+        }
+        assert!((sum / n as f32).length() < 2E-2);
+
+        // Points in a hexagon are within the distance cos(30°) from the center in the direction
+        // of the middle of a side, i.e. the positive x axis.
+        let mut sum = Vec2::zero();
+        for _ in 0..n {
+            let p = uniform_sample_polygon(6, &mut rng);
+            assert!(p.length() <= 1. + 1E-6);
+            assert!(p.x.abs() <= (PI / 6.).cos() + 1E-6);
+            sum += p;
+        }
+        assert!((sum / n as f32).length() < 2E-2);
+    }
+}
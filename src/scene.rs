@@ -1,147 +1,713 @@
 use glam::Vec3;
+use std::f32::consts::PI;
+use std::path::Path;
+use std::sync::OnceLock;
 
+use crate::animation::Motion;
+use crate::bvh::Bvh;
 use crate::defines::*;
+use crate::environment::Environment;
 use crate::light::{Light, PointLight, SphereLight};
-use crate::material::{Color, Material};
+use crate::material::{reflect, refract, schlick_reflectance, Color, Material, Surface};
+use crate::mesh::TriangleMesh;
+use crate::obj::load_obj;
 use crate::plane::Plane;
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::sampling::{cosine_sample_hemisphere, power_heuristic, uniform_sample_ball};
+use crate::scene_file::{load_scene, LoadError};
 use crate::shape::{Intersection, Shape};
+use crate::sky::SunLight;
 use crate::sphere::Sphere;
+use crate::triangle::Triangle;
+
+/// Number of bounces after which paths start being terminated with Russian roulette.
+const ROULETTE_DEPTH: u32 = 3;
+
+/// Acceleration structure over the objects of the scene. Objects with bounding boxes go into the
+/// hierarchy, the unbounded ones (like planes) are checked one by one.
+struct SceneBvh {
+    bvh: Bvh,
+    bounded: Vec<usize>,
+    unbounded: Vec<usize>,
+}
 
 pub struct Scene {
-    spheres: Vec<(usize, Sphere)>,
-    planes: Vec<(usize, Plane)>,
+    objects: Vec<Box<dyn Shape>>,
+    /// Material of each object, with the same index.
     materials: Vec<Material>,
     point_lights: Vec<PointLight>,
     sphere_lights: Vec<SphereLight>,
+    sun_lights: Vec<SunLight>,
+    max_depth: u32,
+    /// Radiance of the rays that escape the scene.
+    environment: Option<Box<dyn Environment>>,
+    /// Built on the first intersection query after the objects have changed.
+    bvh: OnceLock<SceneBvh>,
 }
 
 impl Scene {
     pub fn new() -> Self {
         Scene {
-            spheres: Vec::new(),
-            planes: Vec::new(),
+            objects: Vec::new(),
             materials: Vec::new(),
             point_lights: Vec::new(),
             sphere_lights: Vec::new(),
+            sun_lights: Vec::new(),
+            max_depth: 5,
+            environment: None,
+            bvh: OnceLock::new(),
         }
     }
 
-    pub fn add_sphere(&mut self, sphere: Sphere, material: Material) -> usize {
-        let id = self.materials.len();
-        self.spheres.push((id, sphere));
+    /// Loads the scene from a scene file, ignoring the camera settings.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, LoadError> {
+        load_scene(path).map(|(scene, _)| scene)
+    }
+
+    /// Adds an object of any shape to the scene. Returns the id of the object, which is reported
+    /// by `find_intersection`.
+    pub fn add_object(&mut self, shape: impl Shape + 'static, material: Material) -> usize {
+        let id = self.objects.len();
+        self.bvh = OnceLock::new();
+        self.objects.push(Box::new(shape));
         self.materials.push(material);
         id
     }
 
+    pub fn add_sphere(&mut self, sphere: Sphere, material: Material) -> usize {
+        self.add_object(sphere, material)
+    }
+
     pub fn add_plane(&mut self, plane: Plane, material: Material) -> usize {
-        let id = self.materials.len();
-        self.planes.push((id, plane));
-        self.materials.push(material);
-        id
+        self.add_object(plane, material)
+    }
+
+    pub fn add_triangle(&mut self, triangle: Triangle, material: Material) -> usize {
+        self.add_object(triangle, material)
+    }
+
+    pub fn add_mesh(&mut self, mesh: TriangleMesh, material: Material) -> usize {
+        self.add_object(mesh, material)
+    }
+
+    /// Adds the meshes from an OBJ file with the materials from its MTL libraries. Faces without
+    /// a material get `default_material`.
+    pub fn add_obj(
+        &mut self,
+        path: impl AsRef<Path>,
+        default_material: Material,
+    ) -> Result<(), LoadError> {
+        for (mesh, material) in load_obj(path, default_material)? {
+            self.add_mesh(mesh, material);
+        }
+        Ok(())
     }
 
-    pub fn add_point_light(&mut self, position: Vec3, intensity: f32) {
+    /// Adds a point light. `intensity` is either a `Color` or a single `f32` for white light.
+    pub fn add_point_light(&mut self, position: Vec3, intensity: impl Into<Color>) {
         self.point_lights.push(PointLight::new(position, intensity));
     }
 
-    pub fn add_sphere_light(&mut self, center: Vec3, radius: f32, intensity: f32) {
+    pub fn add_sphere_light(&mut self, center: Vec3, radius: f32, intensity: impl Into<Color>) {
         self.sphere_lights
             .push(SphereLight::new(center, radius, intensity))
     }
 
-    pub fn find_intersection(&self, origin: Vec3, dir: Vec3) -> (Intersection, usize) {
-        let mut best_idx = 0;
-        let mut nearest = Intersection::new_empty();
+    /// Adds a point light that moves with the transform of `motion`.
+    pub fn add_moving_point_light(
+        &mut self,
+        position: Vec3,
+        intensity: impl Into<Color>,
+        motion: impl Motion + 'static,
+    ) {
+        self.point_lights
+            .push(PointLight::new(position, intensity).set_motion(motion));
+    }
 
-        for (id, sphere) in self.spheres.iter() {
-            let intersection = sphere.ray_intersect(origin, dir);
-            if intersection < nearest {
-                nearest = intersection;
-                best_idx = *id;
-            }
-        }
+    /// Adds a sphere light whose center moves with the transform of `motion`.
+    pub fn add_moving_sphere_light(
+        &mut self,
+        center: Vec3,
+        radius: f32,
+        intensity: impl Into<Color>,
+        motion: impl Motion + 'static,
+    ) {
+        self.sphere_lights
+            .push(SphereLight::new(center, radius, intensity).set_motion(motion));
+    }
+
+    /// Adds a sun, usually together with the matching `Sky` as the environment.
+    pub fn add_sun_light(&mut self, light: SunLight) {
+        self.sun_lights.push(light);
+    }
+
+    /// Surrounds the scene with the environment, which lights it and is seen by the rays that
+    /// miss all objects.
+    pub fn set_environment(&mut self, environment: impl Environment + 'static) {
+        self.environment = Some(Box::new(environment));
+    }
+
+    /// Maximum number of indirect bounces along a path. 0 means direct lighting only.
+    pub fn set_max_depth(&mut self, max_depth: u32) {
+        self.max_depth = max_depth;
+    }
+
+    /// Material of the object with the given index, as returned by `find_intersection`.
+    pub fn material(&self, object: usize) -> &Material {
+        &self.materials[object]
+    }
+
+    /// Nearest intersection within the bounds of the ray and the id of the intersected object.
+    pub fn find_intersection(&self, ray: &Ray) -> (Intersection, usize) {
+        let scene_bvh = self.bvh();
+        let (mut nearest, index) = scene_bvh.bvh.intersect(ray, |index| {
+            self.objects[scene_bvh.bounded[index]].ray_intersect(ray)
+        });
+        let mut best_idx = if nearest.exists() {
+            scene_bvh.bounded[index]
+        } else {
+            0
+        };
 
-        for (id, plane) in self.planes.iter() {
-            let intersection = plane.ray_intersect(origin, dir);
+        for &id in scene_bvh.unbounded.iter() {
+            let intersection = self.objects[id].ray_intersect(ray);
             if intersection < nearest {
                 nearest = intersection;
-                best_idx = *id;
+                best_idx = id;
             }
         }
 
         (nearest, best_idx)
     }
 
-    // dir: direction of the ray from the camera to the surface,
-    // normal: normal to the surface
+    /// Checks whether there are any objects within the bounds of the ray.
+    pub fn is_occluded(&self, ray: &Ray) -> bool {
+        let scene_bvh = self.bvh();
+        if scene_bvh
+            .unbounded
+            .iter()
+            .any(|&id| self.objects[id].ray_intersect(ray).exists())
+        {
+            return true;
+        }
+        scene_bvh.bvh.any_hit(ray, |index| {
+            self.objects[scene_bvh.bounded[index]].ray_intersect(ray)
+        })
+    }
+
+    fn bvh(&self) -> &SceneBvh {
+        self.bvh.get_or_init(|| {
+            let mut bounded = Vec::new();
+            let mut bounds = Vec::new();
+            let mut unbounded = Vec::new();
+            for (id, object) in self.objects.iter().enumerate() {
+                match object.bounds() {
+                    Some(aabb) => {
+                        bounded.push(id);
+                        bounds.push(aabb);
+                    }
+                    None => unbounded.push(id),
+                }
+            }
+            SceneBvh {
+                bvh: Bvh::build(&bounds),
+                bounded,
+                unbounded,
+            }
+        })
+    }
+
+    // ray: the ray from the camera that hit the surface,
+    // surface: the hit point with its normals, facing the ray
     fn illumination_from_light(
         &self,
-        point: Vec3,
-        normal: Vec3,
-        dir: Vec3,
+        surface: &SurfacePoint,
+        ray: &Ray,
         material: &Material,
         light: &impl Light,
-        rng: &mut impl rand::Rng,
-    ) -> f32 {
-        let light_vec = light.sample_ray(point, rng);
-        let light_dist2 = light_vec.length_squared();
-        let light_dist = light_dist2.sqrt();
-        let light_dir = light_vec / light_dist;
-
-        let expanded = point + normal * EPSILON;
-        let (to_light_int, _) = self.find_intersection(expanded, light_dir);
-        if to_light_int.exists() && to_light_int.dist < light_dist {
-            return 0.;
+        sampler: &mut impl Sampler,
+    ) -> Color {
+        let SurfacePoint { point, normal, .. } = *surface;
+        let (light_dir, light_dist) = light.sample_ray(point, ray.time, sampler);
+        // Lights at an infinite distance don't fall off.
+        let falloff = if light_dist.is_finite() {
+            1. / (light_dist * light_dist)
+        } else {
+            1.
+        };
+
+        // The light is behind the actual surface, even if a perturbed normal faces it.
+        if light_dir.dot(surface.geometric_normal) <= 0. {
+            return Color::black();
+        }
+        let shadow_ray = ray.spawn_off_surface(point, surface.geometric_normal, light_dir);
+        if self.is_occluded(&shadow_ray.set_tmax(light_dist)) {
+            return Color::black();
         }
         let diffusion_intensity = normal.dot(light_dir);
         // Light is on the other side of the surface.
         if diffusion_intensity < EPSILON {
             // TODO: shouldn't happen
-            return 0.;
+            return Color::black();
         }
 
         let reflect_vec = normal * (2. * light_dir.dot(normal)) - light_dir;
         let reflect_vec = reflect_vec.normalize();
-        let reflect_intensity = reflect_vec.dot(-dir);
+        let reflect_intensity = reflect_vec.dot(-ray.dir);
         let reflect_intensity = if reflect_intensity > 0. {
             reflect_intensity.powf(material.shininess)
         } else {
             0.
         };
 
-        light.intensity() / light_dist2
-            * (material.diffusion * diffusion_intensity
-                + material.reflection * reflect_intensity)
+        // The diffuse term is normalized by 1/pi like the diffuse bounces and the environment, so
+        // that all the lights of the scene use the same units.
+        let intensity = (material.diffusion * diffusion_intensity / PI
+            + material.reflection * reflect_intensity)
+            * falloff;
+        light.intensity() * intensity
     }
 
-    pub fn ray_color(&self, origin: Vec3, dir: Vec3, rng: &mut impl rand::Rng)
-    -> Color {
-        let (intersection, id) = self.find_intersection(origin, dir);
-        if !intersection.exists() {
+    /// Radiance from the environment reaching a diffuse surface, sampled from the environment
+    /// and weighted against sampling the same direction by the bounce from the surface.
+    fn illumination_from_environment(
+        &self,
+        environment: &dyn Environment,
+        surface: &SurfacePoint,
+        ray: &Ray,
+        material: &Material,
+        depth: u32,
+        sampler: &mut impl Sampler,
+    ) -> Color {
+        let (dir, pdf) = environment.sample(sampler.next_2d());
+        let cos = dir.dot(surface.normal);
+        if pdf <= 0. || cos <= 0. || dir.dot(surface.geometric_normal) <= 0. {
             return Color::black();
         }
+        if self.is_occluded(&surface.spawn(ray, dir)) {
+            return Color::black();
+        }
+        // Without further bounces this is the only way to reach the environment.
+        let weight = if depth >= self.max_depth {
+            1.
+        } else {
+            power_heuristic(pdf, cos / PI)
+        };
+        environment.radiance(dir) * (material.diffusion * cos / PI * weight / pdf)
+    }
 
-        let material = self.materials[id];
-        let ipoint = origin + dir * intersection.dist;
-        let mut illumination = 0.;
-        let normal = intersection.normal;
+    /// Radiance coming along the ray towards its origin.
+    pub fn ray_color(&self, ray: &Ray, sampler: &mut impl Sampler) -> Color {
+        self.trace(ray, 0, None, sampler)
+    }
+
+    /// Radiance coming along the ray after `depth` bounces. `bounce_pdf` is the probability
+    /// density of the direction of the ray if it continues a path from a diffuse surface, which
+    /// samples the environment directly as well.
+    fn trace(
+        &self,
+        ray: &Ray,
+        depth: u32,
+        bounce_pdf: Option<f32>,
+        sampler: &mut impl Sampler,
+    ) -> Color {
+        let (intersection, id) = self.find_intersection(ray);
+        if !intersection.exists() {
+            let environment = match self.environment {
+                Some(ref environment) => environment,
+                None => return Color::black(),
+            };
+            let weight = match bounce_pdf {
+                Some(bounce_pdf) => power_heuristic(bounce_pdf, environment.pdf(ray.dir)),
+                None => 1.,
+            };
+            return environment.radiance(ray.dir) * weight;
+        }
+
+        let dir = ray.dir;
+        let ipoint = ray.at(intersection.dist);
+        let material = &self.materials[id];
+        let shading_normal = material.shading_normal(&intersection, ipoint);
+        let material = &material.at(intersection.uv, ipoint, intersection.geometric_normal);
+        // Shapes can be hit from the inside, make the normals face the incoming ray.
+        let front_face = dir.dot(intersection.geometric_normal) < 0.;
+        let side = if front_face { 1. } else { -1. };
+        let surface = SurfacePoint {
+            point: ipoint,
+            normal: shading_normal * side,
+            geometric_normal: intersection.geometric_normal * side,
+        };
+        let normal = surface.normal;
+
+        match material.surface {
+            Surface::Diffuse => self.shade_diffuse(&surface, ray, material, depth, sampler),
+            Surface::Metal { roughness } => {
+                let mut reflected = reflect(dir, normal);
+                if roughness > 0. {
+                    let fuzz = uniform_sample_ball(sampler);
+                    reflected = (reflected + fuzz * roughness).normalize();
+                }
+                if reflected.dot(normal) <= 0. || reflected.dot(surface.geometric_normal) <= 0. {
+                    // Scattered below the surface.
+                    return Color::black();
+                }
+                self.trace_bounce(
+                    &surface.spawn(ray, reflected),
+                    material.color,
+                    depth,
+                    None,
+                    sampler,
+                )
+            }
+            Surface::Dielectric { ior } => {
+                let eta = if front_face { 1. / ior } else { ior };
+                let cos_i = -dir.dot(normal);
+                // Choose between reflection and refraction with the probability given by the
+                // Fresnel reflectance, so that the weight of the path stays the same.
+                let refracted = refract(dir, normal, eta)
+                    .filter(|_| sampler.next_1d() >= schlick_reflectance(cos_i, eta));
+                match refracted {
+                    Some(refracted) => self.trace_bounce(
+                        &surface.spawn(ray, refracted),
+                        material.color,
+                        depth,
+                        None,
+                        sampler,
+                    ),
+                    None => self.trace_bounce(
+                        &surface.spawn(ray, reflect(dir, normal)),
+                        material.color,
+                        depth,
+                        None,
+                        sampler,
+                    ),
+                }
+            }
+        }
+    }
+
+    /// Direct lighting is estimated by sampling the lights, indirect lighting by continuing the
+    /// path in a cosine-weighted random direction.
+    fn shade_diffuse(
+        &self,
+        surface: &SurfacePoint,
+        ray: &Ray,
+        material: &Material,
+        depth: u32,
+        sampler: &mut impl Sampler,
+    ) -> Color {
+        let mut illumination = Color::black();
 
         for light in self.point_lights.iter() {
-            illumination += self.illumination_from_light(
-                ipoint, normal, dir, &material, light, rng);
+            illumination += self.illumination_from_light(surface, ray, material, light, sampler);
         }
 
         for light in self.sphere_lights.iter() {
-            illumination += self.illumination_from_light(
-                ipoint,
-                normal,
-                dir,
-                &material,
-                light,
-                rng,
+            illumination += self.illumination_from_light(surface, ray, material, light, sampler);
+        }
+
+        for light in self.sun_lights.iter() {
+            illumination += self.illumination_from_light(surface, ray, material, light, sampler);
+        }
+
+        if let Some(ref environment) = self.environment {
+            illumination += self.illumination_from_environment(
+                environment.as_ref(),
+                surface,
+                ray,
+                material,
+                depth,
+                sampler,
             );
         }
 
+        // With cosine-weighted sampling the cosine term and the pdf cancel out, leaving just the
+        // albedo.
+        let albedo = material.color * material.diffusion;
+        let bounce_dir = cosine_sample_hemisphere(surface.normal, sampler);
+        if bounce_dir.dot(surface.geometric_normal) <= 0. {
+            // Sampled around a perturbed normal into the surface.
+            return material.color * illumination;
+        }
+        let bounce_pdf = bounce_dir.dot(surface.normal) / PI;
+        let bounce_ray = surface.spawn(ray, bounce_dir);
         material.color * illumination
+            + self.trace_bounce(&bounce_ray, albedo, depth, Some(bounce_pdf), sampler)
+    }
+
+    /// Continues the path from a surface hit after `depth` bounces. Returns the incoming radiance
+    /// multiplied by `weight`, or black if the path is terminated, either because it is too long
+    /// or by Russian roulette.
+    fn trace_bounce(
+        &self,
+        ray: &Ray,
+        weight: Color,
+        depth: u32,
+        bounce_pdf: Option<f32>,
+        sampler: &mut impl Sampler,
+    ) -> Color {
+        if depth >= self.max_depth {
+            return Color::black();
+        }
+
+        let mut survival = 1.;
+        if depth >= ROULETTE_DEPTH {
+            survival = weight.max_component().min(0.95);
+            if sampler.next_1d() >= survival {
+                return Color::black();
+            }
+        }
+
+        weight * self.trace(ray, depth + 1, bounce_pdf, sampler) * (1. / survival)
+    }
+}
+
+/// A point where a ray hit a surface, with the normal used for shading and the normal of the
+/// actual surface, both facing the ray.
+struct SurfacePoint {
+    point: Vec3,
+    normal: Vec3,
+    geometric_normal: Vec3,
+}
+
+impl SurfacePoint {
+    /// Ray continuing the path of `ray` from the point in the direction `dir`.
+    fn spawn(&self, ray: &Ray, dir: Vec3) -> Ray {
+        ray.spawn_off_surface(self.point, self.geometric_normal, dir)
+    }
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::vec3;
+    use rand::SeedableRng as _;
+    use std::f32::consts::FRAC_PI_2;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::environment::EnvironmentMap;
+    use crate::framebuffer::Framebuffer;
+    use crate::texture::ImageTexture;
+
+    /// A floor lit from above with a sphere floating over it. The bottom of the sphere faces away
+    /// from the light.
+    fn create_scene() -> Scene {
+        let mut scene = Scene::new();
+        scene.add_plane(
+            Plane::new(vec3(0., 0., 0.), vec3(0., 1., 0.)),
+            Material::new(0.8, 0.8, 0.8),
+        );
+        scene.add_sphere(
+            Sphere::new(vec3(0., 1., 0.), 0.5),
+            Material::new(0.75, 0.25, 0.25),
+        );
+        scene.add_point_light(vec3(0., 3., 0.), 5.);
+        scene
+    }
+
+    #[test]
+    fn direct_lighting_only() {
+        let mut scene = create_scene();
+        scene.set_max_depth(0);
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(239);
+        let color = scene.ray_color(&Ray::new(vec3(0., 0.1, 0.), Vec3::unit_y()), &mut rng);
+        assert_eq!(color.max_component(), 0.);
+    }
+
+    #[test]
+    fn indirect_lighting() {
+        let scene = create_scene();
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(239);
+        let mut color = Color::black();
+        for _ in 0..100 {
+            color += scene.ray_color(&Ray::new(vec3(0., 0.1, 0.), Vec3::unit_y()), &mut rng);
+        }
+        assert!(color.max_component() > 0.);
+    }
+
+    #[test]
+    fn colored_light() {
+        let mut scene = Scene::new();
+        scene.add_plane(
+            Plane::new(vec3(0., 0., 0.), vec3(0., 1., 0.)),
+            Material::new(0.8, 0.8, 0.8),
+        );
+        scene.add_point_light(vec3(0., 3., 0.), Color::new(2., 0., 0.5));
+        scene.set_max_depth(0);
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(239);
+        let [r, g, b] = scene
+            .ray_color(&Ray::new(vec3(0., 1., 0.), -Vec3::unit_y()), &mut rng)
+            .to_array();
+        assert!(r > 0.);
+        assert_eq!(g, 0.);
+        assert_relative_eq!(b, r / 4.);
+    }
+
+    #[test]
+    fn normal_mapped_floor() {
+        // The normal map tilts the normal of the floor far towards +x.
+        let mut normals = Framebuffer::new(1, 1);
+        normals.put_pixel(0, 0, Color::new(1., 0.5, 0.6));
+        let mut material = Material::new(0.8, 0.8, 0.8);
+        material.textures.normal_map = Some(Arc::new(ImageTexture::new(normals)));
+        let mut scene = Scene::new();
+        scene.add_plane(Plane::new(vec3(0., 0., 0.), vec3(0., 1., 0.)), material);
+        scene.set_max_depth(0);
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(239);
+        let ray = Ray::new(vec3(0., 1., 0.), -Vec3::unit_y());
+
+        // Lit at a grazing angle from above, where the perturbed normal faces the light.
+        scene.add_point_light(vec3(5., 0.5, 0.), 1.);
+        assert!(scene.ray_color(&ray, &mut rng).max_component() > 0.);
+
+        // A light below the floor stays hidden, even though the perturbed normal faces it.
+        scene.point_lights.clear();
+        scene.add_point_light(vec3(5., -0.5, 0.), 1.);
+        assert_eq!(scene.ray_color(&ray, &mut rng).max_component(), 0.);
+    }
+
+    #[test]
+    fn smooth_mesh_below_light() {
+        // A square facing +z with its vertex normals tilted far towards +x, and a light behind
+        // it that the interpolated normal faces.
+        let mesh = TriangleMesh::new(
+            vec![
+                vec3(-1., -1., 0.),
+                vec3(1., -1., 0.),
+                vec3(1., 1., 0.),
+                vec3(-1., 1., 0.),
+            ],
+            vec![[0, 1, 2], [0, 2, 3]],
+        )
+        .set_normals(vec![vec3(1., 0., 0.2); 4]);
+        let mut scene = Scene::new();
+        scene.add_mesh(mesh, Material::new(0.8, 0.8, 0.8));
+        scene.add_point_light(vec3(5., 0., -5.), 1.);
+        scene.set_max_depth(0);
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(239);
+        let ray = Ray::new(vec3(0., 0., 1.), -Vec3::unit_z());
+        assert_eq!(scene.ray_color(&ray, &mut rng).max_component(), 0.);
+    }
+
+    #[test]
+    fn environment_lighting() {
+        // A sphere in a uniform environment reflects the albedo times the radiance, whether the
+        // light comes from sampling the environment or from the bounces that escape.
+        let mut sky = Framebuffer::new(8, 4);
+        for y in 0..4 {
+            for x in 0..8 {
+                sky.put_pixel(x, y, Color::from(2.));
+            }
+        }
+        let mut scene = Scene::new();
+        scene.add_sphere(
+            Sphere::new(vec3(0., 0., -3.), 1.),
+            Material::new(0.5, 0.5, 0.5),
+        );
+        scene.set_environment(EnvironmentMap::new(sky));
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(239);
+
+        let miss = Ray::new(Vec3::zero(), Vec3::unit_z());
+        assert_eq!(scene.ray_color(&miss, &mut rng), Color::from(2.));
+        let n = 10000;
+        for &max_depth in [0, 5].iter() {
+            scene.set_max_depth(max_depth);
+            let mut sum = Color::black();
+            for _ in 0..n {
+                let dir = vec3(0.2, 0.1, -1.).normalize();
+                sum += scene.ray_color(&Ray::new(Vec3::zero(), dir), &mut rng);
+            }
+            assert_relative_eq!((sum * (1. / n as f32)).luminance(), 1., epsilon = 0.03);
+        }
+    }
+
+    #[test]
+    fn sun_shadow() {
+        // A sphere above the floor under the sun at the zenith, which is large enough for a
+        // wide penumbra around the edge of the shadow. Lit parts of the floor receive the full
+        // irradiance, up to the cosine across the disk of the sun.
+        let mut scene = Scene::new();
+        let mut floor = Material::new(1., 1., 1.);
+        floor.reflection = 0.;
+        scene.add_plane(Plane::new(Vec3::zero(), Vec3::unit_y()), floor);
+        scene.add_sphere(Sphere::new(vec3(0., 2., 0.), 1.), Material::new(1., 1., 1.));
+        let sun = SunLight::new(FRAC_PI_2, 0., 3.).set_angular_diameter(10f32.to_radians());
+        let irradiance = sun.intensity();
+        scene.add_sun_light(sun);
+        scene.set_max_depth(0);
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(239);
+        let mut mean_luminance = |x: f32| {
+            let ray = Ray::new(vec3(x, 0.5, 0.5), vec3(0., -1., -1.).normalize());
+            let n = 1000;
+            let mut sum = 0.;
+            for _ in 0..n {
+                sum += scene.ray_color(&ray, &mut rng).luminance();
+            }
+            // A white floor reflects the irradiance divided by pi.
+            sum / n as f32 / irradiance.luminance() * PI
+        };
+        assert_eq!(mean_luminance(0.), 0.);
+        assert_relative_eq!(mean_luminance(1.), 0.5, epsilon = 0.1);
+        assert_relative_eq!(mean_luminance(3.), 1., epsilon = 1E-2);
+    }
+
+    #[test]
+    fn mirror_reflection() {
+        // The top of the sphere seen directly and through a mirror on the ceiling.
+        let mut scene = create_scene();
+        scene.add_plane(
+            Plane::new(vec3(0., 2.5, 0.), vec3(0., -1., 0.)),
+            Material::metal(1., 1., 1., 0.),
+        );
+        scene.add_point_light(vec3(0., 2.2, 0.), 1.);
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(239);
+        scene.set_max_depth(0);
+        let direct = scene.ray_color(&Ray::new(vec3(0., 2., 0.), -Vec3::unit_y()), &mut rng);
+        scene.set_max_depth(1);
+        let reflected = scene.ray_color(&Ray::new(vec3(0., 2., 0.), Vec3::unit_y()), &mut rng);
+        assert!(direct.max_component() > 0.);
+        assert_relative_eq!(reflected.max_component(), direct.max_component());
+    }
+
+    /// A shape defined outside of the scene module: an infinite cylinder around the Y axis.
+    struct Cylinder {
+        radius: f32,
+    }
+
+    impl Shape for Cylinder {
+        fn ray_intersect(&self, ray: &Ray) -> Intersection {
+            let flat_origin = vec3(ray.origin.x, 0., ray.origin.z);
+            let flat_dir = vec3(ray.dir.x, 0., ray.dir.z);
+            let a = flat_dir.length_squared();
+            let b = flat_origin.dot(flat_dir);
+            let c = flat_origin.length_squared() - self.radius * self.radius;
+            let discriminant = b * b - a * c;
+            if a == 0. || discriminant < 0. {
+                return Intersection::new_empty();
+            }
+            let dist = (-b - discriminant.sqrt()) / a;
+            if !ray.contains(dist) {
+                return Intersection::new_empty();
+            }
+            let point = ray.at(dist);
+            Intersection::new(dist, vec3(point.x, 0., point.z).normalize())
+        }
+    }
+
+    #[test]
+    fn add_custom_object() {
+        let mut scene = create_scene();
+        let id = scene.add_object(Cylinder { radius: 1. }, Material::new(0.2, 0.2, 0.8));
+        let ray = Ray::new(vec3(0., 0.5, 5.), -Vec3::unit_z());
+        let (intersection, hit_id) = scene.find_intersection(&ray);
+        assert_eq!(hit_id, id);
+        assert_relative_eq!(intersection.dist, 4.);
     }
 }
@@ -0,0 +1,1580 @@
+//! A simple line-based text format for describing scenes.
+//!
+//! Every non-empty line is a statement: a keyword followed by a list of named properties, each
+//! with a fixed number of values. Everything after `#` is a comment. Angles are in degrees. File
+//! names are relative to the directory of the scene file.
+//!
+//! ```text
+//! camera eye 0 0 0 target 0 0 -1 up 0 1 0 fov 90 size 1280 720 samples 100
+//! max_depth 5
+//! material red color 0.75 0.25 0.25 diffusion 1 reflection 3 shininess 10
+//! material gold type metal color 1 0.8 0.4 roughness 0.1
+//! material glass type dielectric ior 1.5
+//! sphere center 0 0 -3 radius 1 material red
+//! plane point 0 -1 0 normal 0 1 0 material red
+//! triangle v0 -1 0 -5 v1 1 0 -5 v2 0 1 -5 material red
+//! obj file teapot.obj material red translate 0 -1 -5 scale 0.5
+//! point_light position 0 0.1 3.5 intensity 3 color 1 0.9 0.8
+//! sphere_light center 2 1 0 radius 0.5 intensity 3 temperature 3200
+//! ```
+//!
+//! The `intensity` of point and sphere lights is their radiant intensity: a white diffuse surface
+//! facing a light of intensity π at distance 1 has radiance 1. Scenes from before the diffuse
+//! light was normalized by 1/π need their intensities multiplied by π to look the same.
+//!
+//! Adaptive sampling is enabled by the `adaptive_threshold` camera property, with `samples` as
+//! the maximum and `min_samples` as the minimum number of samples per pixel. The `sampler`
+//! camera property selects the sample generator: `independent`, `stratified`, `halton`, `sobol`
+//! or `bluenoise`. The `filter` camera property selects the pixel reconstruction filter: `box`
+//! (default), `tent`, `gaussian`, `mitchell` or `lanczos`, with its usual radius unless
+//! `filter_radius` is given in pixels.
+//!
+//! Depth of field is enabled by the `aperture_radius` camera property. The camera is focused on
+//! the target unless `focus_distance` is given, and `aperture_blades` makes the aperture a
+//! polygon with that many sides instead of a disk.
+//!
+//! The `projection` camera property is `perspective` (default), `orthographic`, `fisheye` or
+//! `equirectangular`. The orthographic projection requires `ortho_width`, the width of the view
+//! in scene units.
+//!
+//! Motion blur is enabled by the `shutter` camera property, the times at which the shutter opens
+//! and closes. A sphere with `center_end` moves linearly from `center` at time 0 to `center_end`
+//! at time 1.
+//!
+//! Animations are described by keyframes. `keyframe` statements add keys to a named motion,
+//! which any object or light can follow with the `motion` property once all its keys are given.
+//! The transform of a motion rotates by Euler angles about x, y and z, scales and translates,
+//! relative to the origin of the scene. Translation, rotation and scale are interpolated
+//! separately between the keys that give them. `camera_keyframe` statements animate the camera,
+//! and the `frames` statement sets the number of frames and the frame rate (default 24 per
+//! second). The camera shutter is relative to the time of each frame.
+//!
+//! ```text
+//! keyframe spin time 0 rotate 0 0 0
+//! keyframe spin time 4 rotate 0 360 0
+//! obj file teapot.obj material red motion spin
+//! camera_keyframe time 0 eye 0 1 5 fov 60
+//! camera_keyframe time 4 eye 0 3 5 fov 40
+//! frames count 96 rate 24
+//! ```
+//!
+//! `texture` statements name textures that vary the parameters of a material through its
+//! `color_texture`, `diffusion_texture`, `reflection_texture`, `shininess_texture` and
+//! `roughness_texture` properties. The color is replaced by the texture, and scalar parameters
+//! are multiplied by its luminance, with `roughness` being 1 by default then. An `image`
+//! texture is tiled `scale` times across the texture coordinates, with `wrap` being `repeat`
+//! (default) or `clamp`. The procedural types `checker`, `noise`, `fbm`, `turbulence`,
+//! `marble`, `wood` and `voronoi` blend from `color1` (default black) to `color2` (default
+//! white). They are evaluated at the surface point, or at the texture coordinates with
+//! `space uv`, times `frequency`. `octaves` sets the number of noise layers.
+//!
+//! The `normal_map` material property names a texture of tangent-space normals, which should be
+//! an image with `encoding linear` to keep its values undecoded. `bump_map` names a texture of
+//! heights, scaled by `bump_scale`.
+//!
+//! ```text
+//! texture bricks type image file bricks.png scale 4 4
+//! texture tiles type checker color1 0.9 0.9 0.9 color2 0.2 0.2 0.2 space uv frequency 2
+//! material wall color_texture bricks
+//! material floor color_texture tiles
+//! ```
+//!
+//! The `environment` statement surrounds the scene with an equirectangular image, usually a
+//! `.hdr` or `.pfm` panorama, which lights the scene and is seen by the rays that escape it. Its
+//! center is in the direction of -z, and `intensity` multiplies its radiance.
+//!
+//! ```text
+//! environment file sky.hdr intensity 2
+//! ```
+//!
+//! The `sky` statement is an analytic daylight sky instead, with a sun that casts soft shadows.
+//! The sun is at `elevation` degrees above the horizon and at `azimuth` degrees from -z towards
+//! +x (default 0). `turbidity` goes from 2 for a clear sky to 10 for haze (default 3).
+//! `intensity` and `sun_intensity` multiply the brightness of the sky and the sun, and
+//! `sun_diameter` is the angular diameter of the sun (default 0.53).
+//!
+//! ```text
+//! sky elevation 30 azimuth 45 turbidity 2.5
+//! ```
+
+use glam::{Mat4, Quat, Vec2, Vec3};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::animation::{Animated, Animation, Keyframes, Transform, TransformKeyframes};
+use crate::camera::{Camera, Projection};
+use crate::environment::EnvironmentMap;
+use crate::filter::{Filter, FilterType};
+use crate::material::{Color, Material, MaterialTextures, Surface};
+use crate::obj::load_obj;
+use crate::plane::Plane;
+use crate::procedural::ProceduralTexture;
+use crate::scene::Scene;
+use crate::shape::Shape;
+use crate::sky::{Sky, SunLight};
+use crate::sphere::Sphere;
+use crate::texture::{ImageTexture, Texture};
+use crate::triangle::Triangle;
+
+/// An error in the scene description, with the 1-based number of the line where it occurred.
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl ParseError {
+    pub(crate) fn new(line: usize, message: impl Into<String>) -> Self {
+        ParseError {
+            line,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, ParseError),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(path, err) => write!(f, "{}: {}", path.display(), err),
+            LoadError::Parse(path, err) => {
+                write!(f, "{}:{}: {}", path.display(), err.line, err.message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadError::Io(_, err) => Some(err),
+            LoadError::Parse(_, err) => Some(err),
+        }
+    }
+}
+
+/// Reads the scene and the camera from a scene file. The camera is the one of the first frame
+/// of the animation.
+pub fn load_scene(path: impl AsRef<Path>) -> Result<(Scene, Camera), LoadError> {
+    let (scene, camera, animation) = load_animation(path)?;
+    let camera = animation.frame_camera(&camera, 1);
+    Ok((scene, camera))
+}
+
+/// Parses the scene and the camera from the text of a scene file. Files referenced by the scene
+/// are looked up relative to the current directory.
+pub fn parse_scene(text: &str) -> Result<(Scene, Camera), ParseError> {
+    let (scene, camera, animation) = parse_animation(text)?;
+    let camera = animation.frame_camera(&camera, 1);
+    Ok((scene, camera))
+}
+
+/// Reads the scene, the camera and the animation from a scene file. The camera is not animated,
+/// the camera of each frame is given by `Animation::frame_camera`.
+pub fn load_animation(path: impl AsRef<Path>) -> Result<(Scene, Camera, Animation), LoadError> {
+    let path = path.as_ref();
+    let text =
+        std::fs::read_to_string(path).map_err(|err| LoadError::Io(path.to_path_buf(), err))?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    parse_scene_in_dir(&text, dir).map_err(|err| LoadError::Parse(path.to_path_buf(), err))
+}
+
+/// Parses the scene, the camera and the animation from the text of a scene file, like
+/// `load_animation`.
+pub fn parse_animation(text: &str) -> Result<(Scene, Camera, Animation), ParseError> {
+    parse_scene_in_dir(text, Path::new(""))
+}
+
+/// Keyframes of a named motion, collected from the `keyframe` statements.
+#[derive(Default)]
+struct MotionKeys {
+    translation: Vec<(f32, Vec3)>,
+    rotation: Vec<(f32, Vec3)>,
+    scale: Vec<(f32, Vec3)>,
+    /// Whether an object follows the motion already, so that no more keys can be added.
+    used: bool,
+}
+
+impl MotionKeys {
+    fn keyframes(&self) -> TransformKeyframes {
+        let keyframes = |keys: &Vec<(f32, Vec3)>, default: Vec3| match keys.len() {
+            0 => Keyframes::constant(default),
+            _ => Keyframes::new(keys.clone()),
+        };
+        TransformKeyframes {
+            translation: keyframes(&self.translation, Vec3::zero()),
+            rotation: keyframes(&self.rotation, Vec3::zero()),
+            scale: keyframes(&self.scale, Vec3::one()),
+        }
+    }
+}
+
+/// Keyframes of the camera settings, collected from the `camera_keyframe` statements.
+#[derive(Default)]
+struct CameraKeys {
+    eye: Vec<(f32, Vec3)>,
+    target: Vec<(f32, Vec3)>,
+    fov: Vec<(f32, f32)>,
+}
+
+/// Adds a shape to the scene, moving it if it has a motion.
+fn add_shape(
+    scene: &mut Scene,
+    shape: impl Shape + 'static,
+    material: Material,
+    motion: Option<TransformKeyframes>,
+) {
+    match motion {
+        Some(motion) => scene.add_object(Animated::new(shape, motion), material),
+        None => scene.add_object(shape, material),
+    };
+}
+
+fn parse_scene_in_dir(text: &str, dir: &Path) -> Result<(Scene, Camera, Animation), ParseError> {
+    let mut scene = Scene::new();
+    let mut camera = None;
+    let mut materials = HashMap::new();
+    let mut textures: HashMap<String, Arc<dyn Texture>> = HashMap::new();
+    let mut motions: HashMap<String, MotionKeys> = HashMap::new();
+    let mut camera_keys = CameraKeys::default();
+    let mut frames = None;
+    let mut has_environment = false;
+
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let line = match line.find('#') {
+            Some(pos) => &line[..pos],
+            None => line,
+        };
+        let mut tokens = line.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let tokens: Vec<&str> = tokens.collect();
+        let mut statement = Statement {
+            line: line_number,
+            keyword,
+            tokens: &tokens,
+        };
+
+        match keyword {
+            "camera" => {
+                if camera.is_some() {
+                    return Err(ParseError::new(line_number, "duplicate camera"));
+                }
+                camera = Some(statement.camera()?);
+            }
+            "max_depth" => scene.set_max_depth(statement.single_u32()?),
+            "material" => {
+                let name = statement.name()?;
+                let props = statement.properties(&[
+                    ("color", 3),
+                    ("diffusion", 1),
+                    ("reflection", 1),
+                    ("shininess", 1),
+                    ("type", 1),
+                    ("roughness", 1),
+                    ("ior", 1),
+                    ("color_texture", 1),
+                    ("diffusion_texture", 1),
+                    ("reflection_texture", 1),
+                    ("shininess_texture", 1),
+                    ("roughness_texture", 1),
+                    ("normal_map", 1),
+                    ("bump_map", 1),
+                    ("bump_scale", 1),
+                ])?;
+                let color = props.vec3("color")?.unwrap_or_else(|| Vec3::splat(1.));
+                let mut material = Material::new(color.x, color.y, color.z);
+                material.surface = props.surface()?;
+                if let Some(diffusion) = props.f32("diffusion")? {
+                    material.diffusion = diffusion;
+                }
+                if let Some(reflection) = props.f32("reflection")? {
+                    material.reflection = reflection;
+                }
+                if let Some(shininess) = props.f32("shininess")? {
+                    material.shininess = shininess;
+                }
+                material.textures = MaterialTextures {
+                    color: props.texture("color_texture", &textures)?,
+                    diffusion: props.texture("diffusion_texture", &textures)?,
+                    reflection: props.texture("reflection_texture", &textures)?,
+                    shininess: props.texture("shininess_texture", &textures)?,
+                    roughness: props.texture("roughness_texture", &textures)?,
+                    normal_map: props.texture("normal_map", &textures)?,
+                    bump_map: props.texture("bump_map", &textures)?,
+                };
+                if let Some(bump_scale) = props.f32("bump_scale")? {
+                    material.bump_scale = bump_scale;
+                }
+                if materials.insert(name.to_string(), material).is_some() {
+                    return Err(ParseError::new(
+                        line_number,
+                        format!("duplicate material '{}'", name),
+                    ));
+                }
+            }
+            "texture" => {
+                let name = statement.name()?;
+                let texture = statement.texture(dir)?;
+                if textures.insert(name.to_string(), texture).is_some() {
+                    return Err(ParseError::new(
+                        line_number,
+                        format!("duplicate texture '{}'", name),
+                    ));
+                }
+            }
+            "sphere" => {
+                let props = statement.properties(&[
+                    ("center", 3),
+                    ("center_end", 3),
+                    ("radius", 1),
+                    ("material", 1),
+                    ("motion", 1),
+                ])?;
+                let center = props.required_vec3("center")?;
+                let radius = props.required_positive_f32("radius")?;
+                let material = props.material(&materials)?;
+                let motion = props.motion(&mut motions)?;
+                match props.vec3("center_end")? {
+                    Some(_) if motion.is_some() => {
+                        return Err(ParseError::new(
+                            line_number,
+                            "'center_end' and 'motion' are mutually exclusive",
+                        ));
+                    }
+                    Some(center_end) => {
+                        let path = Keyframes::new(vec![
+                            (0., Transform::from_translation(center)),
+                            (1., Transform::from_translation(center_end)),
+                        ]);
+                        let sphere = Sphere::new(Vec3::zero(), radius);
+                        scene.add_object(Animated::new(sphere, path), material);
+                    }
+                    None => add_shape(&mut scene, Sphere::new(center, radius), material, motion),
+                }
+            }
+            "plane" => {
+                let props = statement.properties(&[
+                    ("point", 3),
+                    ("normal", 3),
+                    ("material", 1),
+                    ("motion", 1),
+                ])?;
+                let normal = props.required_vec3("normal")?;
+                let length_squared = normal.length_squared();
+                if !(length_squared > 0. && length_squared.is_finite()) {
+                    return Err(ParseError::new(line_number, "'normal' must not be zero"));
+                }
+                let plane = Plane::new(props.required_vec3("point")?, normal);
+                let material = props.material(&materials)?;
+                add_shape(&mut scene, plane, material, props.motion(&mut motions)?);
+            }
+            "triangle" => {
+                let props = statement.properties(&[
+                    ("v0", 3),
+                    ("v1", 3),
+                    ("v2", 3),
+                    ("material", 1),
+                    ("motion", 1),
+                ])?;
+                let triangle = Triangle::new(
+                    props.required_vec3("v0")?,
+                    props.required_vec3("v1")?,
+                    props.required_vec3("v2")?,
+                );
+                let material = props.material(&materials)?;
+                add_shape(&mut scene, triangle, material, props.motion(&mut motions)?);
+            }
+            "obj" => {
+                let props = statement.properties(&[
+                    ("file", 1),
+                    ("material", 1),
+                    ("translate", 3),
+                    ("scale", 1),
+                    ("motion", 1),
+                ])?;
+                let file = props.get("file").ok_or_else(|| props.missing("file"))?[0];
+                let default_material = match props.get("material") {
+                    Some(_) => props.material(&materials)?,
+                    None => Material::new(0.8, 0.8, 0.8),
+                };
+                let transform = Mat4::from_scale_rotation_translation(
+                    Vec3::splat(props.f32("scale")?.unwrap_or(1.)),
+                    Quat::identity(),
+                    props.vec3("translate")?.unwrap_or_else(Vec3::zero),
+                );
+                let meshes = load_obj(dir.join(file), default_material)
+                    .map_err(|err| ParseError::new(line_number, err.to_string()))?;
+                let motion = props.motion(&mut motions)?;
+                for (mesh, material) in meshes {
+                    add_shape(
+                        &mut scene,
+                        mesh.transform(transform),
+                        material,
+                        motion.clone(),
+                    );
+                }
+            }
+            "point_light" => {
+                let props = statement.properties(&[
+                    ("position", 3),
+                    ("intensity", 1),
+                    ("color", 3),
+                    ("temperature", 1),
+                    ("motion", 1),
+                ])?;
+                let position = props.required_vec3("position")?;
+                let intensity = props.light_intensity()?;
+                match props.motion(&mut motions)? {
+                    Some(motion) => scene.add_moving_point_light(position, intensity, motion),
+                    None => scene.add_point_light(position, intensity),
+                }
+            }
+            "sphere_light" => {
+                let props = statement.properties(&[
+                    ("center", 3),
+                    ("radius", 1),
+                    ("intensity", 1),
+                    ("color", 3),
+                    ("temperature", 1),
+                    ("motion", 1),
+                ])?;
+                let center = props.required_vec3("center")?;
+                let radius = props.required_positive_f32("radius")?;
+                let intensity = props.light_intensity()?;
+                match props.motion(&mut motions)? {
+                    Some(motion) => {
+                        scene.add_moving_sphere_light(center, radius, intensity, motion)
+                    }
+                    None => scene.add_sphere_light(center, radius, intensity),
+                }
+            }
+            "keyframe" => {
+                let name = statement.name()?;
+                let props = statement.properties(&[
+                    ("time", 1),
+                    ("translate", 3),
+                    ("rotate", 3),
+                    ("scale", 1),
+                ])?;
+                let time = props.keyframe_time()?;
+                let keys = motions.entry(name.to_string()).or_default();
+                if keys.used {
+                    return Err(ParseError::new(
+                        line_number,
+                        format!("keyframe for motion '{}' after it is used", name),
+                    ));
+                }
+                if let Some(translation) = props.vec3("translate")? {
+                    keys.translation.push((time, translation));
+                }
+                if let Some(angles) = props.vec3("rotate")? {
+                    let radians = Vec3::new(
+                        angles.x.to_radians(),
+                        angles.y.to_radians(),
+                        angles.z.to_radians(),
+                    );
+                    keys.rotation.push((time, radians));
+                }
+                if let Some(scale) = props.f32("scale")? {
+                    keys.scale.push((time, Vec3::splat(scale)));
+                }
+            }
+            "camera_keyframe" => {
+                let props =
+                    statement.properties(&[("time", 1), ("eye", 3), ("target", 3), ("fov", 1)])?;
+                let time = props.keyframe_time()?;
+                if let Some(eye) = props.vec3("eye")? {
+                    camera_keys.eye.push((time, eye));
+                }
+                if let Some(target) = props.vec3("target")? {
+                    camera_keys.target.push((time, target));
+                }
+                if let Some(fov) = props.f32("fov")? {
+                    camera_keys.fov.push((time, fov.to_radians()));
+                }
+            }
+            "environment" => {
+                if has_environment {
+                    return Err(ParseError::new(line_number, "duplicate environment"));
+                }
+                let props = statement.properties(&[("file", 1), ("intensity", 1)])?;
+                let file = props.get("file").ok_or_else(|| props.missing("file"))?[0];
+                let intensity = props
+                    .checked_f32("intensity", check_non_negative)?
+                    .unwrap_or(1.);
+                let environment = EnvironmentMap::load(dir.join(file)).map_err(|err| {
+                    ParseError::new(line_number, format!("cannot load '{}': {}", file, err))
+                })?;
+                scene.set_environment(environment.set_intensity(intensity));
+                has_environment = true;
+            }
+            "sky" => {
+                if has_environment {
+                    return Err(ParseError::new(line_number, "duplicate environment"));
+                }
+                let props = statement.properties(&[
+                    ("elevation", 1),
+                    ("azimuth", 1),
+                    ("turbidity", 1),
+                    ("intensity", 1),
+                    ("sun_intensity", 1),
+                    ("sun_diameter", 1),
+                ])?;
+                let elevation = props.required_f32("elevation")?;
+                let azimuth = props.checked_f32("azimuth", check_finite)?.unwrap_or(0.);
+                let turbidity = props.f32("turbidity")?.unwrap_or(3.);
+                let sun_diameter = props.f32("sun_diameter")?.unwrap_or(0.53);
+                if !(0. ..=90.).contains(&elevation) {
+                    return Err(ParseError::new(
+                        line_number,
+                        "'elevation' must be between 0 and 90",
+                    ));
+                }
+                if !(2. ..=10.).contains(&turbidity) {
+                    return Err(ParseError::new(
+                        line_number,
+                        "'turbidity' must be between 2 and 10",
+                    ));
+                }
+                if !(0. ..=180.).contains(&sun_diameter) {
+                    return Err(ParseError::new(
+                        line_number,
+                        "'sun_diameter' must be between 0 and 180",
+                    ));
+                }
+                let (elevation, azimuth) = (elevation.to_radians(), azimuth.to_radians());
+                let intensity = props
+                    .checked_f32("intensity", check_non_negative)?
+                    .unwrap_or(1.);
+                scene.set_environment(
+                    Sky::new(elevation, azimuth, turbidity).set_intensity(intensity),
+                );
+                let sun_intensity = props
+                    .checked_f32("sun_intensity", check_non_negative)?
+                    .unwrap_or(1.);
+                if sun_intensity > 0. {
+                    scene.add_sun_light(
+                        SunLight::new(elevation, azimuth, turbidity)
+                            .set_angular_diameter(sun_diameter.to_radians())
+                            .set_intensity(sun_intensity),
+                    );
+                }
+                has_environment = true;
+            }
+            "frames" => {
+                if frames.is_some() {
+                    return Err(ParseError::new(line_number, "duplicate frames"));
+                }
+                let props = statement.properties(&[("count", 1), ("rate", 1)])?;
+                let count = match props.get("count") {
+                    Some(count) => parse_number(line_number, count[0])?,
+                    None => return Err(props.missing("count")),
+                };
+                let rate = props.checked_f32("rate", check_positive)?.unwrap_or(24.);
+                if count == 0 {
+                    return Err(ParseError::new(line_number, "'count' must be positive"));
+                }
+                frames = Some((count, rate));
+            }
+            _ => {
+                return Err(ParseError::new(
+                    line_number,
+                    format!("unknown statement '{}'", keyword),
+                ))
+            }
+        }
+    }
+
+    let mut animation = Animation::new();
+    if let Some((count, rate)) = frames {
+        animation = animation.set_frames(count, rate);
+    }
+    if !camera_keys.eye.is_empty() {
+        animation = animation.set_eye(Keyframes::new(camera_keys.eye));
+    }
+    if !camera_keys.target.is_empty() {
+        animation = animation.set_target(Keyframes::new(camera_keys.target));
+    }
+    if !camera_keys.fov.is_empty() {
+        animation = animation.set_fov(Keyframes::new(camera_keys.fov));
+    }
+    Ok((scene, camera.unwrap_or_default(), animation))
+}
+
+/// The tokens of a single statement that haven't been consumed yet.
+struct Statement<'a> {
+    line: usize,
+    keyword: &'a str,
+    tokens: &'a [&'a str],
+}
+
+impl<'a> Statement<'a> {
+    fn error(&self, message: impl Into<String>) -> ParseError {
+        ParseError::new(self.line, message)
+    }
+
+    /// Consumes the name that follows the keyword.
+    fn name(&mut self) -> Result<&'a str, ParseError> {
+        match self.tokens.split_first() {
+            Some((name, rest)) => {
+                self.tokens = rest;
+                Ok(name)
+            }
+            None => Err(self.error(format!("'{}' requires a name", self.keyword))),
+        }
+    }
+
+    fn single_u32(&self) -> Result<u32, ParseError> {
+        match self.tokens {
+            [value] => parse_number(self.line, value),
+            _ => Err(self.error(format!("'{}' requires a single value", self.keyword))),
+        }
+    }
+
+    /// Splits the remaining tokens into properties. `arities` lists the allowed property names
+    /// with the number of values each of them takes.
+    fn properties(&self, arities: &[(&str, usize)]) -> Result<Properties<'a>, ParseError> {
+        let mut values = HashMap::new();
+        let mut tokens = self.tokens;
+        while let Some((&name, rest)) = tokens.split_first() {
+            let arity = match arities.iter().find(|(n, _)| *n == name) {
+                Some(&(_, arity)) => arity,
+                None => {
+                    return Err(self.error(format!(
+                        "unknown property '{}' for '{}'",
+                        name, self.keyword
+                    )))
+                }
+            };
+            if rest.len() < arity {
+                return Err(self.error(format!("'{}' requires {} values", name, arity)));
+            }
+            if values.insert(name, &rest[..arity]).is_some() {
+                return Err(self.error(format!("duplicate property '{}'", name)));
+            }
+            tokens = &rest[arity..];
+        }
+        Ok(Properties {
+            line: self.line,
+            keyword: self.keyword,
+            values,
+        })
+    }
+
+    fn camera(&self) -> Result<Camera, ParseError> {
+        let props = self.properties(&[
+            ("eye", 3),
+            ("target", 3),
+            ("up", 3),
+            ("fov", 1),
+            ("size", 2),
+            ("samples", 1),
+            ("min_samples", 1),
+            ("adaptive_threshold", 1),
+            ("sampler", 1),
+            ("filter", 1),
+            ("filter_radius", 1),
+            ("aperture_radius", 1),
+            ("aperture_blades", 1),
+            ("focus_distance", 1),
+            ("projection", 1),
+            ("ortho_width", 1),
+            ("shutter", 2),
+        ])?;
+        let mut camera = Camera::new();
+        if let Some(eye) = props.vec3("eye")? {
+            camera = camera.set_eye(eye);
+        }
+        if let Some(target) = props.vec3("target")? {
+            camera = camera.set_target(target);
+        }
+        if let Some(up) = props.vec3("up")? {
+            camera = camera.set_up(up);
+        }
+        if let Some(fov) = props.f32("fov")? {
+            camera = camera.set_fov(fov.to_radians());
+        }
+        if let Some(size) = props.get("size") {
+            camera = camera.set_dimensions(
+                parse_number(self.line, size[0])?,
+                parse_number(self.line, size[1])?,
+            );
+        }
+        if let Some(samples) = props.get("samples") {
+            match parse_number(self.line, samples[0])? {
+                0 => return Err(self.error("'samples' must be positive")),
+                samples => camera = camera.set_samples(samples),
+            }
+        }
+        if let Some(min_samples) = props.get("min_samples") {
+            match parse_number(self.line, min_samples[0])? {
+                0 => return Err(self.error("'min_samples' must be positive")),
+                min_samples => camera = camera.set_min_samples(min_samples),
+            }
+        }
+        if let Some(threshold) = props.checked_f32("adaptive_threshold", check_non_negative)? {
+            camera = camera.set_adaptive_threshold(threshold);
+        }
+        if let Some(sampler) = props.get("sampler") {
+            camera = camera.set_sampler(
+                sampler[0]
+                    .parse()
+                    .map_err(|err| ParseError::new(self.line, err))?,
+            );
+        }
+        let filter_type = match props.get("filter") {
+            Some(filter) => filter[0]
+                .parse()
+                .map_err(|err| ParseError::new(self.line, err))?,
+            None => FilterType::Box,
+        };
+        let filter_radius = props
+            .checked_f32("filter_radius", check_positive)?
+            .unwrap_or_else(|| filter_type.default_radius());
+        camera = camera.set_filter(Filter::new(filter_type, filter_radius));
+        if let Some(radius) = props.checked_f32("aperture_radius", check_non_negative)? {
+            camera = camera.set_aperture_radius(radius);
+        }
+        if let Some(blades) = props.get("aperture_blades") {
+            match parse_number(self.line, blades[0])? {
+                blades @ (0 | 3..) => camera = camera.set_aperture_blades(blades),
+                _ => return Err(self.error("'aperture_blades' must be 0 or at least 3")),
+            }
+        }
+        if let Some(distance) = props.checked_f32("focus_distance", check_positive)? {
+            camera = camera.set_focus_distance(distance);
+        }
+        let ortho_width = props.f32("ortho_width")?;
+        let projection = props
+            .get("projection")
+            .map_or("perspective", |name| name[0]);
+        if ortho_width.is_some() && projection != "orthographic" {
+            return Err(self.error("'ortho_width' is only allowed for projection 'orthographic'"));
+        }
+        camera = camera.set_projection(match projection {
+            "perspective" => Projection::Perspective,
+            "orthographic" => match ortho_width {
+                Some(width) if width > 0. => Projection::Orthographic { width },
+                Some(_) => return Err(self.error("'ortho_width' must be positive")),
+                None => return Err(props.missing("ortho_width")),
+            },
+            "fisheye" => Projection::Fisheye,
+            "equirectangular" => Projection::Equirectangular,
+            _ => return Err(self.error(format!("unknown projection '{}'", projection))),
+        });
+        if let Some(shutter) = props.get("shutter") {
+            let time = |token| {
+                check_finite("shutter", parse_number(self.line, token)?)
+                    .map_err(|err| self.error(err))
+            };
+            let (open, close) = (time(shutter[0])?, time(shutter[1])?);
+            if close < open {
+                return Err(self.error("'shutter' must not close before it opens"));
+            }
+            camera = camera.set_shutter(open, close);
+        }
+        Ok(camera)
+    }
+
+    fn texture(&self, dir: &Path) -> Result<Arc<dyn Texture>, ParseError> {
+        let props = self.properties(&[
+            ("type", 1),
+            ("file", 1),
+            ("wrap", 1),
+            ("scale", 2),
+            ("encoding", 1),
+            ("color1", 3),
+            ("color2", 3),
+            ("frequency", 1),
+            ("space", 1),
+            ("octaves", 1),
+        ])?;
+        let texture_type = props.get("type").ok_or_else(|| props.missing("type"))?[0];
+        let image_properties = ["file", "wrap", "scale", "encoding"];
+        let pattern_properties = ["color1", "color2", "frequency", "space", "octaves"];
+        let other_properties: &[&str] = match texture_type {
+            "image" => &pattern_properties,
+            _ => &image_properties,
+        };
+        if let Some(name) = other_properties
+            .iter()
+            .find(|&&name| props.get(name).is_some())
+        {
+            return Err(self.error(format!(
+                "'{}' is not allowed for texture type '{}'",
+                name, texture_type
+            )));
+        }
+        match texture_type {
+            "image" => {
+                let file = props.get("file").ok_or_else(|| props.missing("file"))?[0];
+                let load = match props.get("encoding").map_or("srgb", |values| values[0]) {
+                    "srgb" => ImageTexture::load,
+                    "linear" => ImageTexture::load_raw,
+                    encoding => return Err(self.error(format!("unknown encoding '{}'", encoding))),
+                };
+                let mut texture = load(dir.join(file))
+                    .map_err(|err| self.error(format!("cannot load '{}': {}", file, err)))?;
+                if let Some(wrap) = props.get("wrap") {
+                    texture = texture.set_wrap(wrap[0].parse().map_err(|err| self.error(err))?);
+                }
+                if let Some(scale) = props.get("scale") {
+                    texture = texture.set_scale(Vec2::new(
+                        parse_number(self.line, scale[0])?,
+                        parse_number(self.line, scale[1])?,
+                    ));
+                }
+                Ok(Arc::new(texture))
+            }
+            _ => {
+                let pattern = texture_type
+                    .parse()
+                    .map_err(|_| self.error(format!("unknown texture type '{}'", texture_type)))?;
+                let color = |name, default| -> Result<Color, ParseError> {
+                    let color = props.vec3(name)?;
+                    Ok(color.map_or(default, |color| Color::new(color.x, color.y, color.z)))
+                };
+                let mut texture = ProceduralTexture::new(
+                    pattern,
+                    color("color1", Color::black())?,
+                    color("color2", Color::from(1.))?,
+                );
+                if let Some(frequency) = props.f32("frequency")? {
+                    texture = texture.set_frequency(frequency);
+                }
+                if let Some(space) = props.get("space") {
+                    texture = texture.set_space(space[0].parse().map_err(|err| self.error(err))?);
+                }
+                if let Some(octaves) = props.get("octaves") {
+                    match parse_number(self.line, octaves[0])? {
+                        0 => return Err(self.error("'octaves' must be positive")),
+                        octaves => texture = texture.set_octaves(octaves),
+                    }
+                }
+                Ok(Arc::new(texture))
+            }
+        }
+    }
+}
+
+struct Properties<'a> {
+    line: usize,
+    keyword: &'a str,
+    values: HashMap<&'a str, &'a [&'a str]>,
+}
+
+impl<'a> Properties<'a> {
+    fn get(&self, name: &str) -> Option<&'a [&'a str]> {
+        self.values.get(name).copied()
+    }
+
+    fn missing(&self, name: &str) -> ParseError {
+        ParseError::new(
+            self.line,
+            format!("'{}' requires property '{}'", self.keyword, name),
+        )
+    }
+
+    fn f32(&self, name: &str) -> Result<Option<f32>, ParseError> {
+        self.get(name)
+            .map(|values| parse_number(self.line, values[0]))
+            .transpose()
+    }
+
+    fn vec3(&self, name: &str) -> Result<Option<Vec3>, ParseError> {
+        self.get(name)
+            .map(|values| {
+                Ok(Vec3::new(
+                    parse_number(self.line, values[0])?,
+                    parse_number(self.line, values[1])?,
+                    parse_number(self.line, values[2])?,
+                ))
+            })
+            .transpose()
+    }
+
+    /// The value of the property `name` if there is one, after checking it with `check`.
+    fn checked_f32(
+        &self,
+        name: &str,
+        check: fn(&str, f32) -> Result<f32, String>,
+    ) -> Result<Option<f32>, ParseError> {
+        self.f32(name)?
+            .map(|value| check(name, value).map_err(|err| ParseError::new(self.line, err)))
+            .transpose()
+    }
+
+    fn required_f32(&self, name: &str) -> Result<f32, ParseError> {
+        self.f32(name)?.ok_or_else(|| self.missing(name))
+    }
+
+    fn required_positive_f32(&self, name: &str) -> Result<f32, ParseError> {
+        self.checked_f32(name, check_positive)?
+            .ok_or_else(|| self.missing(name))
+    }
+
+    /// The `time` of a keyframe, which has to be finite to be ordered among the others.
+    fn keyframe_time(&self) -> Result<f32, ParseError> {
+        self.checked_f32("time", check_finite)?
+            .ok_or_else(|| self.missing("time"))
+    }
+
+    fn required_vec3(&self, name: &str) -> Result<Vec3, ParseError> {
+        self.vec3(name)?.ok_or_else(|| self.missing(name))
+    }
+
+    /// Intensity of a light, tinted either by an RGB color or by a black body temperature in
+    /// kelvins.
+    fn light_intensity(&self) -> Result<Color, ParseError> {
+        // Colder black bodies hardly emit visible light.
+        const MIN_TEMPERATURE: f32 = 1000.;
+        let intensity = self.required_f32("intensity")?;
+        let temperature = self.checked_f32("temperature", check_finite)?;
+        let color = match (self.vec3("color")?, temperature) {
+            (Some(_), Some(_)) => {
+                return Err(ParseError::new(
+                    self.line,
+                    "'color' and 'temperature' are mutually exclusive",
+                ))
+            }
+            (Some(color), None) => Color::new(color.x, color.y, color.z),
+            (None, Some(temperature)) if temperature >= MIN_TEMPERATURE => {
+                Color::blackbody(temperature)
+            }
+            (None, Some(_)) => {
+                return Err(ParseError::new(
+                    self.line,
+                    format!("'temperature' must be at least {}", MIN_TEMPERATURE),
+                ))
+            }
+            (None, None) => Color::from(1.),
+        };
+        Ok(color * intensity)
+    }
+
+    fn surface(&self) -> Result<Surface, ParseError> {
+        let surface_type = self.get("type").map_or("diffuse", |values| values[0]);
+        let only_for = |name: &str, allowed_type: &str| {
+            if self.get(name).is_some() && surface_type != allowed_type {
+                Err(ParseError::new(
+                    self.line,
+                    format!("'{}' is only allowed for type '{}'", name, allowed_type),
+                ))
+            } else {
+                Ok(())
+            }
+        };
+        only_for("roughness", "metal")?;
+        only_for("roughness_texture", "metal")?;
+        only_for("ior", "dielectric")?;
+
+        match surface_type {
+            "diffuse" => Ok(Surface::Diffuse),
+            "metal" => {
+                let roughness = self.checked_f32("roughness", check_non_negative)?;
+                Ok(Surface::Metal {
+                    roughness: match self.get("roughness_texture") {
+                        Some(_) => roughness.unwrap_or(1.),
+                        None => roughness.unwrap_or(0.),
+                    },
+                })
+            }
+            "dielectric" => Ok(Surface::Dielectric {
+                ior: self.checked_f32("ior", check_positive)?.unwrap_or(1.5),
+            }),
+            _ => Err(ParseError::new(
+                self.line,
+                format!("unknown material type '{}'", surface_type),
+            )),
+        }
+    }
+
+    fn material(&self, materials: &HashMap<String, Material>) -> Result<Material, ParseError> {
+        let name = self
+            .get("material")
+            .ok_or_else(|| self.missing("material"))?[0];
+        materials
+            .get(name)
+            .cloned()
+            .ok_or_else(|| ParseError::new(self.line, format!("unknown material '{}'", name)))
+    }
+
+    /// The texture named by the property `name`, if there is one.
+    fn texture(
+        &self,
+        name: &str,
+        textures: &HashMap<String, Arc<dyn Texture>>,
+    ) -> Result<Option<Arc<dyn Texture>>, ParseError> {
+        self.get(name)
+            .map(|values| {
+                textures.get(values[0]).cloned().ok_or_else(|| {
+                    ParseError::new(self.line, format!("unknown texture '{}'", values[0]))
+                })
+            })
+            .transpose()
+    }
+
+    /// The motion named by the `motion` property, if there is one. No more keyframes can be
+    /// added to the motion afterwards.
+    fn motion(
+        &self,
+        motions: &mut HashMap<String, MotionKeys>,
+    ) -> Result<Option<TransformKeyframes>, ParseError> {
+        let name = match self.get("motion") {
+            Some(values) => values[0],
+            None => return Ok(None),
+        };
+        let keys = motions
+            .get_mut(name)
+            .ok_or_else(|| ParseError::new(self.line, format!("unknown motion '{}'", name)))?;
+        keys.used = true;
+        Ok(Some(keys.keyframes()))
+    }
+}
+
+/// Checks that the value of the parameter `name` is finite.
+pub fn check_finite(name: &str, value: f32) -> Result<f32, String> {
+    match value {
+        value if value.is_finite() => Ok(value),
+        _ => Err(format!("'{}' must be finite", name)),
+    }
+}
+
+/// Checks that the value of the parameter `name` is finite and greater than zero.
+pub fn check_positive(name: &str, value: f32) -> Result<f32, String> {
+    match value {
+        value if value > 0. && value.is_finite() => Ok(value),
+        _ => Err(format!("'{}' must be positive", name)),
+    }
+}
+
+/// Checks that the value of the parameter `name` is finite and not negative.
+pub fn check_non_negative(name: &str, value: f32) -> Result<f32, String> {
+    match value {
+        value if value >= 0. && value.is_finite() => Ok(value),
+        _ => Err(format!("'{}' must not be negative", name)),
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(line: usize, token: &str) -> Result<T, ParseError> {
+    token
+        .parse()
+        .map_err(|_| ParseError::new(line, format!("invalid number '{}'", token)))
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{vec2, vec3};
+    use rand::SeedableRng as _;
+
+    use super::*;
+    use crate::environment::Environment;
+    use crate::framebuffer::Framebuffer;
+    use crate::ray::Ray;
+    use crate::tonemap::ToneMapping;
+
+    #[test]
+    fn parse_scene_objects() {
+        let (scene, _) = parse_scene(
+            "
+            # A red sphere on a grey floor.
+            material grey color 0.8 0.8 0.8
+            material red color 0.75 0.25 0.25 shininess 20  # Shiny.
+            plane point 0 -1 0 normal 0 1 0 material grey
+            sphere center 0 0 -3 radius 1 material red
+            triangle v0 -1 -1 3 v1 1 -1 3 v2 0 1 3 material red
+            point_light position 0 0.1 3.5 intensity 3
+            ",
+        )
+        .unwrap();
+
+        let (intersection, _) = scene.find_intersection(&Ray::new(Vec3::zero(), -Vec3::unit_z()));
+        assert_eq!(intersection.dist, 2.);
+        let (intersection, _) = scene.find_intersection(&Ray::new(Vec3::zero(), -Vec3::unit_y()));
+        assert_eq!(intersection.dist, 1.);
+        let (intersection, _) = scene.find_intersection(&Ray::new(Vec3::zero(), Vec3::unit_z()));
+        assert_eq!(intersection.dist, 3.);
+    }
+
+    #[test]
+    fn parse_camera() {
+        let (_, camera) =
+            parse_scene("camera eye 1 2 -3 target 0 0 0 up 0 1 0 fov 90 size 400 200 samples 10")
+                .unwrap();
+        let dir = camera.pixel_ray(200, 100).unwrap().dir;
+        assert!((dir - vec3(-1., -2., 3.).normalize()).length() < 1E-6);
+
+        let (_, camera) = parse_scene("camera filter gaussian filter_radius 2").unwrap();
+        assert_eq!(camera.filter(), Filter::new(FilterType::Gaussian, 2.));
+
+        let (_, camera) =
+            parse_scene("camera eye 0 0 0 target 0 0 -4 aperture_radius 0.1").unwrap();
+        assert_eq!(camera.focus_distance(), 4.);
+        let (_, camera) = parse_scene("camera aperture_radius 0.1 focus_distance 2.5").unwrap();
+        assert_eq!(camera.focus_distance(), 2.5);
+
+        let (_, camera) = parse_scene("camera projection orthographic ortho_width 5").unwrap();
+        assert_eq!(camera.projection(), Projection::Orthographic { width: 5. });
+        let (_, camera) = parse_scene("camera projection equirectangular").unwrap();
+        assert_eq!(camera.projection(), Projection::Equirectangular);
+
+        let (_, camera) = parse_scene("camera shutter 0.25 0.75").unwrap();
+        assert_eq!(camera.shutter(), (0.25, 0.75));
+    }
+
+    #[test]
+    fn parse_moving_sphere() {
+        let (scene, _) = parse_scene(
+            "
+            material red color 0.75 0.25 0.25
+            sphere center 0 0 -3 center_end 2 0 -3 radius 1 material red
+            ",
+        )
+        .unwrap();
+        let ray = Ray::new(Vec3::zero(), -Vec3::unit_z());
+        let (intersection, _) = scene.find_intersection(&ray);
+        assert_relative_eq!(intersection.dist, 2.);
+        let (intersection, _) = scene.find_intersection(&ray.set_time(1.));
+        assert!(!intersection.exists());
+    }
+
+    #[test]
+    fn parse_keyframes() {
+        let (scene, camera, animation) = parse_animation(
+            "
+            camera eye 0 0 0 target 0 0 -1 shutter 0 0.5
+            camera_keyframe time 0 eye 0 0 0
+            camera_keyframe time 2 eye 0 2 0 fov 60
+            material red color 0.75 0.25 0.25
+            keyframe slide time 0 translate 0 0 0 scale 1
+            keyframe slide time 1 translate 4 0 0 scale 2
+            sphere center 0 0 -3 radius 1 material red motion slide
+            point_light position 0 0 0 intensity 1 motion slide
+            frames count 48 rate 24
+            ",
+        )
+        .unwrap();
+        assert_eq!(animation.frame_count(), 48);
+        assert_eq!(animation.frame_rate(), 24.);
+        let frame = animation.frame_camera(&camera, 25);
+        assert_eq!(frame.shutter(), (1., 1.5));
+        assert_eq!(frame.pixel_ray(0, 0).unwrap().origin, vec3(0., 1., 0.));
+
+        let ray = Ray::new(Vec3::zero(), -Vec3::unit_z());
+        let (intersection, _) = scene.find_intersection(&ray);
+        assert_relative_eq!(intersection.dist, 2.);
+        let (intersection, _) = scene.find_intersection(&ray.set_time(1.));
+        assert!(!intersection.exists());
+        // Scaled by 2 the sphere reaches to the distance 4 along the ray towards its center.
+        let ray = Ray::new(Vec3::zero(), vec3(4., 0., -6.).normalize()).set_time(1.);
+        let (intersection, _) = scene.find_intersection(&ray);
+        assert_relative_eq!(intersection.dist, 52f32.sqrt() - 2., epsilon = 1E-5);
+
+        // Without keyframes there is a single frame.
+        let (_, _, animation) = parse_animation("camera samples 4").unwrap();
+        assert_eq!(animation.frame_count(), 1);
+    }
+
+    #[test]
+    fn parse_material_types() {
+        let (scene, _) = parse_scene(
+            "
+            material gold type metal color 1 0.8 0.4 roughness 0.1
+            material glass type dielectric ior 1.33
+            sphere center 0 0 -3 radius 1 material gold
+            sphere center 0 0 3 radius 1 material glass
+            ",
+        )
+        .unwrap();
+        let (_, gold) = scene.find_intersection(&Ray::new(Vec3::zero(), -Vec3::unit_z()));
+        assert_eq!(
+            scene.material(gold).surface,
+            Surface::Metal { roughness: 0.1 }
+        );
+        let (_, glass) = scene.find_intersection(&Ray::new(Vec3::zero(), Vec3::unit_z()));
+        assert_eq!(
+            scene.material(glass).surface,
+            Surface::Dielectric { ior: 1.33 }
+        );
+    }
+
+    #[test]
+    fn parse_textures() {
+        let dir = std::env::temp_dir().join(format!("raytracer-textures-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut image = Framebuffer::new(2, 1);
+        image.put_pixel(0, 0, Color::new(1., 0., 0.));
+        image.put_pixel(1, 0, Color::new(0., 0., 1.));
+        image
+            .save(dir.join("stripes.pfm"), &ToneMapping::new())
+            .unwrap();
+        let scene_path = dir.join("textured.scene");
+        std::fs::write(
+            &scene_path,
+            "
+            texture stripes type image file stripes.pfm wrap clamp
+            material striped color_texture stripes reflection_texture stripes
+            sphere center 0 0 -3 radius 1 material striped
+            ",
+        )
+        .unwrap();
+        let result = load_scene(&scene_path);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let (scene, _) = result.unwrap();
+        let (intersection, id) = scene.find_intersection(&Ray::new(Vec3::zero(), -Vec3::unit_z()));
+        // The sphere faces the ray with u = 0.5, halfway between the two pixels.
+        let material = scene
+            .material(id)
+            .at(intersection.uv, Vec3::zero(), intersection.normal);
+        assert_eq!(material.color, Color::new(0.5, 0., 0.5));
+        assert_eq!(
+            material.reflection,
+            Color::new(0.5, 0., 0.5).luminance() * 3.
+        );
+        assert_eq!(material.diffusion, scene.material(id).diffusion);
+    }
+
+    #[test]
+    fn parse_procedural_textures() {
+        let (scene, _) = parse_scene(
+            "
+            texture tiles type checker color1 1 0 0 color2 0 0 1 space uv frequency 2
+            texture veins type marble octaves 2
+            texture bumps type fbm frequency 8
+            material floor color_texture tiles shininess_texture veins bump_map bumps bump_scale 0.1
+            plane point 0 -1 0 normal 0 1 0 material floor
+            ",
+        )
+        .unwrap();
+        let floor = scene.material(0);
+        let color = |x, z| floor.at(vec2(x, z), Vec3::zero(), Vec3::unit_y()).color;
+        assert_eq!(color(0.25, 0.25), Color::new(1., 0., 0.));
+        assert_eq!(color(0.75, 0.25), Color::new(0., 0., 1.));
+        let shininess = floor
+            .at(Vec2::zero(), vec3(0.3, 1.7, -2.2), Vec3::unit_y())
+            .shininess;
+        assert!(shininess > 0. && shininess < 10.);
+        assert_eq!(floor.bump_scale, 0.1);
+        assert!(floor.textures.bump_map.is_some());
+    }
+
+    #[test]
+    fn parse_environment() {
+        let dir =
+            std::env::temp_dir().join(format!("raytracer-environment-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut image = Framebuffer::new(2, 1);
+        image.put_pixel(0, 0, Color::new(4., 2., 1.));
+        image.put_pixel(1, 0, Color::new(4., 2., 1.));
+        image
+            .save(dir.join("sky.hdr"), &ToneMapping::new())
+            .unwrap();
+        let scene_path = dir.join("sky.scene");
+        std::fs::write(&scene_path, "environment file sky.hdr intensity 0.5").unwrap();
+        let result = load_scene(&scene_path);
+        std::fs::write(
+            &scene_path,
+            "environment file sky.hdr\nenvironment file sky.hdr",
+        )
+        .unwrap();
+        let duplicate = load_scene(&scene_path);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let (scene, _) = result.unwrap();
+        let ray = Ray::new(Vec3::zero(), Vec3::unit_y());
+        let color = scene.ray_color(&ray, &mut rand::rngs::SmallRng::seed_from_u64(239));
+        assert_eq!(color, Color::new(2., 1., 0.5));
+        match duplicate {
+            Err(LoadError::Parse(_, err)) => {
+                assert_eq!(err, ParseError::new(2, "duplicate environment"))
+            }
+            _ => panic!("expected a parse error"),
+        }
+    }
+
+    #[test]
+    fn parse_sky() {
+        let (scene, _) =
+            parse_scene("sky elevation 30 azimuth 90 turbidity 4 intensity 2").unwrap();
+        let dir = vec3(0., 1., -1.).normalize();
+        let sky = Sky::new(30f32.to_radians(), 90f32.to_radians(), 4.).set_intensity(2.);
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(239);
+        let color = scene.ray_color(&Ray::new(Vec3::zero(), dir), &mut rng);
+        assert_eq!(color, sky.radiance(dir));
+
+        // The floor is lit by the sun unless it is turned off.
+        let floor = |sun_intensity| {
+            let text = format!(
+                "max_depth 0\n\
+                 material white color 1 1 1 reflection 0\n\
+                 plane point 0 0 0 normal 0 1 0 material white\n\
+                 sky elevation 90 sun_intensity {}",
+                sun_intensity
+            );
+            let (scene, _) = parse_scene(&text).unwrap();
+            let ray = Ray::new(vec3(0., 1., 0.), -Vec3::unit_y());
+            let mut rng = rand::rngs::SmallRng::seed_from_u64(239);
+            (0..100)
+                .map(|_| scene.ray_color(&ray, &mut rng).luminance())
+                .sum::<f32>()
+        };
+        assert!(floor(1.) > 2. * floor(0.));
+    }
+
+    fn parse_error(text: &str) -> ParseError {
+        match parse_scene(text) {
+            Ok(_) => panic!("expected an error"),
+            Err(err) => err,
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(
+            parse_error("\n\nmaterial red color 1 0 0\ncube size 1"),
+            ParseError::new(4, "unknown statement 'cube'")
+        );
+        assert_eq!(
+            parse_error("sphere center 0 0 -3 radius 1 material red"),
+            ParseError::new(1, "unknown material 'red'")
+        );
+        assert_eq!(
+            parse_error("material red\nsphere center 0 0 x radius 1 material red"),
+            ParseError::new(2, "invalid number 'x'")
+        );
+        assert_eq!(
+            parse_error("material red\nsphere center 0 0 -3 material red"),
+            ParseError::new(2, "'sphere' requires property 'radius'")
+        );
+        assert_eq!(
+            parse_error("material red\nsphere center 0 0 -3 radius 0 material red"),
+            ParseError::new(2, "'radius' must be positive")
+        );
+        assert_eq!(
+            parse_error("material red\nsphere center 0 0 -3 radius NaN material red"),
+            ParseError::new(2, "'radius' must be positive")
+        );
+        assert_eq!(
+            parse_error("material red\nplane point 0 0 0 normal 0 0 0 material red"),
+            ParseError::new(2, "'normal' must not be zero")
+        );
+        assert_eq!(
+            parse_error("point_light position 0 0 0 intensity 1 radius 1"),
+            ParseError::new(1, "unknown property 'radius' for 'point_light'")
+        );
+        assert_eq!(
+            parse_error("point_light position 0 0 0 intensity 1 color 1 1 1 temperature 5000"),
+            ParseError::new(1, "'color' and 'temperature' are mutually exclusive")
+        );
+        assert_eq!(
+            parse_error("camera size 8 8 samples 0"),
+            ParseError::new(1, "'samples' must be positive")
+        );
+        assert_eq!(
+            parse_error("camera min_samples 0"),
+            ParseError::new(1, "'min_samples' must be positive")
+        );
+        assert_eq!(
+            parse_error("camera adaptive_threshold -1"),
+            ParseError::new(1, "'adaptive_threshold' must not be negative")
+        );
+        assert_eq!(
+            parse_error("camera adaptive_threshold NaN"),
+            ParseError::new(1, "'adaptive_threshold' must not be negative")
+        );
+        assert_eq!(
+            parse_error("sphere_light center 0 0 0 radius 1 intensity 1 temperature 0"),
+            ParseError::new(1, "'temperature' must be at least 1000")
+        );
+        assert_eq!(
+            parse_error("sphere_light center 0 0 0 radius -1 intensity 1"),
+            ParseError::new(1, "'radius' must be positive")
+        );
+        assert_eq!(
+            parse_error("point_light position 0 0 0 intensity 1 temperature 10"),
+            ParseError::new(1, "'temperature' must be at least 1000")
+        );
+        assert_eq!(
+            parse_error("camera sampler poisson"),
+            ParseError::new(1, "unknown sampler 'poisson'")
+        );
+        assert_eq!(
+            parse_error("camera filter sinc"),
+            ParseError::new(1, "unknown filter 'sinc'")
+        );
+        assert_eq!(
+            parse_error("camera filter tent filter_radius 0"),
+            ParseError::new(1, "'filter_radius' must be positive")
+        );
+        assert_eq!(
+            parse_error("camera filter_radius NaN"),
+            ParseError::new(1, "'filter_radius' must be positive")
+        );
+        assert_eq!(
+            parse_error("camera filter_radius inf"),
+            ParseError::new(1, "'filter_radius' must be positive")
+        );
+        assert_eq!(
+            parse_error("camera aperture_radius 0.1 aperture_blades 2"),
+            ParseError::new(1, "'aperture_blades' must be 0 or at least 3")
+        );
+        assert_eq!(
+            parse_error("camera aperture_radius NaN"),
+            ParseError::new(1, "'aperture_radius' must not be negative")
+        );
+        assert_eq!(
+            parse_error("camera aperture_radius 0.1 focus_distance NaN"),
+            ParseError::new(1, "'focus_distance' must be positive")
+        );
+        assert_eq!(
+            parse_error("camera projection orthographic"),
+            ParseError::new(1, "'camera' requires property 'ortho_width'")
+        );
+        assert_eq!(
+            parse_error("camera projection cylindrical"),
+            ParseError::new(1, "unknown projection 'cylindrical'")
+        );
+        assert_eq!(
+            parse_error("camera shutter NaN 1"),
+            ParseError::new(1, "'shutter' must be finite")
+        );
+        assert_eq!(
+            parse_error("camera shutter 1 0"),
+            ParseError::new(1, "'shutter' must not close before it opens")
+        );
+        assert_eq!(
+            parse_error("material red\nsphere center 0 0 -3 radius 1 material red motion spin"),
+            ParseError::new(2, "unknown motion 'spin'")
+        );
+        assert_eq!(
+            parse_error(
+                "keyframe up time 0 translate 0 0 0\n\
+                 point_light position 0 0 0 intensity 1 motion up\n\
+                 keyframe up time 1 translate 0 1 0"
+            ),
+            ParseError::new(3, "keyframe for motion 'up' after it is used")
+        );
+        assert_eq!(
+            parse_error("camera_keyframe time NaN fov 30\ncamera_keyframe time 1 fov 40"),
+            ParseError::new(1, "'time' must be finite")
+        );
+        assert_eq!(
+            parse_error("keyframe up time inf translate 0 1 0"),
+            ParseError::new(1, "'time' must be finite")
+        );
+        assert_eq!(
+            parse_error("frames count 2 rate NaN"),
+            ParseError::new(1, "'rate' must be positive")
+        );
+        assert_eq!(
+            parse_error("frames rate 24"),
+            ParseError::new(1, "'frames' requires property 'count'")
+        );
+        assert_eq!(
+            parse_error("material gold type gold"),
+            ParseError::new(1, "unknown material type 'gold'")
+        );
+        assert_eq!(
+            parse_error("material glass ior 1.5"),
+            ParseError::new(1, "'ior' is only allowed for type 'dielectric'")
+        );
+        assert_eq!(
+            parse_error("material glass type dielectric ior 0"),
+            ParseError::new(1, "'ior' must be positive")
+        );
+        assert_eq!(
+            parse_error("material glass type dielectric ior inf"),
+            ParseError::new(1, "'ior' must be positive")
+        );
+        assert_eq!(
+            parse_error("material gold type metal roughness -0.1"),
+            ParseError::new(1, "'roughness' must not be negative")
+        );
+        assert_eq!(
+            parse_error("material"),
+            ParseError::new(1, "'material' requires a name")
+        );
+        assert_eq!(
+            parse_error("material wood color_texture grain"),
+            ParseError::new(1, "unknown texture 'grain'")
+        );
+        assert!(parse_error("texture grain type image file missing.png")
+            .message
+            .starts_with("cannot load 'missing.png'"));
+        assert_eq!(
+            parse_error("texture grain type stone"),
+            ParseError::new(1, "unknown texture type 'stone'")
+        );
+        assert_eq!(
+            parse_error("texture grain type wood file wood.png"),
+            ParseError::new(1, "'file' is not allowed for texture type 'wood'")
+        );
+        assert_eq!(
+            parse_error("texture grain type image file grain.png encoding gamma"),
+            ParseError::new(1, "unknown encoding 'gamma'")
+        );
+        assert_eq!(
+            parse_error("texture grain type wood octaves 0"),
+            ParseError::new(1, "'octaves' must be positive")
+        );
+        assert_eq!(
+            parse_error("sky elevation 100"),
+            ParseError::new(1, "'elevation' must be between 0 and 90")
+        );
+        assert_eq!(
+            parse_error("sky elevation 30 turbidity 1"),
+            ParseError::new(1, "'turbidity' must be between 2 and 10")
+        );
+        assert_eq!(
+            parse_error("sky elevation 30 azimuth inf"),
+            ParseError::new(1, "'azimuth' must be finite")
+        );
+        assert_eq!(
+            parse_error("sky elevation 30 sun_diameter NaN"),
+            ParseError::new(1, "'sun_diameter' must be between 0 and 180")
+        );
+        assert_eq!(
+            parse_error("sky elevation 30 intensity -1"),
+            ParseError::new(1, "'intensity' must not be negative")
+        );
+        assert_eq!(
+            parse_error("sky elevation 30 sun_intensity NaN"),
+            ParseError::new(1, "'sun_intensity' must not be negative")
+        );
+        assert_eq!(
+            parse_error("sky elevation 30\nsky elevation 60"),
+            ParseError::new(2, "duplicate environment")
+        );
+        assert!(parse_error("environment file missing.hdr")
+            .message
+            .starts_with("cannot load 'missing.hdr'"));
+        assert_eq!(
+            parse_error("environment intensity 2"),
+            ParseError::new(1, "'environment' requires property 'file'")
+        );
+        assert_eq!(
+            parse_error("environment file sky.hdr intensity NaN"),
+            ParseError::new(1, "'intensity' must not be negative")
+        );
+    }
+}
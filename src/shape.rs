@@ -1,25 +1,66 @@
-use glam::Vec3;
+use glam::{Vec2, Vec3};
 use std::cmp::{Ordering, PartialOrd};
 
+use crate::bvh::Aabb;
 use crate::defines::*;
+use crate::ray::Ray;
 
 #[derive(Debug)]
 pub struct Intersection {
     pub dist: f32,
+    /// Normal used for shading, which can be interpolated across the surface.
     pub normal: Vec3,
+    /// Normal of the actual surface, on the same side as `normal`. Rays leaving the surface are
+    /// offset along it.
+    pub geometric_normal: Vec3,
+    /// Texture coordinates of the intersection point.
+    pub uv: Vec2,
+    /// Derivatives of the point along the surface with respect to u and v, for normal and bump
+    /// mapping. They don't need to be perpendicular to the normal, and are zero if the shape
+    /// doesn't have them.
+    pub tangent: Vec3,
+    pub bitangent: Vec3,
 }
 
 impl Intersection {
-    /// normal should be normalized.
+    /// normal should be normalized and point to the outside of the shape.
     pub fn new(dist: f32, normal: Vec3) -> Self {
         debug_assert!((normal.length() - 1.).abs() < EPSILON );
-        Intersection { dist, normal }
+        Intersection {
+            dist,
+            normal,
+            geometric_normal: normal,
+            uv: Vec2::zero(),
+            tangent: Vec3::zero(),
+            bitangent: Vec3::zero(),
+        }
+    }
+
+    /// Sets the normal of the actual surface if it differs from the shading normal.
+    pub fn set_geometric_normal(mut self, geometric_normal: Vec3) -> Self {
+        self.geometric_normal = geometric_normal;
+        self
+    }
+
+    pub fn set_uv(mut self, uv: Vec2) -> Self {
+        self.uv = uv;
+        self
+    }
+
+    pub fn set_tangents(mut self, tangent: Vec3, bitangent: Vec3) -> Self {
+        self.tangent = tangent;
+        self.bitangent = bitangent;
+        self
     }
 
     pub fn new_empty() -> Self {
         Intersection {
             dist: -1.,
             normal: Vec3::unit_x(),
+            geometric_normal: Vec3::unit_x(),
+            uv: Vec2::zero(),
+            tangent: Vec3::zero(),
+            bitangent: Vec3::zero(),
         }
     }
 
@@ -52,8 +93,15 @@ impl PartialOrd for Intersection {
     }
 }
 
-pub trait Shape {
-    /// Returns negative value if there is no intersection, or the square distance to
-    /// the intersection if there is one.
-    fn ray_intersect(&self, origin: Vec3, dir: Vec3) -> Intersection;
+/// A geometric primitive that can be added to a `Scene`. Shapes are shared between the render
+/// threads, hence `Send + Sync`.
+pub trait Shape: Send + Sync {
+    /// Returns the nearest intersection within the bounds of the ray, or an empty intersection if
+    /// there is none.
+    fn ray_intersect(&self, ray: &Ray) -> Intersection;
+
+    /// Bounding box of the shape, or `None` if the shape is unbounded.
+    fn bounds(&self) -> Option<Aabb> {
+        None
+    }
 }
@@ -0,0 +1,313 @@
+//! Daylight from Preetham, Shirley and Smits, "A Practical Analytic Model for Daylight",
+//! SIGGRAPH 1999: the sky as an environment and the sun as a light, both given by the position
+//! of the sun and the turbidity of the atmosphere.
+//!
+//! The position of the sun is given by its elevation above the horizon and its azimuth, the
+//! angle from -z towards +x, so that the sun at azimuth 0 is in the center of an equirectangular
+//! view. Turbidity ranges from 2 for a very clear sky to 10 for haze.
+
+use glam::Vec3;
+use std::f32::consts::PI;
+
+use crate::environment::Environment;
+use crate::light::Light;
+use crate::material::Color;
+use crate::sampler::Sampler;
+use crate::sampling::uniform_sample_cone;
+
+/// Scene radiance per candela per square metre, so that a white surface in full sunlight is
+/// close to 1 at the default exposure.
+const PHOTOMETRIC_SCALE: f32 = 3E-5;
+
+/// Illuminance from the sun outside of the atmosphere in lux.
+const SOLAR_ILLUMINANCE: f32 = 128_000.;
+
+/// Color temperature of the sun outside of the atmosphere in kelvins.
+const SOLAR_TEMPERATURE: f32 = 5778.;
+
+/// Unit vector towards the sun, with the angles in radians.
+fn sun_direction(elevation: f32, azimuth: f32) -> Vec3 {
+    Vec3::new(
+        azimuth.sin() * elevation.cos(),
+        elevation.sin(),
+        -azimuth.cos() * elevation.cos(),
+    )
+}
+
+/// The elevation and the turbidity clamped to the ranges of the model.
+fn clamp_parameters(elevation: f32, turbidity: f32) -> (f32, f32) {
+    (elevation.clamp(0., PI / 2.), turbidity.clamp(2., 10.))
+}
+
+/// Coefficients of Perez's sky luminance distribution.
+#[derive(Clone, Copy, Debug)]
+struct Perez([f32; 5]);
+
+impl Perez {
+    /// Relative value in a direction at the angle `theta` from the zenith and `gamma` from the
+    /// sun, given `cos(theta)`.
+    fn evaluate(&self, cos_theta: f32, gamma: f32) -> f32 {
+        let [a, b, c, d, e] = self.0;
+        let cos_gamma = gamma.cos();
+        (1. + a * (b / cos_theta).exp()) * (1. + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+    }
+}
+
+/// The sky without the sun. Below the horizon the angle from the zenith is clamped to the
+/// horizon.
+pub struct Sky {
+    sun_dir: Vec3,
+    intensity: f32,
+    /// Distributions of the luminance Y and the chromaticities x and y, each with its value at
+    /// the zenith divided by the distribution at the zenith.
+    channels: [(Perez, f32); 3],
+}
+
+impl Sky {
+    /// Sky with the sun at the angles `elevation` and `azimuth` in radians. The elevation is
+    /// clamped to [0, π/2] and the turbidity to [2, 10].
+    pub fn new(elevation: f32, azimuth: f32, turbidity: f32) -> Self {
+        let (elevation, turbidity) = clamp_parameters(elevation, turbidity);
+        let t = turbidity;
+        let theta_s = PI / 2. - elevation;
+        let perez_y = Perez([
+            0.1787 * t - 1.4630,
+            -0.3554 * t + 0.4275,
+            -0.0227 * t + 5.3251,
+            0.1206 * t - 2.5771,
+            -0.0670 * t + 0.3703,
+        ]);
+        let perez_x = Perez([
+            -0.0193 * t - 0.2592,
+            -0.0665 * t + 0.0008,
+            -0.0004 * t + 0.2125,
+            -0.0641 * t - 0.8989,
+            -0.0033 * t + 0.0452,
+        ]);
+        let perez_yc = Perez([
+            -0.0167 * t - 0.2608,
+            -0.0950 * t + 0.0092,
+            -0.0079 * t + 0.2102,
+            -0.0441 * t - 1.6537,
+            -0.0109 * t + 0.0529,
+        ]);
+
+        // Zenith luminance in kcd/m² and chromaticity.
+        let chi = (4. / 9. - t / 120.) * (PI - 2. * theta_s);
+        let zenith_y = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let theta = [theta_s.powi(3), theta_s.powi(2), theta_s, 1.];
+        let zenith_chromaticity = |m: [[f32; 4]; 3]| {
+            let row = |r: [f32; 4]| r.iter().zip(theta.iter()).map(|(a, b)| a * b).sum::<f32>();
+            t * t * row(m[0]) + t * row(m[1]) + row(m[2])
+        };
+        let zenith_x = zenith_chromaticity([
+            [0.00166, -0.00375, 0.00209, 0.],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let zenith_yc = zenith_chromaticity([
+            [0.00275, -0.00610, 0.00317, 0.],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+
+        let channel = |perez: Perez, zenith: f32| (perez, zenith / perez.evaluate(1., theta_s));
+        Sky {
+            sun_dir: sun_direction(elevation, azimuth),
+            intensity: 1.,
+            channels: [
+                channel(perez_y, zenith_y * 1000. * PHOTOMETRIC_SCALE),
+                channel(perez_x, zenith_x),
+                channel(perez_yc, zenith_yc),
+            ],
+        }
+    }
+
+    /// Multiplies the radiance of the sky.
+    pub fn set_intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+}
+
+impl Environment for Sky {
+    fn radiance(&self, dir: Vec3) -> Color {
+        // The distribution goes to 1 + A at the horizon, where cos(theta) reaches 0.
+        let cos_theta = dir.y.max(0.);
+        let gamma = dir.dot(self.sun_dir).clamp(-1., 1.).acos();
+        let [big_y, x, y] = {
+            let value = |(perez, scale): (Perez, f32)| perez.evaluate(cos_theta, gamma) * scale;
+            [
+                value(self.channels[0]),
+                value(self.channels[1]),
+                value(self.channels[2]),
+            ]
+        };
+        let big_y = big_y * self.intensity;
+        Color::from_xyz(x / y * big_y, big_y, (1. - x - y) / y * big_y)
+    }
+
+    /// The sky varies smoothly, so directions are sampled uniformly over the upper hemisphere.
+    /// Below the horizon the radiance is left to the bounces from the surfaces.
+    fn sample(&self, u: [f32; 2]) -> (Vec3, f32) {
+        let y = u[0];
+        let r = (1. - y * y).max(0.).sqrt();
+        let phi = 2. * PI * u[1];
+        (Vec3::new(r * phi.cos(), y, r * phi.sin()), 1. / (2. * PI))
+    }
+
+    fn pdf(&self, dir: Vec3) -> f32 {
+        if dir.y >= 0. {
+            1. / (2. * PI)
+        } else {
+            0.
+        }
+    }
+}
+
+/// The sun matching a `Sky` with the same parameters: a directional light whose color and
+/// intensity depend on the length of its path through the atmosphere. Its angular diameter
+/// makes soft shadows.
+pub struct SunLight {
+    dir: Vec3,
+    /// Cosine of the angular radius.
+    cos_radius: f32,
+    intensity: Color,
+}
+
+impl SunLight {
+    /// The sun at the angles `elevation` and `azimuth` in radians, 0.53° across. The parameters
+    /// are clamped like those of `Sky::new`.
+    pub fn new(elevation: f32, azimuth: f32, turbidity: f32) -> Self {
+        let (elevation, turbidity) = clamp_parameters(elevation, turbidity);
+        // Relative optical air mass, Kasten and Young's formula.
+        let zenith_degrees = 90. - elevation.to_degrees();
+        let air_mass = 1.
+            / ((PI / 2. - elevation).cos() + 0.50572 * (96.07995 - zenith_degrees).powf(-1.6364));
+        // Transmittance due to Rayleigh scattering by the molecules of the air and due to
+        // scattering by aerosols after Ångström, at wavelengths in µm for the color channels.
+        let beta = 0.04608 * turbidity - 0.04586;
+        let transmittance = |lambda: f32| {
+            let rayleigh = 0.008735 * lambda.powf(-4.08);
+            let aerosol = beta * lambda.powf(-1.3);
+            (-(rayleigh + aerosol) * air_mass).exp()
+        };
+        let [r, g, b] = Color::blackbody(SOLAR_TEMPERATURE).to_array();
+        let scale = SOLAR_ILLUMINANCE * PHOTOMETRIC_SCALE;
+        SunLight {
+            dir: sun_direction(elevation, azimuth),
+            cos_radius: 0.265f32.to_radians().cos(),
+            intensity: Color::new(
+                r * transmittance(0.65) * scale,
+                g * transmittance(0.57) * scale,
+                b * transmittance(0.475) * scale,
+            ),
+        }
+    }
+
+    /// Sets the angular diameter of the sun in radians. 0 makes hard shadows.
+    pub fn set_angular_diameter(mut self, diameter: f32) -> Self {
+        self.cos_radius = (diameter / 2.).cos();
+        self
+    }
+
+    /// Multiplies the intensity of the sun.
+    pub fn set_intensity(mut self, intensity: f32) -> Self {
+        self.intensity = self.intensity * intensity;
+        self
+    }
+}
+
+impl Light for SunLight {
+    fn sample_ray(&self, _from: Vec3, _time: f32, sampler: &mut impl Sampler) -> (Vec3, f32) {
+        (
+            uniform_sample_cone(self.dir, self.cos_radius, sampler),
+            f32::INFINITY,
+        )
+    }
+
+    fn intensity(&self) -> Color {
+        self.intensity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng as _;
+
+    use super::*;
+
+    #[test]
+    fn sky_model() {
+        let sky = Sky::new(30f32.to_radians(), 90f32.to_radians(), 3.);
+        let zenith = sky.radiance(Vec3::unit_y());
+        let [r, g, b] = zenith.to_array();
+        // A clear blue sky, brighter around the sun than opposite of it.
+        assert!(b > g && g > r);
+        let towards_sun = sky.radiance(Vec3::new(1., 0.3, 0.).normalize());
+        let away_from_sun = sky.radiance(Vec3::new(-1., 0.3, 0.).normalize());
+        assert!(towards_sun.luminance() > 2. * away_from_sun.luminance());
+        let below = sky.radiance(Vec3::new(0., -0.5, 1.).normalize());
+        assert!(below.luminance() > 0. && below.luminance() < towards_sun.luminance());
+        // Around 5 kcd/m² at the zenith.
+        let luminance = zenith.luminance() / PHOTOMETRIC_SCALE;
+        assert!(luminance > 3000. && luminance < 8000.);
+
+        // Out of range parameters are clamped.
+        let clamped = Sky::new(-0.1, 90f32.to_radians(), 12.);
+        let horizon = Sky::new(0., 90f32.to_radians(), 10.);
+        assert_eq!(
+            clamped.radiance(Vec3::unit_y()),
+            horizon.radiance(Vec3::unit_y())
+        );
+
+        let hazy = Sky::new(30f32.to_radians(), 90f32.to_radians(), 8.);
+        let [r, _, b] = hazy.radiance(Vec3::unit_y()).to_array();
+        assert!(b / r < zenith.to_array()[2] / zenith.to_array()[0]);
+
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(239);
+        for _ in 0..100 {
+            let (dir, pdf) = sky.sample(rng.next_2d());
+            assert_relative_eq!(dir.length(), 1., epsilon = 1E-5);
+            assert!(dir.y >= 0.);
+            assert_eq!(pdf, sky.pdf(dir));
+        }
+    }
+
+    #[test]
+    fn sun_light() {
+        let sun = SunLight::new(60f32.to_radians(), 0., 2.5);
+        let dir = sun_direction(60f32.to_radians(), 0.);
+        assert_relative_eq!(dir.dot(Vec3::new(0., 0., -1.)), 0.5, epsilon = 1E-6);
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(239);
+        let cos_radius = 0.5f32.to_radians().cos();
+        let sun = sun.set_angular_diameter(1f32.to_radians());
+        let mut spread = 0f32;
+        for _ in 0..100 {
+            let (sample, dist) = sun.sample_ray(Vec3::new(1., 2., 3.), 0., &mut rng);
+            assert_eq!(dist, f32::INFINITY);
+            assert_relative_eq!(sample.length(), 1., epsilon = 1E-5);
+            assert!(sample.dot(dir) >= cos_radius - 1E-6);
+            spread = spread.max(1. - sample.dot(dir));
+        }
+        assert!(spread > 0.5 * (1. - cos_radius));
+
+        // Whiter and brighter high up, dimmer and redder near the horizon or through haze.
+        let [r, g, b] = SunLight::new(60f32.to_radians(), 0., 2.5)
+            .intensity()
+            .to_array();
+        assert!(r > g && g > b && b > 0.5 * r);
+        // About 100 klux of direct sunlight, white surfaces are close to 1.
+        assert!(g / PHOTOMETRIC_SCALE > 80_000.);
+        for sun in [
+            SunLight::new(5f32.to_radians(), 0., 2.5),
+            SunLight::new(60f32.to_radians(), 0., 8.),
+        ]
+        .iter()
+        {
+            let [r_low, g_low, b_low] = sun.intensity().to_array();
+            assert!(g_low < g);
+            assert!(b_low / r_low < b / r);
+        }
+    }
+}
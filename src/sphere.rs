@@ -1,5 +1,9 @@
+use crate::bvh::Aabb;
+use crate::ray::Ray;
 use crate::shape::*;
+use crate::texture::spherical_uv;
 use glam::Vec3;
+use std::f32::consts::PI;
 
 pub struct Sphere {
     center: Vec3,
@@ -18,14 +22,10 @@ impl Sphere {
 }
 
 impl Shape for Sphere {
-    fn ray_intersect(&self, origin: Vec3, dir: Vec3) -> Intersection {
-        let to_center = self.center - origin;
+    fn ray_intersect(&self, ray: &Ray) -> Intersection {
+        let to_center = self.center - ray.origin;
         // Projection of the line to the sphere center on to the ray.
-        let projection = dir.dot(to_center);
-        if projection <= 0. {
-            return Intersection::new_empty();
-        }
-
+        let projection = ray.dir.dot(to_center);
         let projection2 = projection * projection;
         let ray_dist2 = to_center.length_squared() - projection2;
         if ray_dist2 >= self.radius2 {
@@ -33,47 +33,75 @@ impl Shape for Sphere {
         }
 
         let seg2 = self.radius2 - ray_dist2;
-        if projection2 <= seg2 {
+        let mut dist = if projection2 > seg2 {
+            // The origin is outside of the sphere.
+            if projection <= 0. {
+                return Intersection::new_empty();
+            }
+            projection2.sqrt() - seg2.sqrt()
+        } else {
+            // The origin is inside, the ray exits the sphere.
+            projection + seg2.sqrt()
+        };
+        if dist < ray.tmin {
+            // Too close to the origin to be a separate intersection, try the exit point.
+            dist = projection + seg2.sqrt();
+        }
+        if !ray.contains(dist) {
             return Intersection::new_empty();
         }
-        let dist = projection2.sqrt() - seg2.sqrt();
-        let to_intersect = dir * dist;
-        let normal = (to_intersect - to_center) / self.radius;
+        let to_intersect = ray.dir * dist;
+        // The normal always points outwards, even when the ray comes from the inside.
+        let normal = (to_intersect - to_center).normalize();
+        // u goes once around the y axis, v half way from the bottom to the top.
+        let around = Vec3::new(normal.z, 0., -normal.x);
+        let up = if around == Vec3::zero() {
+            Vec3::zero()
+        } else {
+            normal.cross(around).normalize()
+        };
         Intersection::new(dist, normal)
+            .set_uv(spherical_uv(normal))
+            .set_tangents(around * (2. * PI * self.radius), up * (PI * self.radius))
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        let radius = Vec3::splat(self.radius);
+        Some(Aabb::new(self.center - radius, self.center + radius))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use glam::vec3;
+    use glam::{vec2, vec3};
 
     use super::*;
 
     #[test]
     fn sphere_ray_intersect1() {
         let sphere = Sphere::new(vec3(0., 0., 2.), 1.);
-        let intersection = sphere.ray_intersect(vec3(0., 0., 0.), vec3(0., 0., 1.));
+        let intersection = sphere.ray_intersect(&Ray::new(vec3(0., 0., 0.), vec3(0., 0., 1.)));
         assert_eq!(intersection.dist, 1.)
     }
 
     #[test]
     fn sphere_ray_intersect2() {
         let sphere = Sphere::new(vec3(0., 0., 3.), 1.);
-        let intersection = sphere.ray_intersect(vec3(0., 0., 0.), vec3(0., 0., 1.));
+        let intersection = sphere.ray_intersect(&Ray::new(vec3(0., 0., 0.), vec3(0., 0., 1.)));
         assert_eq!(intersection.dist, 2.)
     }
 
     #[test]
     fn sphere_ray_intersect3() {
         let sphere = Sphere::new(vec3(0., 0., 2.), 1.);
-        let intersection = sphere.ray_intersect(vec3(0., 0., 0.), vec3(0., 0., 1.));
+        let intersection = sphere.ray_intersect(&Ray::new(vec3(0., 0., 0.), vec3(0., 0., 1.)));
         assert_eq!(intersection.dist, 1.)
     }
 
     #[test]
     fn sphere_ray_intersect4() {
         let sphere = Sphere::new(vec3(0., 0., 3.), 1.);
-        let intersection = sphere.ray_intersect(vec3(0., 0., 0.), vec3(0., 0., 1.));
+        let intersection = sphere.ray_intersect(&Ray::new(vec3(0., 0., 0.), vec3(0., 0., 1.)));
         assert_eq!(intersection.dist, 2.)
     }
 
@@ -81,11 +109,41 @@ mod tests {
     fn sphere_ray_intersect5() {
         let sphere = Sphere::new(vec3(0., 0., 3.), 1.);
         let intersection1 =
-            sphere.ray_intersect(vec3(0., 0., 0.), vec3(0., -1., 3.).normalize());
+            sphere.ray_intersect(&Ray::new(vec3(0., 0., 0.), vec3(0., -1., 3.).normalize()));
         let intersection2 =
-            sphere.ray_intersect(vec3(0., 0., 0.), vec3(0., -0.5, 1.5).normalize());
+            sphere.ray_intersect(&Ray::new(vec3(0., 0., 0.), vec3(0., -0.5, 1.5).normalize()));
         assert_eq!(intersection1.dist, intersection2.dist);
         assert!(intersection1.dist > 2.5);
         assert!(intersection1.dist < 2.8);
     }
+
+    #[test]
+    fn sphere_uv() {
+        let sphere = Sphere::new(vec3(0., 0., 3.), 1.);
+        let intersection = sphere.ray_intersect(&Ray::new(vec3(-5., 0., 3.), vec3(1., 0., 0.)));
+        assert_eq!(intersection.uv, vec2(0.25, 0.5));
+        assert_eq!(intersection.tangent, vec3(0., 0., 2. * PI));
+        assert_eq!(intersection.bitangent, vec3(0., PI, 0.));
+        let intersection = sphere.ray_intersect(&Ray::new(vec3(0., 5., 3.), vec3(0., -1., 0.)));
+        assert_eq!(intersection.uv.y, 1.);
+    }
+
+    #[test]
+    fn sphere_ray_intersect_inside() {
+        let sphere = Sphere::new(vec3(0., 0., 3.), 1.);
+        let intersection = sphere.ray_intersect(&Ray::new(vec3(0., 0., 3.5), vec3(0., 0., 1.)));
+        assert_eq!(intersection.dist, 0.5);
+        assert_eq!(intersection.normal, vec3(0., 0., 1.));
+
+        let intersection = sphere.ray_intersect(&Ray::new(vec3(0., 0., 3.5), vec3(0., 0., -1.)));
+        assert_eq!(intersection.dist, 1.5);
+        assert_eq!(intersection.normal, vec3(0., 0., -1.));
+    }
+
+    #[test]
+    fn sphere_ray_miss_behind() {
+        let sphere = Sphere::new(vec3(0., 0., 3.), 1.);
+        let intersection = sphere.ray_intersect(&Ray::new(vec3(0., 0., 5.), vec3(0., 0., 1.)));
+        assert!(!intersection.exists());
+    }
 }
@@ -0,0 +1,176 @@
+//! Textures give material parameters that vary over a surface. They are looked up by the texture
+//! coordinates (u, v) of the surface point, with u going right and v going up in the image, or by
+//! the position of the point.
+
+use glam::{Vec2, Vec3};
+use std::f32::consts::PI;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::framebuffer::Framebuffer;
+use crate::material::Color;
+
+pub trait Texture: Send + Sync {
+    /// Color at a surface point with texture coordinates `uv`, the position `point` in the scene
+    /// and the normal `normal` of the actual surface.
+    fn evaluate(&self, uv: Vec2, point: Vec3, normal: Vec3) -> Color;
+
+    /// Scalar value at a surface point, the luminance of the color.
+    fn evaluate_scalar(&self, uv: Vec2, point: Vec3, normal: Vec3) -> f32 {
+        self.evaluate(uv, point, normal).luminance()
+    }
+}
+
+/// Texture coordinates of a direction, e.g. from the center of a sphere. u is the longitude,
+/// with u = 0.5 in the direction of +z and growing towards +x, v is the latitude from 0 at the
+/// bottom (-y) to 1 at the top (+y).
+pub fn spherical_uv(dir: Vec3) -> Vec2 {
+    Vec2::new(
+        0.5 + dir.x.atan2(dir.z) / (2. * PI),
+        0.5 + dir.y.clamp(-1., 1.).asin() / PI,
+    )
+}
+
+/// What an image texture shows outside of the [0, 1] range of texture coordinates.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WrapMode {
+    /// The image is tiled.
+    Repeat,
+    /// The colors of the image edges are extended.
+    Clamp,
+}
+
+impl FromStr for WrapMode {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, String> {
+        match name {
+            "repeat" => Ok(WrapMode::Repeat),
+            "clamp" => Ok(WrapMode::Clamp),
+            _ => Err(format!("unknown wrap mode '{}'", name)),
+        }
+    }
+}
+
+/// An image covering the texture coordinates [0, 1] x [0, 1], with bilinear filtering.
+pub struct ImageTexture {
+    image: Framebuffer,
+    wrap: WrapMode,
+    scale: Vec2,
+}
+
+impl ImageTexture {
+    pub fn new(image: Framebuffer) -> Self {
+        assert!(image.width() > 0 && image.height() > 0);
+        ImageTexture {
+            image,
+            wrap: WrapMode::Repeat,
+            scale: Vec2::one(),
+        }
+    }
+
+    /// Loads the image from a file, see `Framebuffer::load`.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Framebuffer::load(path).map(Self::new)
+    }
+
+    /// Loads an image that holds data instead of colors, see `Framebuffer::load_raw`.
+    pub fn load_raw(path: impl AsRef<Path>) -> io::Result<Self> {
+        Framebuffer::load_raw(path).map(Self::new)
+    }
+
+    pub fn set_wrap(mut self, wrap: WrapMode) -> Self {
+        self.wrap = wrap;
+        self
+    }
+
+    /// Number of times the image repeats per unit of the texture coordinates.
+    pub fn set_scale(mut self, scale: Vec2) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    fn texel(&self, x: i64, y: i64) -> Color {
+        let (width, height) = (self.image.width() as i64, self.image.height() as i64);
+        let (x, y) = match self.wrap {
+            WrapMode::Repeat => (x.rem_euclid(width), y.rem_euclid(height)),
+            WrapMode::Clamp => (x.max(0).min(width - 1), y.max(0).min(height - 1)),
+        };
+        self.image.get_pixel(x as u32, y as u32)
+    }
+}
+
+impl Texture for ImageTexture {
+    fn evaluate(&self, uv: Vec2, _point: Vec3, _normal: Vec3) -> Color {
+        // Position in pixels from the top left corner, relative to the texel centers.
+        let x = uv.x * self.scale.x * self.image.width() as f32 - 0.5;
+        let y = (1. - uv.y * self.scale.y) * self.image.height() as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let top = self.texel(x0, y0) * (1. - fx) + self.texel(x0 + 1, y0) * fx;
+        let bottom = self.texel(x0, y0 + 1) * (1. - fx) + self.texel(x0 + 1, y0 + 1) * fx;
+        top * (1. - fy) + bottom * fy
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::vec2;
+
+    use super::*;
+
+    /// 2x2 image with a black, a red, a green and a white pixel.
+    fn create_texture() -> ImageTexture {
+        let mut image = Framebuffer::new(2, 2);
+        image.put_pixel(1, 0, Color::new(1., 0., 0.));
+        image.put_pixel(0, 1, Color::new(0., 1., 0.));
+        image.put_pixel(1, 1, Color::new(1., 1., 1.));
+        ImageTexture::new(image)
+    }
+
+    #[test]
+    fn bilinear_filtering() {
+        let texture = create_texture();
+        let color = |u, v| {
+            texture
+                .evaluate(vec2(u, v), Vec3::zero(), Vec3::unit_z())
+                .to_array()
+        };
+        // Texel centers, v goes up from the bottom row.
+        assert_eq!(color(0.75, 0.75), [1., 0., 0.]);
+        assert_eq!(color(0.25, 0.25), [0., 1., 0.]);
+        // Halfway between all four texels.
+        assert_eq!(color(0.5, 0.5), [0.5, 0.5, 0.25]);
+        // The left edge blends with the right one when repeating.
+        assert_eq!(color(0., 0.75), [0.5, 0., 0.]);
+        assert_eq!(color(1., 0.75), [0.5, 0., 0.]);
+        assert_eq!(color(-0.75, 1.25), color(0.25, 0.25));
+
+        let texture = create_texture().set_wrap(WrapMode::Clamp);
+        let color = |u, v| {
+            texture
+                .evaluate(vec2(u, v), Vec3::zero(), Vec3::unit_z())
+                .to_array()
+        };
+        assert_eq!(color(0., 0.75), [0., 0., 0.]);
+        assert_eq!(color(5., -3.), [1., 1., 1.]);
+
+        let texture = create_texture().set_scale(vec2(2., 2.));
+        assert_eq!(
+            texture
+                .evaluate(vec2(0.375, 0.375), Vec3::zero(), Vec3::unit_z())
+                .to_array(),
+            [1., 0., 0.]
+        );
+    }
+
+    #[test]
+    fn spherical_mapping() {
+        assert_eq!(spherical_uv(Vec3::unit_z()), vec2(0.5, 0.5));
+        assert_eq!(spherical_uv(Vec3::unit_x()), vec2(0.75, 0.5));
+        assert_eq!(spherical_uv(Vec3::unit_y()).y, 1.);
+        assert_eq!(spherical_uv(-Vec3::unit_y()).y, 0.);
+    }
+}
@@ -0,0 +1,186 @@
+//! Output transform from the linear scene radiance to 8-bit sRGB: exposure, a tone mapping
+//! operator that compresses the dynamic range into [0, 1], and the sRGB transfer function.
+
+use std::str::FromStr;
+
+use crate::material::Color;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneMapOperator {
+    /// Values above 1 are clipped.
+    Clamp,
+    /// x / (1 + x) for every channel.
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic reference rendering transform.
+    AcesFilmic,
+    /// John Hable's filmic curve from Uncharted 2.
+    Hable,
+}
+
+impl ToneMapOperator {
+    /// Maps a non-negative linear value into [0, 1], with infinity mapped to 1.
+    pub fn apply(self, x: f32) -> f32 {
+        if x == f32::INFINITY {
+            return 1.;
+        }
+        match self {
+            ToneMapOperator::Clamp => x.min(1.),
+            ToneMapOperator::Reinhard => x / (1. + x),
+            ToneMapOperator::AcesFilmic => {
+                let (a, b, c, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+                ((x * (a * x + b)) / (x * (c * x + d) + e)).clamp(0., 1.)
+            }
+            ToneMapOperator::Hable => {
+                // The curve reaches white at HABLE_WHITE, after the usual exposure bias of 2.
+                (hable_curve(2. * x) / hable_curve(HABLE_WHITE)).clamp(0., 1.)
+            }
+        }
+    }
+}
+
+impl FromStr for ToneMapOperator {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, String> {
+        match name {
+            "clamp" => Ok(ToneMapOperator::Clamp),
+            "reinhard" => Ok(ToneMapOperator::Reinhard),
+            "aces" => Ok(ToneMapOperator::AcesFilmic),
+            "hable" => Ok(ToneMapOperator::Hable),
+            _ => Err(format!("unknown tone mapping operator '{}'", name)),
+        }
+    }
+}
+
+const HABLE_WHITE: f32 = 11.2;
+
+fn hable_curve(x: f32) -> f32 {
+    let (a, b, c, d, e, f) = (0.15, 0.5, 0.1, 0.2, 0.02, 0.3);
+    (x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f) - e / f
+}
+
+/// Settings of the output transform.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ToneMapping {
+    exposure: f32,
+    operator: ToneMapOperator,
+}
+
+impl ToneMapping {
+    pub fn new() -> Self {
+        ToneMapping {
+            exposure: 0.,
+            operator: ToneMapOperator::Clamp,
+        }
+    }
+
+    /// Exposure adjustment in stops: the radiance is multiplied by 2^exposure.
+    pub fn set_exposure(mut self, exposure: f32) -> Self {
+        self.exposure = exposure;
+        self
+    }
+
+    pub fn set_operator(mut self, operator: ToneMapOperator) -> Self {
+        self.operator = operator;
+        self
+    }
+
+    /// Applies the exposure and the tone mapping operator. The result is still linear.
+    pub fn tone_map(&self, color: Color) -> Color {
+        let scale = 2f32.powf(self.exposure);
+        let [r, g, b] = color.to_array();
+        // Negative and NaN values become 0.
+        let map = |c: f32| self.operator.apply((c * scale).max(0.));
+        Color::new(map(r), map(g), map(b))
+    }
+
+    /// Converts linear radiance to an 8-bit sRGB pixel.
+    pub fn encode(&self, color: Color) -> image::Rgb<u8> {
+        let [r, g, b] = self.tone_map(color).to_array();
+        [quantize(r), quantize(g), quantize(b)].into()
+    }
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The sRGB opto-electronic transfer function (IEC 61966-2-1), mapping linear values in [0, 1]
+/// to the non-linear signal.
+pub fn srgb_oetf(linear: f32) -> f32 {
+    if linear <= 0.003_130_8 {
+        12.92 * linear
+    } else {
+        1.055 * linear.powf(1. / 2.4) - 0.055
+    }
+}
+
+/// The sRGB electro-optical transfer function, the inverse of `srgb_oetf`.
+pub fn srgb_eotf(encoded: f32) -> f32 {
+    if encoded <= 0.040_45 {
+        encoded / 12.92
+    } else {
+        ((encoded + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// sRGB encodes a linear value in [0, 1] and rounds it to 8 bits.
+fn quantize(linear: f32) -> u8 {
+    (srgb_oetf(linear.clamp(0., 1.)) * 255. + 0.5) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srgb_reference_values() {
+        let references = [
+            (0., 0.),
+            (0.001, 0.01292),
+            (0.003_130_8, 0.040_449_936),
+            (0.18, 0.461_356_1),
+            (0.5, 0.735_357),
+            (1., 1.),
+        ];
+        for &(linear, encoded) in references.iter() {
+            assert_relative_eq!(srgb_oetf(linear), encoded, max_relative = 1E-5);
+            assert_relative_eq!(srgb_eotf(encoded), linear, max_relative = 1E-4);
+        }
+
+        // 8-bit code values of common linear intensities.
+        let encode = |x: f32| ToneMapping::new().encode(Color::new(x, x, x))[0];
+        assert_eq!(encode(0.), 0);
+        assert_eq!(encode(0.18), 118);
+        assert_eq!(encode(0.215_860_5), 128);
+        assert_eq!(encode(0.5), 188);
+        assert_eq!(encode(1.), 255);
+        assert_eq!(encode(10.), 255);
+        assert_eq!(encode(-1.), 0);
+    }
+
+    #[test]
+    fn tone_map_operators() {
+        use ToneMapOperator::*;
+        for &operator in [Clamp, Reinhard, AcesFilmic, Hable].iter() {
+            assert!(operator.apply(0.).abs() < 1E-6);
+            assert!(operator.apply(0.5) < operator.apply(1.));
+            assert!(operator.apply(1000.) <= 1.);
+            assert_eq!(operator.apply(f32::INFINITY), 1.);
+        }
+        assert_eq!(Reinhard.apply(1.), 0.5);
+        assert_relative_eq!(Hable.apply(HABLE_WHITE / 2.), 1.);
+        assert_eq!("aces".parse(), Ok(AcesFilmic));
+    }
+
+    #[test]
+    fn exposure() {
+        let tone_mapping = ToneMapping::new().set_exposure(-1.);
+        assert_eq!(
+            tone_mapping.tone_map(Color::new(1., 0.5, 4.)),
+            Color::new(0.5, 0.25, 1.)
+        );
+    }
+}
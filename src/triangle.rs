@@ -0,0 +1,186 @@
+use glam::{Vec2, Vec3};
+
+use crate::bvh::Aabb;
+use crate::ray::Ray;
+use crate::shape::*;
+
+/// A single triangle, visible from both sides. The front side, where the normal points, is the one
+/// from which the vertices appear in counter-clockwise order. The texture coordinates are (0, 0),
+/// (1, 0) and (0, 1) at the three vertices.
+pub struct Triangle {
+    vertices: [Vec3; 3],
+    normal: Vec3,
+}
+
+impl Triangle {
+    pub fn new(v0: Vec3, v1: Vec3, v2: Vec3) -> Self {
+        Triangle {
+            vertices: [v0, v1, v2],
+            normal: (v1 - v0).cross(v2 - v0).normalize(),
+        }
+    }
+
+    /// Returns the distance to the intersection and its barycentric coordinates, i.e. the weights
+    /// of the three vertices.
+    pub fn intersect_barycentric(&self, ray: &Ray) -> Option<(f32, Vec3)> {
+        intersect_triangle(ray, &self.vertices)
+    }
+}
+
+impl Shape for Triangle {
+    fn ray_intersect(&self, ray: &Ray) -> Intersection {
+        match self.intersect_barycentric(ray) {
+            Some((dist, barycentric)) => {
+                let [v0, v1, v2] = self.vertices;
+                Intersection::new(dist, self.normal)
+                    .set_uv(Vec2::new(barycentric.y, barycentric.z))
+                    .set_tangents(v1 - v0, v2 - v0)
+            }
+            None => Intersection::new_empty(),
+        }
+    }
+
+    fn bounds(&self) -> Option<Aabb> {
+        Some(Aabb::from_points(self.vertices.iter().copied()))
+    }
+}
+
+/// Watertight ray-triangle intersection (Woop, Benthin, Wald, "Watertight Ray/Triangle
+/// Intersection", JCGT 2013). Rays going through an edge shared by two triangles always hit at
+/// least one of them. Returns the distance to the intersection and the barycentric coordinates.
+pub(crate) fn intersect_triangle(ray: &Ray, vertices: &[Vec3; 3]) -> Option<(f32, Vec3)> {
+    let (origin, dir) = (ray.origin, ray.dir);
+    // Permute the axes so that the largest component of the direction is z.
+    let abs_dir = dir.abs();
+    let kz = if abs_dir.x > abs_dir.y {
+        if abs_dir.x > abs_dir.z {
+            0
+        } else {
+            2
+        }
+    } else if abs_dir.y > abs_dir.z {
+        1
+    } else {
+        2
+    };
+    let mut kx = (kz + 1) % 3;
+    let mut ky = (kx + 1) % 3;
+    if dir[kz] < 0. {
+        std::mem::swap(&mut kx, &mut ky);
+    }
+
+    // Shear transformation that maps the ray direction to the unit z vector.
+    let sz = 1. / dir[kz];
+    let sx = dir[kx] * sz;
+    let sy = dir[ky] * sz;
+
+    let a = vertices[0] - origin;
+    let b = vertices[1] - origin;
+    let c = vertices[2] - origin;
+    let ax = a[kx] - sx * a[kz];
+    let ay = a[ky] - sy * a[kz];
+    let bx = b[kx] - sx * b[kz];
+    let by = b[ky] - sy * b[kz];
+    let cx = c[kx] - sx * c[kz];
+    let cy = c[ky] - sy * c[kz];
+
+    let mut u = cx * by - cy * bx;
+    let mut v = ax * cy - ay * cx;
+    let mut w = bx * ay - by * ax;
+    if u == 0. || v == 0. || w == 0. {
+        // Fall back to double precision on the edges.
+        u = (cx as f64 * by as f64 - cy as f64 * bx as f64) as f32;
+        v = (ax as f64 * cy as f64 - ay as f64 * cx as f64) as f32;
+        w = (bx as f64 * ay as f64 - by as f64 * ax as f64) as f32;
+    }
+
+    if (u < 0. || v < 0. || w < 0.) && (u > 0. || v > 0. || w > 0.) {
+        return None;
+    }
+    let det = u + v + w;
+    if det == 0. {
+        return None;
+    }
+
+    let t = (u * a[kz] + v * b[kz] + w * c[kz]) * sz;
+    let dist = t / det;
+    if !ray.contains(dist) {
+        return None;
+    }
+    Some((dist, Vec3::new(u, v, w) / det))
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::vec3;
+
+    use super::*;
+
+    fn create_triangle() -> Triangle {
+        Triangle::new(vec3(-1., -1., -2.), vec3(1., -1., -2.), vec3(0., 1., -2.))
+    }
+
+    #[test]
+    fn triangle_ray_intersect() {
+        let triangle = create_triangle();
+        let intersection = triangle.ray_intersect(&Ray::new(Vec3::zero(), -Vec3::unit_z()));
+        assert_relative_eq!(intersection.dist, 2.);
+        assert!((intersection.normal - Vec3::unit_z()).length() < 1E-6);
+    }
+
+    #[test]
+    fn triangle_ray_intersect_back_side() {
+        let triangle = create_triangle();
+        let intersection = triangle.ray_intersect(&Ray::new(vec3(0., 0., -4.), Vec3::unit_z()));
+        assert_relative_eq!(intersection.dist, 2.);
+        // The normal is given by the winding order, not by the side that was hit.
+        assert!((intersection.normal - Vec3::unit_z()).length() < 1E-6);
+    }
+
+    #[test]
+    fn triangle_ray_miss() {
+        let triangle = create_triangle();
+        assert!(!triangle
+            .ray_intersect(&Ray::new(vec3(2., 0., 0.), -Vec3::unit_z()))
+            .exists());
+        assert!(!triangle
+            .ray_intersect(&Ray::new(Vec3::zero(), Vec3::unit_z()))
+            .exists());
+        assert!(!triangle
+            .ray_intersect(&Ray::new(Vec3::zero(), Vec3::unit_x()))
+            .exists());
+    }
+
+    #[test]
+    fn triangle_barycentric() {
+        let triangle = create_triangle();
+        let (dist, barycentric) = triangle
+            .intersect_barycentric(&Ray::new(vec3(0., 1., 0.), -Vec3::unit_z()))
+            .unwrap();
+        assert_relative_eq!(dist, 2.);
+        assert!((barycentric - Vec3::unit_z()).length() < 1E-6);
+
+        let (_, barycentric) = triangle
+            .intersect_barycentric(&Ray::new(vec3(0., -1., 0.), -Vec3::unit_z()))
+            .unwrap();
+        assert!((barycentric - vec3(0.5, 0.5, 0.)).length() < 1E-6);
+    }
+
+    #[test]
+    fn shared_edge_is_watertight() {
+        let t1 = Triangle::new(vec3(0., 0., 0.), vec3(1., 0., 0.), vec3(0., 1., 0.));
+        let t2 = Triangle::new(vec3(1., 0., 0.), vec3(1., 1., 0.), vec3(0., 1., 0.));
+        for i in 1..100 {
+            let s = i as f32 / 100.;
+            let origin = vec3(s, 1. - s, 1.);
+            let dir = vec3(0.01, 0.003, -1.).normalize();
+            assert!(
+                t1.ray_intersect(&Ray::new(origin - vec3(0.01, 0.003, 0.), dir))
+                    .exists()
+                    || t2
+                        .ray_intersect(&Ray::new(origin - vec3(0.01, 0.003, 0.), dir))
+                        .exists()
+            );
+        }
+    }
+}